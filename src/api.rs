@@ -71,13 +71,13 @@ pub struct ListProjectsResponse {
     pub projects: Vec<Project>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FeatureConfig {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub enum ContainerState {
     Invalid = 0,
     Starting = 1,
@@ -86,6 +86,19 @@ pub enum ContainerState {
     Failed = 4,
 }
 
+impl Display for ContainerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // The container hasn't been created yet. Printed as-is, scripts parse deploy status output.
+            ContainerState::Invalid => write!(f, "Invalid"),
+            ContainerState::Starting => write!(f, "Starting"),
+            ContainerState::Running => write!(f, "Running"),
+            ContainerState::Paused => write!(f, "Paused"),
+            ContainerState::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeployStatusResponse {
     pub status: ContainerState,
@@ -137,6 +150,9 @@ pub struct ChatSession {
     pub id: u64,
     #[serde(rename = "name")]
     pub _name: Option<String>,
+    /// RFC 3339 timestamp of the last activity in the session
+    #[serde(default, rename = "updatedAt")]
+    pub updated_at: Option<String>,
 }
 
 impl ChatSession {
@@ -169,6 +185,30 @@ pub struct LLMConfigurationRequest {
     pub key: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LLMConfiguration {
+    #[serde(default)]
+    pub has_key: bool,
+    pub provider: Option<String>,
+    /// The last few characters of the configured key
+    pub key_hint: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LLMModel {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetModelRequest {
+    pub model: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreditUsage {
@@ -177,6 +217,16 @@ pub struct CreditUsage {
     pub purchased_remaining: i32,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditUsageEntry {
+    /// YYYY-MM-DD
+    pub date: String,
+    pub project: Option<String>,
+    pub feature: Option<String>,
+    pub credits: u64,
+}
+
 pub mod ws {
     use serde::{ser::SerializeStruct, Deserialize, Serialize};
 
@@ -189,7 +239,7 @@ pub mod ws {
         ResponseState,
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Clone, Debug, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct AuthMessage {
         pub token: String,
@@ -287,6 +337,9 @@ pub mod ws {
         Search {
             // (filename, line number, line content)
             results: Vec<(String, usize, String)>,
+            /// Files which were too large to search, so results may be incomplete
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            skipped_files: Vec<String>,
         },
     }
 
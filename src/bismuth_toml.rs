@@ -1,13 +1,95 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use globset::Glob;
-use serde::Deserialize;
-use std::{fs, path::Path};
+use serde::{Deserialize, Deserializer};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use url::Url;
 
 /// The root configuration for Bismuth.
 #[derive(Default, Deserialize, Debug)]
 #[serde(default)]
 pub struct BismuthTOML {
     pub chat: ChatConfig,
+    pub backend: BackendConfig,
+    pub git: GitConfig,
+}
+
+/// Git integration options.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct GitConfig {
+    /// Name of the git remote pointing at Bismuth. Default "bismuth".
+    /// Useful if the repository already has an unrelated remote with that name.
+    pub remote_name: String,
+
+    /// How pushes and fetches to the Bismuth remote are authenticated. Default "token".
+    pub remote_auth: RemoteAuth,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        GitConfig {
+            remote_name: "bismuth".to_string(),
+            remote_auth: RemoteAuth::default(),
+        }
+    }
+}
+
+/// Authentication for the Bismuth remote.
+/// `ssh` takes precedence for GitHub-linked projects, and falls back to `token` for other projects.
+/// `credential-helper` never falls back, and leaves authentication entirely to git.
+#[derive(Default, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemoteAuth {
+    /// Use the project's clone token. The token is passed to git when pushing or fetching, and is never stored in the remote URL.
+    #[default]
+    Token,
+    /// Use the linked GitHub repository over SSH.
+    Ssh,
+    /// Use the HTTPS remote with whatever credential helper git is configured with.
+    CredentialHelper,
+}
+
+impl GitConfig {
+    fn validate(&self) -> Result<()> {
+        if self.remote_name.is_empty() {
+            return Err(anyhow!("git.remote_name cannot be empty"));
+        }
+        if self.remote_name == "origin" {
+            return Err(anyhow!(
+                "git.remote_name cannot be origin, Bismuth needs its own remote"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Per-repository backend overrides, for projects hosted on a self-hosted backend.
+/// Unset values fall back to the global configuration.
+#[derive(Default, Deserialize, Debug)]
+#[serde(default)]
+pub struct BackendConfig {
+    /// The API URL to use when operating inside this repository.
+    #[serde(deserialize_with = "deserialize_url")]
+    pub api_url: Option<Url>,
+
+    /// The chat websocket URL to use when operating inside this repository.
+    #[serde(deserialize_with = "deserialize_url")]
+    pub websocket_url: Option<Url>,
+
+    /// PEM file with an additional CA certificate to trust, relative to the repository root.
+    pub ca_cert: Option<PathBuf>,
+}
+
+fn deserialize_url<'de, D>(deserializer: D) -> Result<Option<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| Url::parse(&s).map_err(serde::de::Error::custom))
+        .transpose()
 }
 
 /// Configuration options for interactive chat.
@@ -17,6 +99,10 @@ pub struct ChatConfig {
     /// Timeout in seconds for commands run by the agent. Deafult 60s.
     pub command_timeout: u64,
 
+    /// Maximum number of bytes of output kept from commands run by the agent.
+    /// Only the end of the output is kept. Default 64KiB.
+    pub command_output_limit: usize,
+
     /// Additional files to be sent to the agent that are normally excluded by .gitignore.
     /// Defaults to .env, .env.local, .env.development.
     pub additional_files: Vec<String>,
@@ -25,12 +111,31 @@ pub struct ChatConfig {
     /// Mainly used to avoid accidentally sending large directories like node_modules in the case of a missing or misconfigured .gitignore.
     /// Defaults to **/.*/**, venv/**, **/__pycache__/**, *.pyc, **/node_modules/**, **/target/**, **/dist/**, **/build/**
     pub block_globs: Vec<Glob>,
+
+    /// Shell used to run commands for the agent. Defaults to `sh` (`cmd` on Windows).
+    pub shell: Option<String>,
+
+    /// Arguments passed to the shell before the command. Defaults to `-c` (`/C` on Windows).
+    pub shell_args: Option<Vec<String>>,
+
+    /// Seconds between keepalive pings on the chat connection. Default 30s, 0 disables.
+    /// The connection is re-established if nothing is received for twice this long.
+    pub ping_interval: u64,
+
+    /// Lines per second the agent's file view scrolls at. Default 30, 0 jumps straight to where the agent is looking.
+    /// On tall terminals it scrolls faster if needed to keep up with the agent paging through a file.
+    pub aci_scroll_speed: u32,
+
+    /// Show the agent's file view while it works. Default true.
+    /// When false the agent works in the background and only the resulting diff is shown.
+    pub show_aci: bool,
 }
 
 impl Default for ChatConfig {
     fn default() -> Self {
         ChatConfig {
             command_timeout: 60,
+            command_output_limit: 64 * 1024,
             additional_files: vec![
                 ".env".to_string(),
                 ".env.local".to_string(),
@@ -46,10 +151,63 @@ impl Default for ChatConfig {
                 Glob::new("**/dist/**").unwrap(),
                 Glob::new("**/build/**").unwrap(),
             ],
+            shell: None,
+            shell_args: None,
+            ping_interval: 30,
+            aci_scroll_speed: 30,
+            show_aci: true,
         }
     }
 }
 
+impl ChatConfig {
+    /// The shell and arguments to run agent commands with.
+    pub fn shell(&self) -> (String, Vec<String>) {
+        let (default_shell, default_args) = if cfg!(target_os = "windows") {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        (
+            self.shell.clone().unwrap_or(default_shell.to_string()),
+            self.shell_args
+                .clone()
+                .unwrap_or(vec![default_args.to_string()]),
+        )
+    }
+
+    /// Make sure the configured shell can actually be run.
+    pub fn check_shell(&self) -> Result<()> {
+        let (shell, _) = self.shell();
+        if find_executable(&shell).is_none() {
+            return Err(anyhow!(
+                "chat.shell: {} was not found. Check the path or your PATH",
+                shell
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let extensions: &[&str] = if cfg!(target_os = "windows") {
+        &["", ".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| {
+            extensions
+                .iter()
+                .map(move |ext| dir.join(format!("{}{}", name, ext)))
+        })
+        .find(|candidate| candidate.is_file())
+}
+
 pub fn parse_config(repo_root: &Path) -> Result<BismuthTOML> {
     let config_path = repo_root.join("bismuth.toml");
     if fs::metadata(&config_path).is_err() {
@@ -57,9 +215,51 @@ pub fn parse_config(repo_root: &Path) -> Result<BismuthTOML> {
     }
     let config_str = fs::read_to_string(config_path)?;
     let config: BismuthTOML = toml::from_str(&config_str)?;
+    config.git.validate()?;
     Ok(config)
 }
 
+/// The git configuration for the repository containing `path`.
+pub fn git_config(path: &Path) -> Result<GitConfig> {
+    let repo = git2::Repository::discover(path)?;
+    match repo.workdir() {
+        Some(root) => Ok(parse_config(root)?.git),
+        None => Ok(GitConfig::default()),
+    }
+}
+
+/// The name of the Bismuth git remote for the repository containing `path`.
+pub fn remote_name(path: &Path) -> Result<String> {
+    Ok(git_config(path)?.remote_name)
+}
+
+/// Check the config for problems that don't prevent it from loading.
+/// Returns a list of human-readable warnings. Hard errors (invalid TOML, invalid globs, invalid URLs) are returned as `Err`.
+pub fn lint_config(repo_root: &Path) -> Result<Vec<String>> {
+    let config = parse_config(repo_root)?;
+    let mut warnings = vec![];
+    if fs::metadata(repo_root.join("bismuth.toml")).is_err() {
+        warnings.push("No bismuth.toml found, using defaults".to_string());
+    }
+    for file in &config.chat.additional_files {
+        if !repo_root.join(file).is_file() {
+            warnings.push(format!("chat.additional_files: {} does not exist", file));
+        }
+    }
+    if let Err(e) = config.chat.check_shell() {
+        warnings.push(e.to_string());
+    }
+    for glob in &config.chat.block_globs {
+        if glob.glob().contains('\\') {
+            warnings.push(format!(
+                "chat.block_globs: {} contains a backslash, which is treated as an escape. Use / as the path separator",
+                glob
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -88,4 +288,144 @@ mod test {
         assert!(globset.is_match("dist/thing.whl"));
         assert!(globset.is_match("build/out.o"));
     }
+
+    #[test]
+    fn test_lint_config() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        fs::write(
+            tmpdir.path().join("bismuth.toml"),
+            r#"
+            [chat]
+            additional_files = ["exists", "missing"]
+            "#,
+        )
+        .unwrap();
+        fs::write(tmpdir.path().join("exists"), "").unwrap();
+        let warnings = lint_config(tmpdir.path()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("missing"));
+
+        fs::write(
+            tmpdir.path().join("bismuth.toml"),
+            r#"
+            [chat]
+            block_globs = ["a/[b"]
+            "#,
+        )
+        .unwrap();
+        assert!(lint_config(tmpdir.path()).is_err());
+    }
+
+    #[test]
+    fn test_shell() {
+        let config = ChatConfig::default();
+        assert!(config.check_shell().is_ok());
+
+        let config: BismuthTOML = toml::from_str(
+            r#"
+            [chat]
+            shell = "definitely-not-a-real-shell"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.chat.shell().0,
+            "definitely-not-a-real-shell".to_string()
+        );
+        assert!(config.chat.check_shell().is_err());
+    }
+
+    #[test]
+    fn test_aci_options() {
+        let config = ChatConfig::default();
+        assert!(config.show_aci);
+        assert_eq!(config.aci_scroll_speed, 30);
+
+        let config: BismuthTOML = toml::from_str(
+            r#"
+            [chat]
+            show_aci = false
+            aci_scroll_speed = 0
+            "#,
+        )
+        .unwrap();
+        assert!(!config.chat.show_aci);
+        assert_eq!(config.chat.aci_scroll_speed, 0);
+        // Unrelated options keep their defaults
+        assert_eq!(config.chat.command_timeout, 60);
+    }
+
+    #[test]
+    fn test_remote_name() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        git2::Repository::init(tmpdir.path()).unwrap();
+        assert_eq!(remote_name(tmpdir.path()).unwrap(), "bismuth");
+
+        fs::write(
+            tmpdir.path().join("bismuth.toml"),
+            r#"
+            [git]
+            remote_name = "bismuth-cloud"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(remote_name(tmpdir.path()).unwrap(), "bismuth-cloud");
+
+        fs::write(
+            tmpdir.path().join("bismuth.toml"),
+            r#"
+            [git]
+            remote_name = "origin"
+            "#,
+        )
+        .unwrap();
+        assert!(remote_name(tmpdir.path()).is_err());
+
+        let config: BismuthTOML = toml::from_str(
+            r#"
+            [git]
+            remote_auth = "credential-helper"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.git.remote_name, "bismuth");
+        assert_eq!(config.git.remote_auth, RemoteAuth::CredentialHelper);
+    }
+
+    #[test]
+    fn test_backend_overrides() {
+        let config: BismuthTOML = toml::from_str(
+            r#"
+            [backend]
+            api_url = "https://bismuth.example.com"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.backend.api_url.unwrap().as_str(),
+            "https://bismuth.example.com/"
+        );
+        assert!(config.backend.websocket_url.is_none());
+        assert!(config.backend.ca_cert.is_none());
+
+        let config: BismuthTOML = toml::from_str(
+            r#"
+            [backend]
+            ca_cert = "certs/ca.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.backend.ca_cert.unwrap(),
+            PathBuf::from("certs/ca.pem")
+        );
+
+        assert!(toml::from_str::<BismuthTOML>(
+            r#"
+            [backend]
+            api_url = "not a url"
+            "#,
+        )
+        .is_err());
+    }
 }
@@ -1,9 +1,13 @@
 use std::{
     cell::OnceCell,
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
+    io::IsTerminal as _,
     path::{Path, PathBuf},
     process::Command,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
@@ -12,13 +16,14 @@ use copypasta::ClipboardProvider;
 use derivative::Derivative;
 use futures::{stream::SplitStream, SinkExt, StreamExt, TryStreamExt};
 use log::{debug, trace};
+use once_cell::sync::Lazy;
 use ratatui::{
     crossterm::{
         cursor::SetCursorStyle,
         event::{self, Event, KeyCode, MouseButton},
     },
     layout::{Constraint, Layout, Margin, Rect},
-    style::Style,
+    style::{Color, Style},
     text::{Line, Span},
     widgets::{
         block::Title, Block, Borders, Clear, Padding, Paragraph, Scrollbar, ScrollbarState,
@@ -31,7 +36,8 @@ use syntect::util::LinesWithEndings;
 use tokio::{io::AsyncBufReadExt as _, net::TcpStream, sync::mpsc};
 use tokio_stream::wrappers::LinesStream;
 use tokio_tungstenite::{
-    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+    connect_async_tls_with_config, tungstenite::protocol::Message, Connector, MaybeTlsStream,
+    WebSocketStream,
 };
 use url::Url;
 
@@ -41,7 +47,9 @@ use crate::{
         self,
         ws::{ChatModifiedFile, RunCommandResponse},
     },
-    APIClient, ResponseErrorExt as _,
+    can_launch_browser,
+    cli::ThemeChoice,
+    APIClient, CliError, ResponseErrorExt as _,
 };
 
 fn websocket_url(api_url: &Url) -> &'static str {
@@ -157,15 +165,50 @@ fn list_all_files(repo_path: &Path) -> Result<Vec<String>> {
     Ok(files.into_iter().collect())
 }
 
+/// The name of the checked out branch.
+pub fn current_branch(repo: &git2::Repository) -> Result<String> {
+    if repo.head_detached().unwrap_or(false) {
+        return Err(anyhow!(
+            "HEAD is detached. Checkout a branch (`git checkout <branch>`) and try again"
+        ));
+    }
+    let head = repo.head().map_err(|e| {
+        anyhow!(
+            "Unable to read HEAD. Does the branch have any commits? ({})",
+            e
+        )
+    })?;
+    head.shorthand()
+        .map(String::from)
+        .ok_or_else(|| anyhow!("Current branch name is not valid UTF-8"))
+}
+
+/// The commit `branch` points to on the Bismuth remote.
+fn upstream_commit(
+    repo: &git2::Repository,
+    repo_path: &Path,
+    project: &api::Project,
+    branch: &str,
+) -> Result<git2::Oid> {
+    let remote = bismuth_toml::remote_name(repo_path)?;
+    repo.find_branch(&format!("{}/{}", remote, branch), git2::BranchType::Remote)
+        .ok()
+        .and_then(|b| b.get().target())
+        .ok_or_else(|| {
+            anyhow!(
+                "Branch {} has not been pushed to Bismuth yet. Push it with `bismuth project upload {} --branch {}` first",
+                branch,
+                project.name,
+                branch
+            )
+        })
+}
+
 /// List files that have changed in the working directory compared to the upstream branch.
-fn list_changed_files(repo_path: &Path) -> Result<Vec<PathBuf>> {
+fn list_changed_files(repo_path: &Path, project: &api::Project) -> Result<Vec<PathBuf>> {
     let repo = git2::Repository::open(repo_path)?;
-    let branch = repo.head()?.shorthand().unwrap().to_string();
-    let upstream_commit = repo
-        .find_branch(&format!("bismuth/{}", branch), git2::BranchType::Remote)?
-        .get()
-        .target()
-        .unwrap();
+    let branch = current_branch(&repo)?;
+    let upstream_commit = upstream_commit(&repo, repo_path, project, &branch)?;
     let upstream_tree = repo.find_commit(upstream_commit)?.tree()?;
     let head_tree = repo.find_commit(repo.head()?.target().unwrap())?.tree()?;
     // Diff tree to HEAD
@@ -271,17 +314,153 @@ fn command_modified_files(repo_path: &Path) -> Result<Vec<ChatModifiedFile>> {
                 .filter(|p| repo_path.join(p).is_file())
                 .map(PathBuf::from),
         )
-        .map(|path| ChatModifiedFile {
-            name: path.file_name().unwrap().to_string_lossy().to_string(),
-            project_path: path.to_string_lossy().to_string(),
-            content: std::fs::read_to_string(repo_path.join(&path)).unwrap_or("".to_string()),
-            deleted: Some(!repo_path.join(&path).exists()),
-        })
+        .filter_map(|path| modified_file(repo_path, &path))
         .collect())
 }
 
+/// Files larger than this aren't searched, to avoid stalling on large data files.
+const SEARCH_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// (filename, line number, line content)
+type SearchMatch = (String, usize, String);
+
+/// Search all files the agent can see for lines containing `query`.
+/// Returns the matches, and the files which were too large to search.
+fn search_files(repo_path: &Path, query: &str) -> Result<(Vec<SearchMatch>, Vec<String>)> {
+    use std::io::BufRead as _;
+
+    let mut results = vec![];
+    let mut skipped = vec![];
+    for file in list_all_files(repo_path)? {
+        let path = repo_path.join(&file);
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.len() > SEARCH_MAX_FILE_SIZE {
+            skipped.push(file);
+            continue;
+        }
+        let Ok(f) = std::fs::File::open(&path) else {
+            continue;
+        };
+        let mut file_results = vec![];
+        for (line, text) in std::io::BufReader::new(f).lines().enumerate() {
+            // Not UTF-8 or has NUL bytes, so binary
+            let Ok(text) = text else {
+                file_results.clear();
+                break;
+            };
+            if text.contains('\0') {
+                file_results.clear();
+                break;
+            }
+            if text.contains(query) {
+                file_results.push((file.clone(), line + 1, text));
+            }
+        }
+        results.extend(file_results);
+    }
+    Ok((results, skipped))
+}
+
+/// Read a file as text, returning None if it doesn't exist or is binary.
+fn read_text_file(path: &Path) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    // NUL bytes are valid UTF-8 but a good sign the file isn't text
+    if bytes.contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Build the payload for a changed file to send to the agent.
+/// Binary files can't be represented, so they're skipped rather than sent as empty.
+fn modified_file(repo_path: &Path, path: &Path) -> Option<ChatModifiedFile> {
+    let full_path = repo_path.join(path);
+    let deleted = !full_path.exists();
+    let content = if deleted {
+        "".to_string()
+    } else {
+        match read_text_file(&full_path) {
+            Some(content) => content,
+            None => {
+                debug!("Not sending binary file {}", path.display());
+                return None;
+            }
+        }
+    };
+    Some(ChatModifiedFile {
+        name: path.file_name()?.to_string_lossy().to_string(),
+        project_path: path.to_string_lossy().to_string(),
+        content,
+        deleted: Some(deleted),
+    })
+}
+
 const BISMUTH_AUTHOR: &str = "bismuthdev[bot]";
 
+/// Sent back to the agent in place of running a command in dry run mode.
+const DRY_RUN_COMMAND_OUTPUT: &str =
+    "This is a dry run, so commands are not run. The user's working tree must not be modified. Continue without running commands.";
+
+/// Options for a chat session set from the command line
+#[derive(Clone, Debug, Default)]
+pub struct ChatOptions {
+    /// Only preview the agent's changes, never write them to the working tree
+    pub dry_run: bool,
+    /// Send just this message without the interactive UI
+    pub message: Option<String>,
+    /// Commit the changes from a one-shot `message` rather than discarding them
+    pub auto_apply: bool,
+    /// Never show the agent's file view, overriding bismuth.toml
+    pub no_aci: bool,
+    /// Color palette for the interactive UI
+    pub theme: ThemeChoice,
+}
+
+/// Output of a command run by the agent, keeping only the last `limit` bytes.
+struct CommandOutput {
+    lines: VecDeque<String>,
+    len: usize,
+    limit: usize,
+    truncated: bool,
+}
+
+impl CommandOutput {
+    fn new(limit: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            len: 0,
+            limit,
+            truncated: false,
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.len += line.len() + 1;
+        self.lines.push_back(line.to_string());
+        while self.len > self.limit {
+            let Some(dropped) = self.lines.pop_front() else {
+                break;
+            };
+            self.len -= dropped.len() + 1;
+            self.truncated = true;
+        }
+    }
+}
+
+impl std::fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.truncated {
+            writeln!(f, "(Output truncated, showing the last {} bytes)", self.len)?;
+        }
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
 fn process_chat_message(
     repo_path: &Path,
     modified_files: &[ChatModifiedFile],
@@ -336,7 +515,7 @@ fn process_chat_message(
                 return Err(anyhow::anyhow!("Invalid file path"));
             }
             std::fs::create_dir_all(full_path.parent().unwrap())?;
-            std::fs::write(full_path, &mf.content)?;
+            write_preserving_permissions(&full_path, &mf.content)?;
         }
     }
 
@@ -363,6 +542,63 @@ fn process_chat_message(
     Ok(Some(diff))
 }
 
+/// Write a file, keeping the mode of the file being replaced.
+/// New files with a shebang are made executable.
+fn write_preserving_permissions(path: &Path, content: &str) -> Result<()> {
+    let original_permissions = std::fs::metadata(path).ok().map(|m| m.permissions());
+    std::fs::write(path, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        match original_permissions {
+            Some(permissions) => std::fs::set_permissions(path, permissions)?,
+            None if content.starts_with("#!") => {
+                let mut permissions = std::fs::metadata(path)?.permissions();
+                // Executable by whoever can read it
+                permissions.set_mode(permissions.mode() | ((permissions.mode() & 0o444) >> 2));
+                std::fs::set_permissions(path, permissions)?;
+            }
+            None => {}
+        }
+    }
+    #[cfg(not(unix))]
+    if let Some(permissions) = original_permissions {
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// Build the diff `process_chat_message` would apply, without touching the working tree.
+fn preview_chat_message(
+    repo_path: &Path,
+    modified_files: &[ChatModifiedFile],
+) -> Result<Option<String>> {
+    if modified_files.is_empty() {
+        return Ok(None);
+    }
+
+    let repo_path = std::fs::canonicalize(repo_path)?;
+    let mut diff = String::new();
+    for mf in modified_files {
+        let file_name = Path::new(mf.project_path.trim_start_matches('/'));
+        let full_path = repo_path.join(file_name);
+        if !full_path.starts_with(&repo_path) {
+            return Err(anyhow!("Invalid file path"));
+        }
+        let old = std::fs::read(&full_path).unwrap_or_default();
+        let new = if mf.deleted.unwrap_or_default() {
+            &[][..]
+        } else {
+            mf.content.as_bytes()
+        };
+        let mut patch =
+            git2::Patch::from_buffers(&old, Some(file_name), new, Some(file_name), None)?;
+        diff += patch.to_buf()?.as_str().unwrap_or_default();
+    }
+
+    Ok(Some(diff))
+}
+
 fn commit(repo_path: &Path, message: Option<&str>) -> Result<()> {
     Command::new("git")
         .arg("-C")
@@ -432,6 +668,48 @@ fn commit(repo_path: &Path, message: Option<&str>) -> Result<()> {
     Ok(())
 }
 
+/// Whether HEAD is a temp commit holding changes which haven't been committed or reverted yet
+fn has_temp_commit(repo_path: &Path) -> Result<bool> {
+    let repo = git2::Repository::open(repo_path)?;
+    let head = repo.head()?.peel_to_commit()?;
+    Ok(head.message().unwrap_or("") == "Bismuth Temp Commit"
+        && head.author().name() == Some(BISMUTH_AUTHOR))
+}
+
+/// Ask what to do with pending changes before exiting
+fn resolve_temp_commit(repo_path: &Path) -> Result<()> {
+    if !has_temp_commit(repo_path)? {
+        return Ok(());
+    }
+    println!("Bismuth's last changes have not been committed or reverted.");
+    if !std::io::stdin().is_terminal() {
+        println!("Keeping them for now. Use /diff in the next chat to review them.");
+        return Ok(());
+    }
+    loop {
+        print!("[c]ommit, [r]evert, or [k]eep them for later? [c/r/K] ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        match answer.trim().to_lowercase().as_str() {
+            "c" | "commit" => {
+                commit(repo_path, None)?;
+                println!("Changes committed.");
+                return Ok(());
+            }
+            "r" | "revert" => {
+                revert(repo_path)?;
+                println!("Changes reverted.");
+                return Ok(());
+            }
+            "" | "k" | "keep" => {
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
 fn revert(repo_path: &Path) -> Result<()> {
     let repo = git2::Repository::open(repo_path)?;
 
@@ -536,6 +814,121 @@ impl<'a> OwnedLine {
                 .collect::<Vec<_>>(),
         )
     }
+
+    /// Render only `width` characters, starting from the `skip`th.
+    fn as_clipped_line(&'a self, skip: usize, width: usize) -> Line<'a> {
+        let mut skip = skip;
+        let mut remaining = width;
+        Line::from(
+            self.spans
+                .iter()
+                .filter_map(|(s, style)| {
+                    let len = s.chars().count();
+                    let start = skip.min(len);
+                    let end = (start + remaining).min(len);
+                    skip -= start;
+                    remaining -= end - start;
+                    let byte_idx = |idx| s.char_indices().nth(idx).map_or(s.len(), |(i, _)| i);
+                    (start < end).then(|| Span::styled(&s[byte_idx(start)..byte_idx(end)], *style))
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn width(&self) -> usize {
+        self.spans.iter().map(|(s, _)| s.chars().count()).sum()
+    }
+}
+
+/// Whether the diff review and agent file views show a line number gutter, toggled with `l`
+static LINE_NUMBERS: AtomicBool = AtomicBool::new(true);
+
+/// Checking this is relatively expensive on some platforms, so only do it once
+static CLIPBOARD_AVAILABLE: Lazy<bool> = Lazy::new(|| copypasta::ClipboardContext::new().is_ok());
+
+/// Loading these is expensive, so only do it once
+static SYNTAX_SET: Lazy<syntect::parsing::SyntaxSet> = Lazy::new(two_face::syntax::extra_newlines);
+static THEME_SET: Lazy<two_face::theme::EmbeddedLazyThemeSet> = Lazy::new(two_face::theme::extra);
+static THEME: once_cell::sync::OnceCell<Theme> = once_cell::sync::OnceCell::new();
+
+/// Colors used throughout the chat UI, picked with `--theme`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Theme {
+    /// Bismuth's name and logo
+    brand: Color,
+    /// Usernames, the model, and the selected code block
+    accent: Color,
+    /// Hints like "(press Esc to close)" and folded code blocks
+    notice: Color,
+    error: Color,
+    /// Added lines, finished steps, and the agent status
+    success: Color,
+    /// The step currently in progress
+    active: Color,
+    /// Secondary text like the key legend and scrollbars
+    muted: Color,
+    /// Background of the selected session or file tab
+    selected_bg: Color,
+    /// Background of the unselected file tabs
+    panel_bg: Color,
+    /// Syntax highlighting for code blocks
+    syntax: two_face::theme::EmbeddedThemeName,
+}
+
+impl Theme {
+    const DARK: Theme = Theme {
+        brand: Color::Magenta,
+        accent: Color::Cyan,
+        notice: Color::Yellow,
+        error: Color::Red,
+        success: Color::Green,
+        active: Color::LightGreen,
+        muted: Color::DarkGray,
+        selected_bg: Color::Blue,
+        panel_bg: Color::DarkGray,
+        syntax: two_face::theme::EmbeddedThemeName::Base16OceanDark,
+    };
+
+    const LIGHT: Theme = Theme {
+        brand: Color::Magenta,
+        accent: Color::Blue,
+        notice: Color::Rgb(0xaf, 0x5f, 0x00),
+        error: Color::Red,
+        success: Color::Rgb(0x00, 0x87, 0x00),
+        active: Color::Green,
+        muted: Color::Gray,
+        selected_bg: Color::LightBlue,
+        panel_bg: Color::Gray,
+        syntax: two_face::theme::EmbeddedThemeName::Base16OceanLight,
+    };
+
+    fn new(choice: ThemeChoice) -> Self {
+        match choice {
+            ThemeChoice::Dark => Self::DARK,
+            ThemeChoice::Light => Self::LIGHT,
+            // Most terminals are dark, so that's the fallback when we can't tell
+            ThemeChoice::Auto => match std::env::var("COLORFGBG")
+                .ok()
+                .and_then(|v| colorfgbg_is_light(&v))
+            {
+                Some(true) => Self::LIGHT,
+                _ => Self::DARK,
+            },
+        }
+    }
+}
+
+/// The active theme. Dark until the chat sets one.
+fn theme() -> &'static Theme {
+    THEME.get().unwrap_or(&Theme::DARK)
+}
+
+/// Whether a `COLORFGBG` value ("fg;bg", set by e.g. rxvt and Konsole) has a light background.
+/// Returns None if it can't be parsed.
+fn colorfgbg_is_light(value: &str) -> Option<bool> {
+    let bg: u8 = value.rsplit(';').next()?.trim().parse().ok()?;
+    // 7 is light gray and 9-15 are the bright colors, except 8 (dark gray)
+    Some(bg == 7 || (9..=15).contains(&bg))
 }
 
 #[derive(Clone, Debug, Derivative)]
@@ -562,8 +955,8 @@ impl CodeBlock {
             language: if let Some(language) = language {
                 language.to_string()
             } else if let Some(filename) = filename {
-                let ps = two_face::syntax::extra_newlines();
-                ps.syntaxes()
+                SYNTAX_SET
+                    .syntaxes()
                     .iter()
                     .find(|s| s.file_extensions.iter().any(|e| filename.ends_with(e)))
                     .map(|s| s.name.clone())
@@ -579,17 +972,13 @@ impl CodeBlock {
     }
     fn lines(&self) -> &Vec<OwnedLine> {
         self.lines.get_or_init(|| {
-            let ps = two_face::syntax::extra_newlines();
-            let ts = two_face::theme::extra();
+            let ps = &*SYNTAX_SET;
             let syntax = ps
                 .syntaxes()
                 .iter()
                 .find(|s| s.name.to_lowercase() == self.language.to_lowercase())
                 .unwrap_or(ps.find_syntax_plain_text());
-            let mut h = HighlightLines::new(
-                syntax,
-                ts.get(two_face::theme::EmbeddedThemeName::Base16OceanDark),
-            );
+            let mut h = HighlightLines::new(syntax, THEME_SET.get(theme().syntax));
             if let Some(diff_highlight_lines) = &self.diff_highlight_lines {
                 LinesWithEndings::from(&self.raw_code)
                     .enumerate()
@@ -598,7 +987,7 @@ impl CodeBlock {
                             // Necessary so empty lines don't get double rendered
                             (if line != "\n" {
                                 vec![if diff_highlight_lines.contains(&line_no) {
-                                    ("█", Style::default().fg(ratatui::style::Color::Green))
+                                    ("█", Style::default().fg(theme().success))
                                 } else {
                                     (" ", Style::default())
                                 }]
@@ -606,7 +995,7 @@ impl CodeBlock {
                                 vec![]
                             })
                             .into_iter()
-                            .chain(h.highlight_line(line, &ps).unwrap().into_iter().map(
+                            .chain(h.highlight_line(line, ps).unwrap().into_iter().map(
                                 |(syntect_style, content)| {
                                     (
                                         content,
@@ -632,7 +1021,7 @@ impl CodeBlock {
                 LinesWithEndings::from(&self.raw_code)
                     .map(|line| {
                         OwnedLine::from(
-                            h.highlight_line(line, &ps)
+                            h.highlight_line(line, ps)
                                 .unwrap()
                                 .into_iter()
                                 .map(|(syntect_style, content)| {
@@ -673,13 +1062,26 @@ impl MessageBlock {
     }
 }
 
+/// Everything which affects how many lines a block wraps to.
+/// Cached line counts are only valid for the key they were computed with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct LineCacheKey {
+    width: u16,
+    wrap_code: bool,
+}
+
 #[derive(Clone, Debug)]
 struct ChatMessage {
     user: ChatMessageUser,
     raw: String,
     finalized: bool,
     blocks: Vec<MessageBlock>,
-    block_line_cache: (usize, Vec<usize>),
+    block_line_cache: (LineCacheKey, Vec<usize>),
+
+    /// Length of the prefix of `raw` which can no longer change when more tokens are appended,
+    /// and the number of `blocks` it was parsed into. Only the remainder is reparsed on append.
+    stable_raw_len: usize,
+    stable_block_count: usize,
 }
 
 impl ChatMessage {
@@ -707,36 +1109,54 @@ impl ChatMessage {
             finalized: false,
             blocks,
             // Cache the result of line wrapping for each block. This is surprisingly expensive
-            block_line_cache: (0, vec![]), // width, list of rendered line counts for each block
+            block_line_cache: (LineCacheKey::default(), vec![]), // render key, list of rendered line counts for each block
+            stable_raw_len: 0,
+            stable_block_count: 0,
         }
     }
 
     fn format_user(user: &ChatMessageUser) -> OwnedLine {
         let mut spans = Vec::with_capacity(3);
         // Copy
-        if copypasta::ClipboardContext::new().is_ok() {
+        if *CLIPBOARD_AVAILABLE {
             spans.push(("⎘ ", ratatui::style::Style::default()));
         }
         spans.push(match user {
             ChatMessageUser::AI => (
                 "Bismuth",
-                ratatui::style::Style::default().fg(ratatui::style::Color::Magenta),
-            ),
-            ChatMessageUser::User(ref user) => (
-                user,
-                ratatui::style::Style::default().fg(ratatui::style::Color::Cyan),
+                ratatui::style::Style::default().fg(theme().brand),
             ),
+            ChatMessageUser::User(ref user) => {
+                (user, ratatui::style::Style::default().fg(theme().accent))
+            }
         });
         spans.push((": ", ratatui::style::Style::default()));
         OwnedLine::from(spans)
     }
 
     fn parse_md(text: &str) -> Vec<MessageBlock> {
+        Self::parse_md_with_stable(text).0
+    }
+
+    /// Parse markdown into blocks, also returning the length of the prefix of `text`
+    /// which can't be affected by any further appended text, and how many blocks it contains.
+    fn parse_md_with_stable(text: &str) -> (Vec<MessageBlock>, usize, usize) {
         let root = markdown::to_mdast(text, &markdown::ParseOptions::default()).unwrap();
-        let mut blocks = match root.children() {
-            Some(nodes) => nodes
-                .iter()
-                .filter_map(|block| match block {
+        let nodes = root.children().map(Vec::as_slice).unwrap_or_default();
+        // The last node may still be growing, and the one before it can be changed by the last
+        // (e.g. a setext heading underline turns the previous paragraph into a heading),
+        // but anything before that is final.
+        let stable_nodes = nodes.len().saturating_sub(2);
+        let stable_len = match stable_nodes {
+            0 => 0,
+            n => nodes[n - 1].position().unwrap().end.offset,
+        };
+        let mut stable_blocks = 0;
+        let mut blocks: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, block)| {
+                let parsed = match block {
                     markdown::mdast::Node::Code(code_block) => {
                         if !code_block.value.is_empty() {
                             let fn_line = code_block.value.lines().next().unwrap();
@@ -765,48 +1185,67 @@ impl ChatMessage {
                             &text[position.start.offset..position.end.offset],
                         ))
                     }
-                })
-                .collect(),
-            None => vec![],
-        };
+                };
+                if parsed.is_some() && idx < stable_nodes {
+                    stable_blocks += 1;
+                }
+                parsed
+            })
+            .collect();
         if let Some(MessageBlock::Code(code_block)) = blocks.last_mut() {
             code_block.folded = false;
         }
         trace!("Parsed blocks: {:#?}", blocks);
-        blocks
+        (blocks, stable_len, stable_blocks)
     }
 
     pub fn append(&mut self, token: &str) {
-        self.raw += token;
-        self.raw = self
-            .raw
+        // Only the tail after the stable prefix needs to be reparsed
+        let mut tail = self.raw.split_off(self.stable_raw_len);
+        tail += token;
+        let tail = tail
             .replace("\n<BCODE>", "\n")
             .replace("\n</BCODE>", "\n")
             .replace("\r", "\n");
-        let mut blocks = Self::parse_md(&self.raw);
-        let prefix_spans = Self::format_user(&self.user);
-
-        if let Some(MessageBlock::Text(text_lines)) = blocks.first_mut() {
-            text_lines[0].spans = prefix_spans
-                .spans
-                .into_iter()
-                .chain(text_lines[0].spans.drain(..))
-                .collect();
-        } else {
-            blocks.insert(0, MessageBlock::Text(vec![prefix_spans]));
+        let (mut blocks, stable_len, mut stable_blocks) = Self::parse_md_with_stable(&tail);
+        self.raw += &tail;
+
+        if self.stable_block_count == 0 {
+            let prefix_spans = Self::format_user(&self.user);
+            if let Some(MessageBlock::Text(text_lines)) = blocks.first_mut() {
+                text_lines[0].spans = prefix_spans
+                    .spans
+                    .into_iter()
+                    .chain(text_lines[0].spans.drain(..))
+                    .collect();
+            } else {
+                blocks.insert(0, MessageBlock::Text(vec![prefix_spans]));
+                if stable_blocks > 0 {
+                    stable_blocks += 1;
+                }
+            }
         }
 
         // Update any existing blocks
-        for (i, (existing, new)) in self.blocks.iter_mut().zip(blocks.iter()).enumerate() {
+        let base = self.stable_block_count;
+        for (i, (existing, new)) in self.blocks[base..]
+            .iter_mut()
+            .zip(blocks.iter())
+            .enumerate()
+        {
             if existing != new {
                 *existing = new.clone();
-                self.block_line_cache.1.truncate(i);
+                self.block_line_cache.1.truncate(base + i);
             }
         }
 
         // And add any new blocks
+        let existing_len = self.blocks.len() - base;
         self.blocks
-            .extend_from_slice(&blocks[self.blocks.len().min(blocks.len())..]);
+            .extend_from_slice(&blocks[existing_len.min(blocks.len())..]);
+
+        self.stable_raw_len += stable_len;
+        self.stable_block_count += stable_blocks;
     }
 }
 
@@ -825,6 +1264,79 @@ impl From<api::ChatMessage> for ChatMessage {
     }
 }
 
+/// Slash commands handled in `App::handle_chat_input`, shown in the help popup.
+const SLASH_COMMANDS: &[(&str, &str)] = &[
+    ("/exit, /quit", "Exit the chat"),
+    ("/docs", "Open the Bismuth documentation"),
+    ("/new-session [NAME]", "Start a new session"),
+    ("/rename-session <NAME>", "Rename the current session"),
+    ("/session [NAME]", "Switch to a different session"),
+    ("/feedback <DESCRIPTION>", "Send us feedback"),
+    ("/diff", "Review the last diff Bismuth made"),
+    ("/wrap", "Toggle wrapping of long lines in code blocks"),
+    ("/refill", "Open billing page to refill credits"),
+    ("/retry", "Regenerate the response to your last message"),
+    ("/help", "Show this help"),
+];
+
+/// Key bindings handled in `App::run`, shown in the help popup.
+const KEY_BINDINGS: &[(&str, &str)] = &[
+    ("Enter", "Send message"),
+    ("Alt/Shift+Enter", "Insert a newline"),
+    (
+        "Ctrl+N",
+        "Start a new session, after typing its name (when the message box is empty)",
+    ),
+    ("Ctrl+C", "Stop the current generation, or exit the chat"),
+    ("Ctrl+K", "Cancel the command the agent is running"),
+    ("?", "Show this help (when the message box is empty)"),
+    ("Scroll", "Scroll the chat history"),
+    (
+        "Shift+Scroll, Left/Right",
+        "Scroll code blocks horizontally (when /wrap is off)",
+    ),
+    ("Click ⎘", "Copy a message or code block"),
+    ("Click code block", "Fold/unfold the code block"),
+    ("Drag", "Select and copy text from the chat history"),
+    ("Tab / Shift+Tab", "Select the next/previous code block"),
+    ("Enter / Space", "Fold/unfold the selected code block"),
+    ("Esc", "Clear the code block selection"),
+    ("y / n", "Commit / revert changes when reviewing a diff"),
+    (
+        "l",
+        "Toggle line numbers when reviewing a diff or watching the agent",
+    ),
+    (
+        "s",
+        "Save the agent's current file and command output next to the Bismuth config file (while watching the agent)",
+    ),
+    (
+        "h",
+        "Show/hide the log of what the agent has done (while watching the agent)",
+    ),
+];
+
+fn help_text() -> String {
+    let width = SLASH_COMMANDS
+        .iter()
+        .chain(KEY_BINDINGS.iter())
+        .map(|(k, _)| k.chars().count())
+        .max()
+        .unwrap_or(0);
+    let format_section = |entries: &[(&str, &str)]| {
+        entries
+            .iter()
+            .map(|(k, v)| format!("  {:width$}  {}", k, v, width = width))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    format!(
+        "Commands:\n{}\n\nKeys:\n{}",
+        format_section(SLASH_COMMANDS),
+        format_section(KEY_BINDINGS)
+    )
+}
+
 fn title_case(s: &str) -> String {
     let mut chars = s.chars();
     match chars.next() {
@@ -833,45 +1345,339 @@ fn title_case(s: &str) -> String {
     }
 }
 
+#[derive(Default)]
+struct Credits {
+    /// Remaining account credits
+    remaining: i32,
+    /// Credits used by finished generations in this session
+    session_used: u64,
+    /// Running total for the generation in progress
+    generation_used: u64,
+}
+
+impl Credits {
+    fn session_total(&self) -> u64 {
+        self.session_used + self.generation_used
+    }
+}
+
+/// A mouse selection in the chat history, as (rendered line, column) positions.
+/// Positions are relative to the content rather than the screen so that scrolling doesn't move it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Selection {
+    anchor: (usize, u16),
+    cursor: (usize, u16),
+}
+
+impl Selection {
+    /// Start and (inclusive) end of the selection, in order
+    fn ordered(&self) -> ((usize, u16), (usize, u16)) {
+        if self.anchor <= self.cursor {
+            (self.anchor, self.cursor)
+        } else {
+            (self.cursor, self.anchor)
+        }
+    }
+
+    /// A selection which never moved from where it started is just a click
+    fn is_empty(&self) -> bool {
+        self.anchor == self.cursor
+    }
+
+    fn contains(&self, line: usize, column: u16) -> bool {
+        let (start, end) = self.ordered();
+        start <= (line, column) && (line, column) <= end
+    }
+}
+
+/// Plain text of the rendered rows of `lines` from `start` to `end` (inclusive) when wrapped to `width`.
+/// Rows which only exist because a line was wrapped are joined back together rather than split by newlines.
+fn selected_text(lines: &[Line], width: u16, start: (usize, u16), end: (usize, u16)) -> String {
+    let mut text = String::new();
+    if width == 0 {
+        return text;
+    }
+    let mut row = 0;
+    let mut first = true;
+    for line in lines {
+        if row > end.0 {
+            break;
+        }
+        let paragraph = Paragraph::new(line.clone()).wrap(ratatui::widgets::Wrap { trim: false });
+        let nrows = paragraph.line_count(width);
+        if row + nrows <= start.0 {
+            row += nrows;
+            continue;
+        }
+
+        let area = Rect::new(0, 0, width, nrows as u16);
+        let mut buf = ratatui::buffer::Buffer::empty(area);
+        paragraph.render(area, &mut buf);
+        if !first {
+            text.push('\n');
+        }
+        first = false;
+        for wrapped_row in 0..nrows {
+            let abs_row = row + wrapped_row;
+            if abs_row < start.0 || abs_row > end.0 {
+                continue;
+            }
+            let from = if abs_row == start.0 { start.1 } else { 0 };
+            let to = if abs_row == end.0 {
+                end.1.min(width - 1)
+            } else {
+                width - 1
+            };
+            let segment: String = (from..=to)
+                .map(|x| buf[(x, wrapped_row as u16)].symbol())
+                .collect();
+            text.push_str(segment.trim_end());
+            // Word wrapping drops the whitespace it broke on, a full row means a word was split
+            if wrapped_row + 1 < nrows
+                && abs_row < end.0
+                && buf[(width - 1, wrapped_row as u16)].symbol() == " "
+            {
+                text.push(' ');
+            }
+        }
+        row += nrows;
+    }
+    text
+}
+
 struct ChatHistoryWidget {
     messages: Arc<Mutex<Vec<ChatMessage>>>,
     scroll_position: usize,
     scroll_max: usize,
     scroll_state: ratatui::widgets::ScrollbarState,
+    /// Where the widget was last rendered, used to map mouse clicks back to lines
+    area: ratatui::layout::Rect,
+    selection: Option<Selection>,
+    /// Set when a selection is finished, the next render fills in `copied_selection`
+    copy_selection: bool,
+    /// Text of the finished selection, waiting to be put on the clipboard
+    copied_selection: Option<String>,
     code_block_hitboxes: Vec<(usize, usize)>,
     message_hitboxes: Vec<(usize, usize)>,
+    /// Whether long lines in code blocks are wrapped, or clipped and scrolled horizontally
+    wrap_code: bool,
+    h_scroll_position: usize,
+    h_scroll_max: usize,
+    /// Index of the code block selected for keyboard folding
+    selected_code_block: Option<usize>,
     sessions: Vec<api::ChatSession>,
     session: api::ChatSession,
     feature: api::Feature,
     project: api::Project,
-    credit_remaining: Arc<Mutex<i32>>,
+    credits: Arc<Mutex<Credits>>,
+    /// The model the agent is configured to use, if one has been chosen
+    model: Option<String>,
+    dry_run: bool,
+    /// Shown in the title while the websocket connection is being re-established
+    connection_status: Arc<Mutex<Option<String>>>,
+}
+
+impl ChatHistoryWidget {
+    /// Whether the last message is showing the animated thinking indicator
+    fn is_thinking(&self) -> bool {
+        self.messages
+            .lock()
+            .unwrap()
+            .last()
+            .is_some_and(|msg| matches!(msg.blocks.last(), Some(MessageBlock::Thinking(_))))
+    }
+
+    fn toggle_wrap_code(&mut self) {
+        self.wrap_code = !self.wrap_code;
+        self.h_scroll_position = 0;
+    }
+
+    /// Rendered chat line and column under the given screen position,
+    /// or None if it's on the border or outside of the chat history entirely.
+    fn position_at(&self, column: u16, row: u16) -> Option<(usize, u16)> {
+        let inner = self.area.inner(Margin::new(1, 1));
+        if column < inner.x
+            || column >= inner.x + inner.width
+            || row < inner.y
+            || row >= inner.y + inner.height
+        {
+            return None;
+        }
+        Some((
+            self.scroll_position + (row - inner.y) as usize,
+            column - inner.x,
+        ))
+    }
+
+    /// Index of the rendered chat line under the given screen position,
+    /// or None if it's on the border or outside of the chat history entirely.
+    fn line_at(&self, column: u16, row: u16) -> Option<usize> {
+        self.position_at(column, row).map(|(line, _)| line)
+    }
+
+    /// Begin a mouse selection at the given screen position
+    fn start_selection(&mut self, column: u16, row: u16) {
+        self.selection = self.position_at(column, row).map(|pos| Selection {
+            anchor: pos,
+            cursor: pos,
+        });
+    }
+
+    /// Move the end of the selection to the given screen position,
+    /// scrolling if it's been dragged past the top or bottom of the chat history.
+    fn extend_selection(&mut self, column: u16, row: u16) {
+        if self.selection.is_none() {
+            return;
+        }
+        let inner = self.area.inner(Margin::new(1, 1));
+        if inner.width == 0 || inner.height == 0 {
+            return;
+        }
+        if row < inner.y {
+            self.scroll_position = self.scroll_position.saturating_sub(1);
+        } else if row >= inner.y + inner.height {
+            self.scroll_position = (self.scroll_position + 1).min(self.scroll_max);
+        }
+        let column = column.clamp(inner.x, inner.right().saturating_sub(1));
+        let row = row.clamp(inner.y, inner.bottom().saturating_sub(1));
+        if let (Some(pos), Some(selection)) =
+            (self.position_at(column, row), self.selection.as_mut())
+        {
+            selection.cursor = pos;
+        }
+    }
+
+    /// Finish the mouse selection, copying it on the next render.
+    /// Returns false (and drops the selection) if the mouse never moved, i.e. it was a click.
+    fn finish_selection(&mut self) -> bool {
+        match self.selection {
+            Some(selection) if !selection.is_empty() => {
+                self.copy_selection = true;
+                true
+            }
+            _ => {
+                self.selection = None;
+                false
+            }
+        }
+    }
+
+    /// Whether the given screen position is on the copy icon of a hitbox starting at `start`
+    fn is_copy_icon_click(&self, column: u16, row: u16, start: usize) -> bool {
+        // The icon takes up the first two columns inside the border
+        self.line_at(column, row) == Some(start) && column - self.area.x - 1 < 2
+    }
+
+    fn line_cache_key(&self, area: ratatui::layout::Rect) -> LineCacheKey {
+        LineCacheKey {
+            width: area.width,
+            wrap_code: self.wrap_code,
+        }
+    }
+
+    /// Move the code block selection forwards or backwards, scrolling it into view
+    fn select_code_block(&mut self, delta: isize) {
+        let count = self.code_block_hitboxes.len();
+        if count == 0 {
+            self.selected_code_block = None;
+            return;
+        }
+        let idx = match self.selected_code_block {
+            Some(idx) => (idx as isize + delta).rem_euclid(count as isize) as usize,
+            None if delta < 0 => count - 1,
+            None => 0,
+        };
+        self.selected_code_block = Some(idx);
+        self.scroll_position = self.code_block_hitboxes[idx].0.min(self.scroll_max);
+    }
+
+    /// Fold or unfold the selected code block. Returns false if nothing is selected.
+    fn toggle_selected_code_block(&mut self) -> bool {
+        let Some(selected) = self.selected_code_block else {
+            return false;
+        };
+        let mut idx = 0;
+        for msg in self.messages.lock().unwrap().iter_mut() {
+            for block in &mut msg.blocks {
+                if let MessageBlock::Code(code) = block {
+                    if idx == selected {
+                        code.folded = !code.folded;
+                        msg.block_line_cache.1.clear();
+                        return true;
+                    }
+                    idx += 1;
+                }
+            }
+        }
+        false
+    }
+
+    fn scroll_horizontal(&mut self, delta: isize) {
+        self.h_scroll_position = self
+            .h_scroll_position
+            .saturating_add_signed(delta)
+            .clamp(0, self.h_scroll_max);
+    }
 }
 
 impl Widget for &mut ChatHistoryWidget {
     fn render(self, area: ratatui::layout::Rect, buf: &mut ratatui::buffer::Buffer) {
-        let credit_remaining = *self.credit_remaining.lock().unwrap();
+        self.area = area;
+        let (credit_remaining, session_usage) = {
+            let credits = self.credits.lock().unwrap();
+            (credits.remaining, credits.session_total())
+        };
         let block = Block::new()
-            .title(
-                Title::from(format!(
-                    " Chat History ({}/{}{}) ",
-                    self.project.name,
-                    self.feature.name,
-                    match &self.session._name {
-                        Some(name) => format!(" - {}", name),
-                        None => "".to_string(),
-                    }
-                ))
-                .alignment(ratatui::layout::Alignment::Left),
-            )
             .title(
                 Title::from(vec![
-                    " Remaining Credits: ".into(),
-                    if credit_remaining > 0 {
-                        Span::raw(format!("{} ", credit_remaining))
-                    } else {
+                    Span::raw(format!(
+                        " Chat History ({}/{}{}) ",
+                        self.project.name,
+                        self.feature.name,
+                        match &self.session._name {
+                            Some(name) => format!(" - {}", name),
+                            None => "".to_string(),
+                        }
+                    )),
+                    if self.dry_run {
                         Span::styled(
-                            format!("{} ", credit_remaining),
-                            ratatui::style::Style::default().fg(ratatui::style::Color::Red),
+                            "[DRY RUN: changes will not be applied, commands will not be run] ",
+                            ratatui::style::Style::default().fg(theme().error),
+                        )
+                    } else {
+                        Span::raw("")
+                    },
+                    match &self.model {
+                        Some(model) => Span::styled(
+                            format!("[{}] ", model),
+                            ratatui::style::Style::default().fg(theme().accent),
+                        ),
+                        None => Span::raw(""),
+                    },
+                    match &*self.connection_status.lock().unwrap() {
+                        Some(status) => Span::styled(
+                            format!("[{}] ", status),
+                            ratatui::style::Style::default().fg(theme().notice),
+                        ),
+                        None => Span::raw(""),
+                    },
+                ])
+                .alignment(ratatui::layout::Alignment::Left),
+            )
+            .title(
+                Title::from(vec![
+                    if session_usage > 0 {
+                        Span::raw(format!(" Session: {} Credits Used |", session_usage))
+                    } else {
+                        Span::raw("")
+                    },
+                    " Remaining Credits: ".into(),
+                    if credit_remaining > 0 {
+                        Span::raw(format!("{} ", credit_remaining))
+                    } else {
+                        Span::styled(
+                            format!("{} ", credit_remaining),
+                            ratatui::style::Style::default().fg(theme().error),
                         )
                     },
                 ])
@@ -885,6 +1691,14 @@ impl Widget for &mut ChatHistoryWidget {
         let mut code_block_hitboxes: Vec<(usize, usize)> = vec![];
         let mut message_hitboxes: Vec<(usize, usize)> = vec![];
 
+        // -2 for the borders, -2 for the code block indent
+        let code_width = (area.width as usize).saturating_sub(4);
+        let wrap_code = self.wrap_code;
+        let h_scroll_position = self.h_scroll_position;
+        let mut code_max_width = 0;
+        let selected_code_block = self.selected_code_block;
+        let cache_key = self.line_cache_key(area);
+
         let mut messages = self.messages.lock().unwrap();
         if messages.len() > 0 {
             let lines: Vec<_> = messages
@@ -915,15 +1729,15 @@ impl Widget for &mut ChatHistoryWidget {
                                     vec![Line::styled(
                                         format!("  {} {}", detail, indicator),
                                         ratatui::style::Style::default().fg(if is_last {
-                                            ratatui::style::Color::LightGreen
+                                            theme().active
                                         } else {
-                                            ratatui::style::Color::Green
+                                            theme().success
                                         }),
                                     )]
                                 }
                                 MessageBlock::Code(code) => {
-                                    let code_block_lines = if code.folded {
-                                        vec![Line::styled(
+                                    let mut code_block_lines = if code.folded {
+                                        vec![Line::from(Span::styled(
                                             if let Some(filename) = &code.filename {
                                                 format!("Change to {} (click to expand)", &filename)
                                             } else {
@@ -935,19 +1749,46 @@ impl Widget for &mut ChatHistoryWidget {
                                                     .trim(),
                                                 )
                                             },
-                                            ratatui::style::Style::default()
-                                                .fg(ratatui::style::Color::Yellow),
-                                        )]
+                                            ratatui::style::Style::default().fg(theme().notice),
+                                        ))]
                                     } else {
                                         code.lines()
                                             .iter()
                                             .map(|line| {
-                                                let mut indented = line.as_line();
+                                                let mut indented = if wrap_code {
+                                                    line.as_line()
+                                                } else {
+                                                    code_max_width =
+                                                        code_max_width.max(line.width());
+                                                    line.as_clipped_line(
+                                                        h_scroll_position,
+                                                        code_width,
+                                                    )
+                                                };
                                                 indented.spans.insert(0, "│ ".into());
                                                 indented
                                             })
                                             .collect()
                                     };
+                                    if selected_code_block == Some(code_block_hitboxes.len()) {
+                                        let selected_style = ratatui::style::Style::default()
+                                            .fg(theme().accent)
+                                            .add_modifier(ratatui::style::Modifier::BOLD);
+                                        for line in code_block_lines.iter_mut() {
+                                            line.spans[0] =
+                                                line.spans[0].clone().patch_style(selected_style);
+                                        }
+                                    }
+                                    // Copy button for just this block
+                                    if *CLIPBOARD_AVAILABLE {
+                                        if let Some(first) = code_block_lines.first_mut() {
+                                            if code.folded {
+                                                first.spans.insert(0, "⎘ ".into());
+                                            } else {
+                                                first.spans[0].content = "⎘ ".into();
+                                            }
+                                        }
+                                    }
                                     code_block_hitboxes
                                         .push((line_idx, line_idx + code_block_lines.len()));
                                     code_block_lines
@@ -955,15 +1796,15 @@ impl Widget for &mut ChatHistoryWidget {
                             };
                             lines.push(Line::raw(""));
                             let rendered_line_len = if message.finalized
-                                && message.block_line_cache.0 == area.width as usize
+                                && message.block_line_cache.0 == cache_key
                                 && message.block_line_cache.1.len() > idx
                             {
                                 message.block_line_cache.1[idx]
                             } else {
-                                // Just resized, so clear the cache.
+                                // Just resized (or otherwise re-styled), so clear the cache.
                                 // The len guard above will make sure we end up recalculating each block
-                                if message.block_line_cache.0 != area.width as usize {
-                                    message.block_line_cache.0 = area.width as usize;
+                                if message.block_line_cache.0 != cache_key {
+                                    message.block_line_cache.0 = cache_key;
                                     message.block_line_cache.1.clear();
                                 }
                                 // have to "simulate" line wrapping here to get an accurate line count
@@ -988,6 +1829,7 @@ impl Widget for &mut ChatHistoryWidget {
                 })
                 .collect();
 
+            let selection_lines = self.copy_selection.then(|| lines.clone());
             let paragraph = Paragraph::new(ratatui::text::Text::from_iter(lines))
                 .block(block)
                 .scroll((self.scroll_position as u16, 0))
@@ -1001,6 +1843,8 @@ impl Widget for &mut ChatHistoryWidget {
             if self.scroll_position == old_scroll_max {
                 self.scroll_position = self.scroll_max;
             }
+            // Content can shrink or the terminal grow, e.g. after a resize
+            self.scroll_position = self.scroll_position.min(self.scroll_max);
             self.scroll_state = self
                 .scroll_state
                 .position(self.scroll_position)
@@ -1009,7 +1853,28 @@ impl Widget for &mut ChatHistoryWidget {
             self.code_block_hitboxes = code_block_hitboxes;
             self.message_hitboxes = message_hitboxes;
 
+            self.h_scroll_max = code_max_width.saturating_sub(code_width);
+            self.h_scroll_position = self.h_scroll_position.min(self.h_scroll_max);
+
             paragraph.render(area, buf);
+            if let Some(selection) = self.selection {
+                let inner = area.inner(Margin::new(1, 1));
+                for y in inner.top()..inner.bottom() {
+                    for x in inner.left()..inner.right() {
+                        let line = self.scroll_position + (y - inner.y) as usize;
+                        if selection.contains(line, x - inner.x) {
+                            buf[(x, y)].set_style(
+                                Style::default().add_modifier(ratatui::style::Modifier::REVERSED),
+                            );
+                        }
+                    }
+                }
+                if let Some(lines) = selection_lines {
+                    let (start, end) = selection.ordered();
+                    self.copied_selection = Some(selected_text(&lines, inner.width, start, end));
+                }
+            }
+            self.copy_selection = false;
             StatefulWidget::render(
                 Scrollbar::new(ratatui::widgets::ScrollbarOrientation::VerticalRight),
                 area,
@@ -1026,7 +1891,7 @@ impl Widget for &mut ChatHistoryWidget {
 |____/|_|___/_| |_| |_|\__,_|\__|_| |_|
 "#
             .split('\n')
-            .map(|line| Line::styled(line, Style::default().fg(ratatui::style::Color::Magenta)))
+            .map(|line| Line::styled(line, Style::default().fg(theme().brand)))
             .collect::<Vec<_>>();
             lines.push(Line::raw("Use `/session` to change session"));
             let paragraph = Paragraph::new(lines);
@@ -1039,7 +1904,7 @@ impl Widget for &mut ChatHistoryWidget {
                 "Ctrl+C: Exit",
                 "/session: Switch session",
                 "/feedback: Send feedback",
-                "/help: Show full help",
+                "/help or ?: Show full help",
             ];
             let legend_height = legend_text.len() as u16;
             let legend_width = legend_text.iter().map(|s| s.len()).max().unwrap() as u16;
@@ -1052,7 +1917,7 @@ impl Widget for &mut ChatHistoryWidget {
             let legend = Paragraph::new(
                 legend_text
                     .into_iter()
-                    .map(|t| Line::styled(t, Style::default().fg(ratatui::style::Color::DarkGray)))
+                    .map(|t| Line::styled(t, Style::default().fg(theme().muted)))
                     .collect::<Vec<_>>(),
             )
             .alignment(ratatui::layout::Alignment::Right);
@@ -1061,12 +1926,198 @@ impl Widget for &mut ChatHistoryWidget {
     }
 }
 
+/// Hunks longer than this are shown without intraline highlighting, diffing every line pair is too slow
+const INTRALINE_DIFF_MAX_HUNK_LINES: usize = 500;
+/// Changed line pairs less similar than this are just shown as a whole line removed and added
+const INTRALINE_DIFF_MIN_RATIO: f32 = 0.5;
+
+fn is_added_line(line: &str) -> bool {
+    line.starts_with('+') && !line.starts_with("+++")
+}
+
+fn is_removed_line(line: &str) -> bool {
+    line.starts_with('-') && !line.starts_with("---")
+}
+
+fn diff_line_style(line: &str) -> Style {
+    if is_added_line(line) {
+        Style::default().fg(theme().success)
+    } else if is_removed_line(line) {
+        Style::default().fg(theme().error)
+    } else {
+        Style::default()
+    }
+}
+
+/// Segments of a line, flagged if they differ from the line it was diffed against
+type DiffSegments = Vec<(String, bool)>;
+
+/// Word diff a removed line against the added line which replaced it (both without the +/- prefix).
+/// Returns None if the lines are too different for highlighting the changed words to be useful.
+fn intraline_diff(old: &str, new: &str) -> Option<(DiffSegments, DiffSegments)> {
+    let diff = similar::TextDiff::from_words(old, new);
+    if diff.ratio() < INTRALINE_DIFF_MIN_RATIO {
+        return None;
+    }
+
+    fn push(segments: &mut DiffSegments, s: &str, changed: bool) {
+        match segments.last_mut() {
+            Some((last, last_changed)) if *last_changed == changed => last.push_str(s),
+            _ => segments.push((s.to_string(), changed)),
+        }
+    }
+
+    let mut old_segments = vec![];
+    let mut new_segments = vec![];
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            similar::ChangeTag::Equal => {
+                push(&mut old_segments, change.value(), false);
+                push(&mut new_segments, change.value(), false);
+            }
+            similar::ChangeTag::Delete => push(&mut old_segments, change.value(), true),
+            similar::ChangeTag::Insert => push(&mut new_segments, change.value(), true),
+        }
+    }
+    Some((old_segments, new_segments))
+}
+
+/// Color a unified diff, emphasizing just the changed words in lines which were edited
+fn highlight_diff(diff: &str) -> Vec<OwnedLine> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let segmented_line = |line: &str, segments: &DiffSegments| {
+        let style = diff_line_style(line);
+        let emphasis = style.add_modifier(ratatui::style::Modifier::REVERSED);
+        OwnedLine::from(
+            std::iter::once((&line[..1], style))
+                .chain(
+                    segments
+                        .iter()
+                        .map(|(s, changed)| (s.as_str(), if *changed { emphasis } else { style })),
+                )
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let mut highlighted = Vec::with_capacity(lines.len());
+    let mut small_hunk = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with("diff ") {
+            small_hunk = false;
+        } else if line.starts_with("@@") {
+            small_hunk = lines[i + 1..]
+                .iter()
+                .take_while(|l| !l.starts_with("@@") && !l.starts_with("diff "))
+                .count()
+                <= INTRALINE_DIFF_MAX_HUNK_LINES;
+        }
+
+        if !(small_hunk && is_removed_line(line)) {
+            highlighted.push(OwnedLine::from(vec![(line, diff_line_style(line))]));
+            i += 1;
+            continue;
+        }
+
+        // Pair up a run of removed lines with the run of added lines replacing them
+        let removed_end = i + lines[i..].iter().take_while(|l| is_removed_line(l)).count();
+        let added_end = removed_end
+            + lines[removed_end..]
+                .iter()
+                .take_while(|l| is_added_line(l))
+                .count();
+        let removed = &lines[i..removed_end];
+        let added = &lines[removed_end..added_end];
+        let pairs: Vec<_> = removed
+            .iter()
+            .zip(added)
+            .map(|(old, new)| intraline_diff(&old[1..], &new[1..]))
+            .collect();
+        for (idx, line) in removed.iter().enumerate() {
+            highlighted.push(match pairs.get(idx) {
+                Some(Some((old, _))) => segmented_line(line, old),
+                _ => OwnedLine::from(vec![(*line, diff_line_style(line))]),
+            });
+        }
+        for (idx, line) in added.iter().enumerate() {
+            highlighted.push(match pairs.get(idx) {
+                Some(Some((_, new))) => segmented_line(line, new),
+                _ => OwnedLine::from(vec![(*line, diff_line_style(line))]),
+            });
+        }
+        i = added_end;
+    }
+    highlighted
+}
+
+/// Old and new file line numbers for each line of a unified diff.
+/// Lines outside of a hunk (file headers etc.) have neither.
+fn diff_line_numbers(diff: &str) -> Vec<(Option<usize>, Option<usize>)> {
+    let mut old = 0;
+    let mut new = 0;
+    let mut in_hunk = false;
+    diff.lines()
+        .map(|line| {
+            if line.starts_with("@@") {
+                // @@ -old_start[,old_len] +new_start[,new_len] @@
+                let mut ranges = line.split_whitespace().skip(1).map(|range| {
+                    range[1..]
+                        .split(',')
+                        .next()
+                        .and_then(|start| start.parse().ok())
+                });
+                match (ranges.next().flatten(), ranges.next().flatten()) {
+                    (Some(old_start), Some(new_start)) => {
+                        old = old_start;
+                        new = new_start;
+                        in_hunk = true;
+                    }
+                    _ => in_hunk = false,
+                }
+                return (None, None);
+            }
+            if line.starts_with("diff ") {
+                in_hunk = false;
+            }
+            if !in_hunk {
+                return (None, None);
+            }
+            match line.chars().next() {
+                Some('-') => {
+                    old += 1;
+                    (Some(old - 1), None)
+                }
+                Some('+') => {
+                    new += 1;
+                    (None, Some(new - 1))
+                }
+                // "\ No newline at end of file"
+                Some('\\') => (None, None),
+                _ => {
+                    old += 1;
+                    new += 1;
+                    (Some(old - 1), Some(new - 1))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Number of digits needed to show line numbers up to `max`
+fn line_number_width(max: usize) -> usize {
+    max.max(1).ilog10() as usize + 1
+}
+
 #[derive(Clone, Debug)]
 struct DiffReviewWidget {
     lines: Vec<OwnedLine>,
+    line_numbers: Vec<(Option<usize>, Option<usize>)>,
     commit_message: Option<String>,
     msg_id: u64,
     can_apply: bool,
+    /// Changes are only a preview and were never written to disk
+    dry_run: bool,
     v_scroll_position: usize,
     v_scroll_max: usize,
     v_scroll_state: ratatui::widgets::ScrollbarState,
@@ -1076,26 +2127,27 @@ struct DiffReviewWidget {
 }
 
 impl DiffReviewWidget {
+    fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .map(|(s, _)| s.as_str())
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect()
+    }
+
     fn new(diff: String, msg_id: u64, commit_message: Option<String>) -> Self {
         Self {
-            lines: diff
-                .lines()
-                .map(|line| {
-                    OwnedLine::from(vec![(
-                        line,
-                        if line.starts_with('+') && !line.starts_with("+++") {
-                            Style::default().fg(ratatui::style::Color::Green)
-                        } else if line.starts_with('-') && !line.starts_with("---") {
-                            Style::default().fg(ratatui::style::Color::Red)
-                        } else {
-                            Style::default()
-                        },
-                    )])
-                })
-                .collect::<Vec<_>>(),
+            lines: highlight_diff(&diff),
+            line_numbers: diff_line_numbers(&diff),
             commit_message,
             msg_id,
             can_apply: true,
+            dry_run: false,
             v_scroll_position: 0,
             v_scroll_max: diff.lines().count(),
             v_scroll_state: ratatui::widgets::ScrollbarState::default(),
@@ -1108,14 +2160,64 @@ impl DiffReviewWidget {
 
 impl Widget for &mut DiffReviewWidget {
     fn render(self, area: ratatui::layout::Rect, buf: &mut ratatui::buffer::Buffer) {
-        let area = centered(self.h_scroll_max, self.lines.len(), area);
+        // Old and new line numbers, each followed by a space
+        let number_width = line_number_width(
+            self.line_numbers
+                .iter()
+                .flat_map(|&(old, new)| old.max(new))
+                .max()
+                .unwrap_or(0),
+        );
+        let gutter_width = if LINE_NUMBERS.load(Ordering::Relaxed) {
+            (number_width + 1) * 2
+        } else {
+            0
+        };
+        let area = centered(self.h_scroll_max + gutter_width, self.lines.len(), area);
+        // -2 for the borders
+        let code_width = (area.width as usize).saturating_sub(2 + gutter_width);
 
         self.v_scroll_position = self
             .v_scroll_position
             .min(self.v_scroll_max.saturating_sub(area.height as usize - 2));
         self.h_scroll_position = self
             .h_scroll_position
-            .min(self.h_scroll_max.saturating_sub(area.width as usize - 2));
+            .min(self.h_scroll_max.saturating_sub(code_width));
+
+        let block = Block::bordered().title(vec![
+            " Review Diff ".into(),
+            if self.dry_run {
+                Span::styled(
+                    "(dry run, changes were not applied - press Esc to close) ",
+                    theme().notice,
+                )
+            } else if self.can_apply {
+                Span::styled("(y to commit, n to revert) ", theme().notice)
+            } else {
+                Span::styled("(press Esc to close) ", theme().notice)
+            },
+        ]);
+        let [gutter_area, code_area] =
+            Layout::horizontal([Constraint::Length(gutter_width as u16), Constraint::Fill(1)])
+                .areas(block.inner(area));
+
+        // Only scrolled vertically, so the numbers stay put when scrolling horizontally
+        let format_number = |n: Option<usize>| match n {
+            Some(n) => format!("{:>width$} ", n, width = number_width),
+            None => " ".repeat(number_width + 1),
+        };
+        let gutter = Paragraph::new(
+            self.line_numbers
+                .iter()
+                .map(|(old, new)| {
+                    Line::styled(
+                        format_number(*old) + &format_number(*new),
+                        Style::default().fg(theme().muted),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .scroll((self.v_scroll_position as u16, 0));
 
         let paragraph = Paragraph::new(
             self.lines
@@ -1123,14 +2225,6 @@ impl Widget for &mut DiffReviewWidget {
                 .map(OwnedLine::as_line)
                 .collect::<Vec<_>>(),
         )
-        .block(Block::bordered().title(vec![
-            " Review Diff ".into(),
-            if self.can_apply {
-                Span::styled("(y to commit, n to revert) ", ratatui::style::Color::Yellow)
-            } else {
-                Span::styled("(press Esc to close) ", ratatui::style::Color::Yellow)
-            },
-        ]))
         .scroll((self.v_scroll_position as u16, self.h_scroll_position as u16));
 
         self.v_scroll_state = self
@@ -1141,10 +2235,12 @@ impl Widget for &mut DiffReviewWidget {
         self.h_scroll_state = self
             .h_scroll_state
             .position(self.h_scroll_position)
-            .content_length(self.h_scroll_max.saturating_sub(area.width as usize - 2));
+            .content_length(self.h_scroll_max.saturating_sub(code_width));
 
         Clear.render(area, buf);
-        paragraph.render(area, buf);
+        block.render(area, buf);
+        gutter.render(gutter_area, buf);
+        paragraph.render(code_area, buf);
         StatefulWidget::render(
             Scrollbar::new(ratatui::widgets::ScrollbarOrientation::VerticalRight),
             area,
@@ -1173,7 +2269,7 @@ impl Widget for &mut SelectSessionWidget {
         for (idx, session) in self.sessions.iter().enumerate() {
             let mut line = Line::raw(session.name());
             if idx == self.selected_idx {
-                line = line.style(Style::default().bg(ratatui::style::Color::Blue));
+                line = line.style(Style::default().bg(theme().selected_bg));
             }
             lines.push(line);
         }
@@ -1211,15 +2307,172 @@ struct ACIVizWidget {
     files: Vec<String>,
     current_idx: Option<usize>,
     contents: CodeBlock,
+    /// The agent is reading through the file, so the view moves down a page at a time
     in_scroll: bool,
-    anim_scroll_position: usize,
+    /// Fractional, as it glides toward `target_scroll_position` over several frames
+    anim_scroll_position: f64,
+    /// When the view last moved down a page
     anim_scroll_time: Instant,
+    /// When `anim_scroll_position` was last updated
+    anim_frame_time: Instant,
     target_scroll_position: usize,
+    /// Lines per second to scroll at, 0 to jump straight to the target
+    scroll_speed: u32,
     status: String,
     test_output: Option<String>,
     run_cmd_output: Option<String>,
+    /// Used to cancel the currently running command, if any
+    cancel_cmd: Option<Arc<tokio::sync::Notify>>,
     /// Credit usage
     usage: u64,
+    /// Output of the tests and commands run so far, oldest first.
+    /// The output panel only shows the latest, this is kept so it can be saved.
+    output_history: VecDeque<String>,
+    /// Where the view was last saved to, or why saving it failed
+    saved_to: Option<Result<PathBuf, String>>,
+    /// Recent statuses and when they were set, oldest first
+    status_log: VecDeque<(Instant, String)>,
+    started_at: Instant,
+    /// Whether the status log panel is expanded
+    show_status_log: bool,
+}
+
+/// Number of statuses to keep in `ACIVizWidget::status_log`
+const ACI_STATUS_LOG_LEN: usize = 200;
+
+/// Number of test and command outputs to keep in `ACIVizWidget::output_history`
+const ACI_OUTPUT_HISTORY_LEN: usize = 50;
+
+/// How often the view moves down a page while the agent is reading through a file
+const ACI_PAGE_INTERVAL: Duration = Duration::from_millis(1000);
+/// Redraw interval while the view is scrolling
+const ACI_FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Move a scroll position toward `target` at `speed` lines per second, or straight there if speed is 0
+fn scroll_toward(position: f64, target: usize, elapsed: Duration, speed: u32) -> f64 {
+    let target = target as f64;
+    let step = speed as f64 * elapsed.as_secs_f64();
+    if speed == 0 || (target - position).abs() <= step {
+        target
+    } else {
+        position + step.copysign(target - position)
+    }
+}
+
+impl ACIVizWidget {
+    fn new(
+        files: Vec<String>,
+        active_file: &str,
+        contents: &str,
+        scroll_position: usize,
+        scroll_speed: u32,
+    ) -> Self {
+        let status = format!("Looking through {}", active_file);
+        let now = Instant::now();
+        ACIVizWidget {
+            current_idx: files.iter().position(|f| f == active_file),
+            files,
+            contents: CodeBlock::new(Some(active_file), None, contents),
+            in_scroll: true,
+            anim_scroll_position: scroll_position as f64,
+            anim_scroll_time: now,
+            anim_frame_time: now,
+            target_scroll_position: scroll_position,
+            scroll_speed,
+            status_log: VecDeque::from([(now, status.clone())]),
+            status,
+            started_at: now,
+            show_status_log: false,
+            test_output: None,
+            run_cmd_output: None,
+            cancel_cmd: None,
+            usage: 0,
+            output_history: VecDeque::new(),
+            saved_to: None,
+        }
+    }
+
+    /// Whether the view needs redrawing at `ACI_FRAME_INTERVAL` to animate scrolling.
+    /// Between page steps, and once the end of the file is reached, the slow tick is enough.
+    fn is_scrolling(&self) -> bool {
+        self.scroll_speed > 0 && self.anim_scroll_position != self.target_scroll_position as f64
+    }
+
+    /// `scroll_speed`, raised enough to catch up with each page step before the next one
+    fn effective_scroll_speed(&self, page_lines: usize) -> u32 {
+        if self.scroll_speed == 0 {
+            return 0;
+        }
+        let catch_up = page_lines as f64 * 2.0 / ACI_PAGE_INTERVAL.as_secs_f64();
+        self.scroll_speed.max(catch_up.ceil() as u32)
+    }
+
+    fn set_status(&mut self, status: String) {
+        // Scrolling around a file repeats the same status, which isn't interesting in the log
+        if self.status_log.back().map(|(_, last)| last) != Some(&status) {
+            if self.status_log.len() == ACI_STATUS_LOG_LEN {
+                self.status_log.pop_front();
+            }
+            self.status_log.push_back((Instant::now(), status.clone()));
+        }
+        self.status = status;
+    }
+
+    /// Status log entries as "mm:ss status", timed from when the agent started
+    fn status_log_lines(&self) -> Vec<(String, String)> {
+        self.status_log
+            .iter()
+            .map(|(at, status)| {
+                let elapsed = at.saturating_duration_since(self.started_at).as_secs();
+                (
+                    format!("{:02}:{:02}", elapsed / 60, elapsed % 60),
+                    status.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn push_output(&mut self, output: String) {
+        if self.output_history.len() == ACI_OUTPUT_HISTORY_LEN {
+            self.output_history.pop_front();
+        }
+        self.output_history.push_back(output);
+    }
+
+    /// Write the file being shown and the output history to a new directory, returning its path
+    fn save(&self, parent: &Path) -> Result<PathBuf> {
+        let dir = parent.join(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis()
+                .to_string(),
+        );
+        std::fs::create_dir_all(&dir)?;
+        if let Some(idx) = self.current_idx {
+            // Flattened, the path comes from the server
+            let name = Path::new(&self.files[idx])
+                .file_name()
+                .map(|name| name.to_os_string())
+                .unwrap_or_else(|| "file".into());
+            std::fs::write(dir.join(name), &self.contents.raw_code)?;
+        }
+        std::fs::write(
+            dir.join("output.log"),
+            self.output_history
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        )?;
+        std::fs::write(
+            dir.join("status.log"),
+            self.status_log_lines()
+                .into_iter()
+                .map(|(time, status)| format!("{} {}\n", time, status))
+                .collect::<String>(),
+        )?;
+        Ok(dir)
+    }
 }
 
 impl Widget for &mut ACIVizWidget {
@@ -1240,6 +2493,32 @@ impl Widget for &mut ACIVizWidget {
         ]);
         let [tab_area, file_area, status_area] = vertical.areas(area);
 
+        let file_area = if self.show_status_log {
+            let [file_area, log_area] =
+                Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                    .areas(file_area);
+            let log_lines: Vec<_> = self
+                .status_log_lines()
+                .into_iter()
+                .map(|(time, status)| {
+                    Line::from(vec![
+                        Span::styled(time + " ", Style::default().fg(theme().muted)),
+                        Span::raw(status),
+                    ])
+                })
+                .collect();
+            let log = Paragraph::new(log_lines)
+                .block(Block::new().borders(Borders::LEFT).title(" Agent Log "))
+                .wrap(ratatui::widgets::Wrap { trim: false });
+            // Keep the latest entries in view
+            let log_len = log.line_count(log_area.width.saturating_sub(1));
+            log.scroll((log_len.saturating_sub(log_area.height as usize) as u16, 0))
+                .render(log_area, buf);
+            file_area
+        } else {
+            file_area
+        };
+
         let mut tabs = Tabs::new(
             self.files
                 .iter()
@@ -1248,36 +2527,65 @@ impl Widget for &mut ACIVizWidget {
         )
         .divider("")
         .padding("", "")
-        .style(Style::default().bg(ratatui::style::Color::DarkGray))
-        .highlight_style(Style::default().bg(ratatui::style::Color::DarkGray));
+        .style(Style::default().bg(theme().panel_bg))
+        .highlight_style(Style::default().bg(theme().panel_bg));
         if let Some(idx) = self.current_idx {
             tabs = tabs
                 .select(idx)
-                .highlight_style(Style::default().bg(ratatui::style::Color::Blue));
+                .highlight_style(Style::default().bg(theme().selected_bg));
         }
         tabs.render(tab_area, buf);
 
         let lines = self.contents.lines();
         let scroll_max = lines.len().saturating_sub(file_area.height as usize);
 
-        if self.anim_scroll_time.elapsed() > Duration::from_millis(1000) {
+        let page_lines = (file_area.height as usize).saturating_sub(5).max(1);
+        if self.anim_scroll_time.elapsed() > ACI_PAGE_INTERVAL {
             if self.in_scroll {
-                self.anim_scroll_position += file_area.height as usize - 5;
+                self.target_scroll_position += page_lines;
             }
             self.anim_scroll_time = Instant::now();
         }
-        self.anim_scroll_position = self.anim_scroll_position.min(scroll_max);
+        self.target_scroll_position = self.target_scroll_position.min(scroll_max);
+        self.anim_scroll_position = scroll_toward(
+            self.anim_scroll_position.min(scroll_max as f64),
+            self.target_scroll_position,
+            self.anim_frame_time.elapsed(),
+            self.effective_scroll_speed(page_lines),
+        );
+        self.anim_frame_time = Instant::now();
+        let scroll_position = self.anim_scroll_position.round() as usize;
 
-        let paragraph = Paragraph::new(lines.iter().map(OwnedLine::as_line).collect::<Vec<_>>())
-            .scroll((self.anim_scroll_position as u16, 0))
-            .wrap(ratatui::widgets::Wrap { trim: false });
+        let show_line_numbers = LINE_NUMBERS.load(Ordering::Relaxed);
+        let number_width = line_number_width(lines.len());
+        let paragraph = Paragraph::new(
+            lines
+                .iter()
+                .enumerate()
+                .map(|(idx, line)| {
+                    let mut line = line.as_line();
+                    if show_line_numbers {
+                        line.spans.insert(
+                            0,
+                            Span::styled(
+                                format!("{:>width$} ", idx + 1, width = number_width),
+                                Style::default().fg(theme().muted),
+                            ),
+                        );
+                    }
+                    line
+                })
+                .collect::<Vec<_>>(),
+        )
+        .scroll((scroll_position as u16, 0))
+        .wrap(ratatui::widgets::Wrap { trim: false });
 
         let mut scroll_state = ratatui::widgets::ScrollbarState::default()
-            .position(self.anim_scroll_position)
+            .position(scroll_position)
             .content_length(scroll_max);
 
         let file_scroll = Scrollbar::new(ratatui::widgets::ScrollbarOrientation::VerticalRight)
-            .style(Style::default().fg(ratatui::style::Color::DarkGray));
+            .style(Style::default().fg(theme().muted));
 
         if let Some(test_output) = &self.test_output {
             let vertical = ratatui::layout::Layout::vertical([
@@ -1304,7 +2612,7 @@ impl Widget for &mut ACIVizWidget {
                 .content_length(test_len.saturating_sub(test_area.height as usize));
 
             let test_scroll = Scrollbar::new(ratatui::widgets::ScrollbarOrientation::VerticalRight)
-                .style(Style::default().fg(ratatui::style::Color::DarkGray));
+                .style(Style::default().fg(theme().muted));
 
             test_paragraph.render(test_area, buf);
             StatefulWidget::render(test_scroll, test_area, buf, &mut test_scroll_state);
@@ -1324,7 +2632,11 @@ impl Widget for &mut ACIVizWidget {
                 .block(
                     Block::new()
                         .borders(Borders::TOP)
-                        .title("─ Command Output "),
+                        .title(if self.cancel_cmd.is_some() {
+                            "─ Command Output (Ctrl+K to cancel) "
+                        } else {
+                            "─ Command Output "
+                        }),
                 )
                 .scroll((test_len.saturating_sub(test_area.height as usize) as u16, 0))
                 .wrap(ratatui::widgets::Wrap { trim: false });
@@ -1338,10 +2650,23 @@ impl Widget for &mut ACIVizWidget {
             StatefulWidget::render(file_scroll, file_area, buf, &mut scroll_state);
         }
 
-        Paragraph::new(Line::styled(
-            format!(" Agent Status: {} ", &self.status),
-            Style::default().fg(ratatui::style::Color::Green),
-        ))
+        Paragraph::new(Line::from(vec![
+            Span::styled(
+                format!(" Agent Status: {} ", &self.status),
+                Style::default().fg(theme().success),
+            ),
+            match &self.saved_to {
+                Some(Ok(path)) => Span::styled(
+                    format!("(saved to {}) ", path.display()),
+                    Style::default().fg(theme().muted),
+                ),
+                Some(Err(e)) => Span::styled(
+                    format!("(failed to save: {}) ", e),
+                    Style::default().fg(theme().error),
+                ),
+                None => Span::raw(""),
+            },
+        ]))
         .block(Block::new().borders(Borders::TOP))
         .render(status_area, buf);
     }
@@ -1356,7 +2681,7 @@ enum AppState {
     ReviewDiff(DiffReviewWidget),
     // Sort of a hacky way to feed state from the event input loop back up
     ChangeSession(api::ChatSession),
-    ACI(ACIVizWidget),
+    ACI(Box<ACIVizWidget>),
     Exit,
 }
 
@@ -1369,17 +2694,22 @@ struct App {
 
     /// Current chatbox input
     input: tui_textarea::TextArea<'static>,
+    /// The input as last saved to the session's draft file
+    saved_draft: String,
+    last_draft_save: Instant,
 
     client: APIClient,
-    ws_stream: Option<
-        tokio_tungstenite::WebSocketStream<
-            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-        >,
-    >,
+    ws_url: String,
     project: api::Project,
     feature: api::Feature,
     session: api::ChatSession,
     state: Arc<Mutex<AppState>>,
+
+    /// Set when the chat needs to be redrawn
+    dirty: Arc<AtomicBool>,
+    /// Whether the agent's file view is drawn over the chat while it works.
+    /// It's still kept up to date when hidden, just not rendered.
+    show_aci: bool,
 }
 
 impl App {
@@ -1390,10 +2720,9 @@ impl App {
         session: &api::ChatSession,
         current_user: &api::User,
         sessions: Vec<api::ChatSession>,
-        ws_stream: tokio_tungstenite::WebSocketStream<
-            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-        >,
+        ws_url: &str,
         client: &APIClient,
+        options: &ChatOptions,
     ) -> Result<Self> {
         let chat_history: Vec<ChatMessage> = client
             .get(&format!(
@@ -1419,6 +2748,16 @@ impl App {
             .json()
             .await?;
 
+        // Not fatal, the header just won't show the model
+        let model = match client.get("/llm-configuration").send().await {
+            Ok(resp) if resp.status().is_success() => resp
+                .json::<api::LLMConfiguration>()
+                .await
+                .ok()
+                .and_then(|c| c.model),
+            _ => None,
+        };
+
         let mut x = Self {
             repo_path: repo_path.to_path_buf(),
             user: current_user.clone(),
@@ -1427,28 +2766,67 @@ impl App {
                 scroll_position: 0,
                 scroll_max: 0,
                 scroll_state: ratatui::widgets::ScrollbarState::default(),
+                area: ratatui::layout::Rect::default(),
+                selection: None,
+                copy_selection: false,
+                copied_selection: None,
                 code_block_hitboxes: vec![],
                 message_hitboxes: vec![],
+                wrap_code: true,
+                h_scroll_position: 0,
+                h_scroll_max: 0,
+                selected_code_block: None,
                 sessions,
                 session: session.clone(),
                 feature: feature.clone(),
                 project: project.clone(),
-                credit_remaining: Arc::new(Mutex::new(
-                    credits.plan_included - credits.plan_used + credits.purchased_remaining,
-                )),
+                credits: Arc::new(Mutex::new(Credits {
+                    remaining: credits.plan_included - credits.plan_used
+                        + credits.purchased_remaining,
+                    session_used: 0,
+                    generation_used: 0,
+                })),
+                model,
+                dry_run: options.dry_run,
+                connection_status: Arc::new(Mutex::new(None)),
             },
             input: tui_textarea::TextArea::default(),
+            saved_draft: String::new(),
+            last_draft_save: Instant::now(),
             client: client.clone(),
-            ws_stream: Some(ws_stream),
+            ws_url: ws_url.to_string(),
             project: project.clone(),
             feature: feature.clone(),
             session: session.clone(),
             state: Arc::new(Mutex::new(AppState::Chat)),
+            dirty: Arc::new(AtomicBool::new(true)),
+            show_aci: !options.no_aci
+                && bismuth_toml::parse_config(repo_path)
+                    .map_or(true, |config| config.chat.show_aci),
         };
         x.clear_input();
+        if let Some(draft) = load_draft(&draft_file(session.id)) {
+            x.input.insert_str(&draft);
+            x.saved_draft = draft;
+        }
         Ok(x)
     }
 
+    /// Save the current input to the session's draft file, if it changed.
+    /// An empty input removes the draft.
+    fn save_draft(&mut self) {
+        self.last_draft_save = Instant::now();
+        let draft = self.input.lines().join("\n");
+        if draft == self.saved_draft {
+            return;
+        }
+        let path = draft_file(self.session.id);
+        if let Err(e) = write_draft(&path, &draft) {
+            debug!("Failed to save draft to {}: {}", path.display(), e);
+        }
+        self.saved_draft = draft;
+    }
+
     fn clear_input(&mut self) {
         self.input = tui_textarea::TextArea::default();
         self.input.set_block(Block::bordered().title(" Message "));
@@ -1457,30 +2835,55 @@ impl App {
         self.input.set_cursor_line_style(Style::default());
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn read_loop(
         read: &mut SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
         write: &mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
         scrollback: Arc<Mutex<Vec<ChatMessage>>>,
-        credit_remaining: Arc<Mutex<i32>>,
+        credits: Arc<Mutex<Credits>>,
         repo_path: &Path,
+        dry_run: bool,
         state: Arc<Mutex<AppState>>,
+        dirty: Arc<AtomicBool>,
+        last_received: Arc<Mutex<Instant>>,
     ) -> Result<()> {
+        // The server drops the connection right after an Auth it doesn't accept.
+        // Only a drop shortly after connecting counts, so an idle connection being
+        // closed before its first message is still treated as transient.
+        let connected_at = Instant::now();
+        let mut received_any = false;
+        let auth_rejected =
+            |received_any: bool| !received_any && connected_at.elapsed() < CHAT_AUTH_REJECT_WINDOW;
         loop {
+            // Whatever the previous message changed is now ready to be drawn
+            dirty.store(true, Ordering::Relaxed);
             let message = match read.try_next().await {
                 Err(e) => {
                     return Err(e.into());
                 }
+                Ok(None) if auth_rejected(received_any) => {
+                    return Err(anyhow!(ChatAuthError));
+                }
                 Ok(None) => {
                     return Ok(());
                 }
                 Ok(Some(message)) => message,
             };
-            if let Message::Ping(_) = message {
+            *last_received.lock().unwrap() = Instant::now();
+            if let Message::Ping(_) | Message::Pong(_) = message {
+                received_any = true;
                 continue;
             }
-            if let Message::Close(_) = message {
+            if let Message::Close(frame) = message {
+                let policy_violation = frame.is_some_and(|f| {
+                    f.code == tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Policy
+                });
+                if policy_violation || auth_rejected(received_any) {
+                    return Err(anyhow!(ChatAuthError));
+                }
                 return Ok(());
             }
+            received_any = true;
             let message_txt = &message.into_text().unwrap();
 
             let data: api::ws::Message = match serde_json::from_str(message_txt) {
@@ -1529,26 +2932,39 @@ impl App {
                                 let mut scrollback = scrollback.lock().unwrap();
                                 let last = scrollback.last_mut().unwrap();
                                 *last = ChatMessage::new(ChatMessageUser::AI, &generated_text);
-                                last.finalized = true;
                             }
-                            if let Some(credits_used) = credits_used {
-                                let mut credit_remaining = credit_remaining.lock().unwrap();
-                                *credit_remaining -= credits_used as i32;
+                            {
+                                let mut credits = credits.lock().unwrap();
+                                if let Some(credits_used) = credits_used {
+                                    credits.remaining -= credits_used as i32;
+                                }
+                                credits.session_used +=
+                                    credits_used.unwrap_or(credits.generation_used);
+                                credits.generation_used = 0;
                             }
 
-                            revert(repo_path).unwrap();
-                            if let Some(diff) =
+                            let diff = if dry_run {
+                                preview_chat_message(repo_path, &output_modified_files).unwrap()
+                            } else {
+                                revert(repo_path).unwrap();
                                 process_chat_message(repo_path, &output_modified_files).unwrap()
-                            {
+                            };
+                            if let Some(diff) = diff {
                                 if !diff.is_empty() {
+                                    let mut widget =
+                                        DiffReviewWidget::new(diff, id, commit_message);
+                                    if dry_run {
+                                        widget.can_apply = false;
+                                        widget.dry_run = true;
+                                    }
                                     let mut state = state.lock().unwrap();
-                                    *state = AppState::ReviewDiff(DiffReviewWidget::new(
-                                        diff,
-                                        id,
-                                        commit_message,
-                                    ));
+                                    *state = AppState::ReviewDiff(widget);
                                 }
                             }
+                            // Only mark finalized once changes are ready for review
+                            if let Some(last) = scrollback.lock().unwrap().last_mut() {
+                                last.finalized = true;
+                            }
                         }
                     }
                 }
@@ -1564,51 +2980,70 @@ impl App {
                     }
                 }
                 api::ws::Message::RunCommand(cmd) => {
+                    // Commands could write to the working tree, so they aren't run at all in dry run mode
+                    if dry_run {
+                        let output = DRY_RUN_COMMAND_OUTPUT.to_string();
+                        {
+                            let mut state = state.lock().unwrap();
+                            if let AppState::ACI(ref mut widget) = &mut *state {
+                                widget.set_status(format!("Skipped command '{}'", cmd.command));
+                                widget.push_output(format!("$ {}\n{}", cmd.command, output));
+                            }
+                        }
+                        write
+                            .send(Message::Text(serde_json::to_string(
+                                &api::ws::Message::RunCommandResponse(RunCommandResponse {
+                                    exit_code: 1,
+                                    output,
+                                    modified_files: vec![],
+                                }),
+                            )?))
+                            .await?;
+                        continue;
+                    }
+
                     let repo_path = repo_path.to_path_buf();
-                    let timeout = Duration::from_secs(
-                        bismuth_toml::parse_config(&repo_path)
-                            .unwrap_or_default()
-                            .chat
-                            .command_timeout,
-                    );
+                    let config = bismuth_toml::parse_config(&repo_path).unwrap_or_default();
+                    let timeout = Duration::from_secs(config.chat.command_timeout);
+                    let output_limit = config.chat.command_output_limit;
+                    let (shell, shell_args) = config.chat.shell();
 
                     process_chat_message(&repo_path, &cmd.output_modified_files)?;
 
                     let write_ = write.clone();
                     let state_ = state.clone();
                     tokio::spawn(async move {
-                        let mut output = String::new();
+                        let mut output = CommandOutput::new(output_limit);
+                        let cancel = Arc::new(tokio::sync::Notify::new());
                         {
                             let mut state = state_.lock().unwrap();
                             if let AppState::ACI(ref mut widget) = &mut *state {
-                                widget.status = format!("Running command '{}'", cmd.command);
-                                widget.run_cmd_output = Some(output.clone());
+                                widget.set_status(format!("Running command '{}'", cmd.command));
+                                widget.run_cmd_output = Some(output.to_string());
+                                widget.cancel_cmd = Some(cancel.clone());
                                 widget.in_scroll = false;
                             }
                         }
                         let proc_future = async {
-                            let mut proc = if cfg!(not(target_os = "windows")) {
-                                tokio::process::Command::new("sh")
-                                    .arg("-c")
-                                    .arg(&cmd.command)
-                                    .stdin(std::process::Stdio::null())
-                                    .stdout(std::process::Stdio::piped())
-                                    .stderr(std::process::Stdio::piped())
-                                    .current_dir(&repo_path)
-                                    .env("TERM", "dumb")
-                                    .spawn()
-                                    .unwrap()
-                            } else {
-                                tokio::process::Command::new("cmd")
-                                    .arg("/C")
-                                    .arg(&cmd.command)
-                                    .stdin(std::process::Stdio::null())
-                                    .stdout(std::process::Stdio::piped())
-                                    .stderr(std::process::Stdio::piped())
-                                    .current_dir(&repo_path)
-                                    .env("TERM", "dumb")
-                                    .spawn()
-                                    .unwrap()
+                            let mut proc = match tokio::process::Command::new(&shell)
+                                .args(&shell_args)
+                                .arg(&cmd.command)
+                                .stdin(std::process::Stdio::null())
+                                .stdout(std::process::Stdio::piped())
+                                .stderr(std::process::Stdio::piped())
+                                .current_dir(&repo_path)
+                                .env("TERM", "dumb")
+                                .kill_on_drop(true)
+                                .spawn()
+                            {
+                                Ok(proc) => proc,
+                                Err(e) => {
+                                    output.push_line(&format!(
+                                        "Failed to run shell {}: {}",
+                                        shell, e
+                                    ));
+                                    return 127;
+                                }
                             };
 
                             let stdout = LinesStream::new(
@@ -1617,32 +3052,52 @@ impl App {
                             let stderr = LinesStream::new(
                                 tokio::io::BufReader::new(proc.stderr.take().unwrap()).lines(),
                             );
-                            let mut merged_stream = tokio_stream::StreamExt::merge(stdout, stderr);
-                            while let Some(line) = merged_stream.next().await {
-                                output += &line.unwrap();
-                                output += "\n";
-                                {
-                                    let mut state = state_.lock().unwrap();
-                                    if let AppState::ACI(ref mut widget) = &mut *state {
-                                        widget.run_cmd_output = Some(output.clone());
+                            let exit_code = {
+                                let run = async {
+                                    let mut merged_stream =
+                                        tokio_stream::StreamExt::merge(stdout, stderr);
+                                    while let Some(line) = merged_stream.next().await {
+                                        output.push_line(&line.unwrap());
+                                        {
+                                            let mut state = state_.lock().unwrap();
+                                            if let AppState::ACI(ref mut widget) = &mut *state {
+                                                widget.run_cmd_output = Some(output.to_string());
+                                            }
+                                        }
                                     }
+                                    proc.wait().await.unwrap().code().unwrap()
+                                };
+                                tokio::select! {
+                                    exit_code = run => Some(exit_code),
+                                    _ = cancel.notified() => None,
                                 }
-                            }
+                            };
 
-                            proc.wait().await.unwrap()
+                            match exit_code {
+                                Some(exit_code) => exit_code,
+                                None => {
+                                    let _ = proc.kill().await;
+                                    output.push_line("");
+                                    output.push_line("(Command cancelled by user)");
+                                    130
+                                }
+                            }
                         };
 
                         let exit_code = match tokio::time::timeout(timeout, proc_future).await {
-                            Ok(exit_status) => exit_status.code().unwrap(),
+                            Ok(exit_code) => exit_code,
                             Err(_) => {
-                                output += "\n(Command timed out)";
+                                output.push_line("");
+                                output.push_line("(Command timed out)");
                                 1
                             }
                         };
                         {
                             let mut state = state_.lock().unwrap();
                             if let AppState::ACI(ref mut widget) = &mut *state {
-                                widget.run_cmd_output = Some(output.clone());
+                                widget.run_cmd_output = Some(output.to_string());
+                                widget.cancel_cmd = None;
+                                widget.push_output(format!("$ {}\n{}", cmd.command, output));
                             }
                         }
                         let _ = write_
@@ -1650,7 +3105,7 @@ impl App {
                                 serde_json::to_string(&api::ws::Message::RunCommandResponse(
                                     RunCommandResponse {
                                         exit_code,
-                                        output,
+                                        output: output.to_string(),
                                         modified_files: command_modified_files(&repo_path).unwrap(),
                                     },
                                 ))
@@ -1667,21 +3122,18 @@ impl App {
                         scroll_position,
                     } = aci
                     {
-                        let current_idx = files.iter().position(|f| *f == active_file).unwrap();
+                        let scroll_speed = bismuth_toml::parse_config(repo_path)
+                            .unwrap_or_default()
+                            .chat
+                            .aci_scroll_speed;
                         let mut state = state.lock().unwrap();
-                        *state = AppState::ACI(ACIVizWidget {
+                        *state = AppState::ACI(Box::new(ACIVizWidget::new(
                             files,
-                            current_idx: Some(current_idx),
-                            contents: CodeBlock::new(Some(&active_file), None, &new_contents),
-                            in_scroll: true,
-                            anim_scroll_position: scroll_position,
-                            anim_scroll_time: Instant::now(),
-                            target_scroll_position: scroll_position,
-                            status: format!("Looking through {}", active_file),
-                            test_output: None,
-                            run_cmd_output: None,
-                            usage: 0,
-                        });
+                            &active_file,
+                            &new_contents,
+                            scroll_position,
+                            scroll_speed,
+                        )));
                     } else {
                         let mut state = state.lock().unwrap();
                         if let AppState::ACI(ref mut widget) = &mut *state {
@@ -1702,7 +3154,7 @@ impl App {
                                     widget.in_scroll = true;
                                     widget.target_scroll_position = scroll_position;
                                     widget.anim_scroll_time = Instant::now();
-                                    widget.status = status;
+                                    widget.set_status(status);
                                 }
                                 api::ws::ACIMessage::Switch {
                                     status,
@@ -1712,11 +3164,11 @@ impl App {
                                 } => {
                                     widget.contents =
                                         CodeBlock::new(Some(&active_file), None, &new_contents);
-                                    widget.anim_scroll_position = scroll_position;
+                                    widget.anim_scroll_position = scroll_position as f64;
                                     widget.target_scroll_position = scroll_position;
                                     widget.anim_scroll_time = Instant::now();
                                     widget.in_scroll = true;
-                                    widget.status = status;
+                                    widget.set_status(status);
                                     if let Some(current_idx) =
                                         widget.files.iter().position(|f| *f == active_file)
                                     {
@@ -1727,7 +3179,7 @@ impl App {
                                     }
                                 }
                                 api::ws::ACIMessage::Close { status } => {
-                                    widget.status = status;
+                                    widget.set_status(status);
                                     widget.files.remove(widget.current_idx.unwrap());
                                     widget.current_idx = None;
                                     widget.contents = CodeBlock::new(None, None, "");
@@ -1742,11 +3194,11 @@ impl App {
                                     widget.files = files;
                                     widget.contents =
                                         CodeBlock::new(Some(&active_file), None, &new_contents);
-                                    widget.anim_scroll_position = scroll_position;
+                                    widget.anim_scroll_position = scroll_position as f64;
                                     widget.target_scroll_position = scroll_position;
                                     widget.anim_scroll_time = Instant::now();
                                     widget.in_scroll = true;
-                                    widget.status = status;
+                                    widget.set_status(status);
                                     widget.current_idx = Some(
                                         widget
                                             .files
@@ -1768,20 +3220,22 @@ impl App {
                                     );
                                     widget.contents.diff_highlight_lines =
                                         Some((changed_range.0..changed_range.1).collect());
-                                    widget.anim_scroll_position = scroll_position.saturating_sub(5);
+                                    widget.anim_scroll_position =
+                                        scroll_position.saturating_sub(5) as f64;
                                     widget.target_scroll_position =
                                         scroll_position.saturating_sub(5);
-                                    widget.status = status;
+                                    widget.set_status(status);
                                 }
                                 api::ws::ACIMessage::Test {
                                     status,
                                     test_output,
                                 } => {
                                     widget.test_output = Some(test_output.replace("\t", "    "));
-                                    widget.status = status;
+                                    widget.push_output(format!("{}\n{}", status, test_output));
+                                    widget.set_status(status);
                                 }
                                 api::ws::ACIMessage::Status { status } => {
-                                    widget.status = status;
+                                    widget.set_status(status);
                                 }
                                 api::ws::ACIMessage::End => {
                                     // Fully reset terminal before going back to chat to clear any corruption from running commands
@@ -1798,34 +3252,15 @@ impl App {
                             api::ws::FileRPCResponse::List { files }
                         }
                         api::ws::FileRPCRequest::Read { path } => {
-                            let content = std::fs::read_to_string(repo_path.join(&path)).ok();
+                            let content = read_text_file(&repo_path.join(&path));
                             api::ws::FileRPCResponse::Read { content }
                         }
                         api::ws::FileRPCRequest::Search { query } => {
-                            let results = list_all_files(repo_path)
-                                .unwrap()
-                                .into_iter()
-                                .flat_map(|file| {
-                                    if let Ok(contents) =
-                                        std::fs::read_to_string(repo_path.join(&file))
-                                    {
-                                        contents
-                                            .lines()
-                                            .enumerate()
-                                            .filter_map(|(line, text)| {
-                                                if text.contains(&query) {
-                                                    Some((file.clone(), line + 1, text.to_string()))
-                                                } else {
-                                                    None
-                                                }
-                                            })
-                                            .collect::<Vec<_>>()
-                                    } else {
-                                        vec![]
-                                    }
-                                })
-                                .collect();
-                            api::ws::FileRPCResponse::Search { results }
+                            let (results, skipped_files) = search_files(repo_path, &query).unwrap();
+                            api::ws::FileRPCResponse::Search {
+                                results,
+                                skipped_files,
+                            }
                         }
                     };
                     write
@@ -1840,6 +3275,7 @@ impl App {
                     return Err(anyhow!(err));
                 }
                 api::ws::Message::Usage(usage) => {
+                    credits.lock().unwrap().generation_used = usage;
                     let mut state = state.lock().unwrap();
                     if let AppState::ACI(ref mut widget) = &mut *state {
                         widget.usage = usage;
@@ -1856,37 +3292,132 @@ impl App {
     ) -> Result<Option<api::ChatSession>> {
         terminal.clear()?; // needed to clear session selection screen
 
-        let (mut write_sink, mut read) = self.ws_stream.take().unwrap().split();
         let (dead_tx, mut dead_rx) = tokio::sync::oneshot::channel();
-
         let (write, mut write_source) = mpsc::channel(1);
-        tokio::spawn(async move {
-            while let Some(msg) = write_source.recv().await {
-                write_sink.send(msg).await.unwrap();
-            }
-            write_sink.close().await.unwrap();
-        });
 
+        let ws_url = self.ws_url.clone();
+        let auth = api::ws::AuthMessage {
+            feature_id: self.feature.id,
+            session_id: self.session.id,
+            token: self.client.token.clone(),
+        };
+        let connection_status = self.chat_history.connection_status.clone();
         let scrollback = self.chat_history.messages.clone();
-        let credits_remaining = self.chat_history.credit_remaining.clone();
+        let credits = self.chat_history.credits.clone();
         let repo_path = self.repo_path.clone();
+        let dry_run = self.chat_history.dry_run;
         let state = self.state.clone();
+        let dirty = self.dirty.clone();
         let write_ = write.clone();
-        tokio::spawn(async move {
-            let res = Self::read_loop(
-                &mut read,
-                &write_,
-                scrollback.clone(),
-                credits_remaining,
-                &repo_path,
-                state,
-            )
-            .await;
+        let ping_interval = Duration::from_secs(
+            bismuth_toml::parse_config(&self.repo_path)
+                .unwrap_or_default()
+                .chat
+                .ping_interval,
+        );
+        let _connection = AbortOnDrop(tokio::spawn(async move {
+            let mut connected = false;
+            let mut attempt = 0;
+            let res: Result<()> = loop {
+                let ws_stream = match connect_chat(&ws_url, &auth).await {
+                    Ok(ws_stream) => ws_stream,
+                    Err(e)
+                        if !connected
+                            || attempt >= CHAT_RECONNECT_ATTEMPTS
+                            || e.is::<ChatAuthError>() =>
+                    {
+                        break Err(if connected && !e.is::<ChatAuthError>() {
+                            CliError::NetworkError(format!("Lost connection to Bismuth: {}", e))
+                                .into()
+                        } else {
+                            e
+                        });
+                    }
+                    Err(e) => {
+                        debug!("Reconnect failed: {}", e);
+                        attempt += 1;
+                        *connection_status.lock().unwrap() = Some(format!(
+                            "Reconnecting... ({}/{})",
+                            attempt, CHAT_RECONNECT_ATTEMPTS
+                        ));
+                        dirty.store(true, Ordering::Relaxed);
+                        tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                        continue;
+                    }
+                };
+                connected = true;
+                attempt = 0;
+                *connection_status.lock().unwrap() = None;
+                dirty.store(true, Ordering::Relaxed);
+
+                let (mut write_sink, mut read) = ws_stream.split();
+                let forward = async {
+                    while let Some(msg) = write_source.recv().await {
+                        write_sink.send(msg).await?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                };
+                let last_received = Arc::new(Mutex::new(Instant::now()));
+                let keepalive = {
+                    let last_received = last_received.clone();
+                    let write_ = write_.clone();
+                    async move {
+                        if ping_interval.is_zero() {
+                            return std::future::pending().await;
+                        }
+                        loop {
+                            tokio::time::sleep(ping_interval).await;
+                            if last_received.lock().unwrap().elapsed() > ping_interval * 2 {
+                                return Err(anyhow!(tokio_tungstenite::tungstenite::Error::Io(
+                                    std::io::Error::new(
+                                        std::io::ErrorKind::TimedOut,
+                                        "chat connection stalled",
+                                    )
+                                )));
+                            }
+                            let _ = write_.send(Message::Ping(vec![])).await;
+                        }
+                    }
+                };
+                let res = tokio::select! {
+                    res = Self::read_loop(
+                        &mut read,
+                        &write_,
+                        scrollback.clone(),
+                        credits.clone(),
+                        &repo_path,
+                        dry_run,
+                        state.clone(),
+                        dirty.clone(),
+                        last_received,
+                    ) => res,
+                    res = forward => res,
+                    res = keepalive => res,
+                };
+                // Server closing the stream or a websocket error are transient, anything else is fatal
+                match res {
+                    Err(e)
+                        if e.downcast_ref::<tokio_tungstenite::tungstenite::Error>()
+                            .is_none() =>
+                    {
+                        break Err(e);
+                    }
+                    res => {
+                        debug!("Chat connection lost ({:?}), reconnecting", res);
+                        *connection_status.lock().unwrap() = Some("Reconnecting...".to_string());
+                        dirty.store(true, Ordering::Relaxed);
+                    }
+                }
+            };
             let _ = dead_tx.send(res);
-        });
+        }));
 
+        self.dirty.store(true, Ordering::Relaxed);
         let mut last_draw = Instant::now();
         loop {
+            if self.last_draft_save.elapsed() > DRAFT_SAVE_INTERVAL {
+                self.save_draft();
+            }
             let state = { self.state.lock().unwrap().clone() };
             if let AppState::Exit = state {
                 return Ok(None);
@@ -1901,10 +3432,23 @@ impl App {
                 terminal.clear()?;
                 let mut state = self.state.lock().unwrap();
                 *state = AppState::Chat;
+                self.dirty.store(true, Ordering::Relaxed);
                 continue;
             }
 
-            if last_draw.elapsed() > Duration::from_millis(40) {
+            // Only redraw when something changed, except for animations (thinking spinner, ACI scrolling)
+            // which just need a slow tick.
+            let frame_interval = match &state {
+                AppState::ACI(widget) if self.show_aci && widget.is_scrolling() => {
+                    Some(ACI_FRAME_INTERVAL)
+                }
+                AppState::ACI(_) if self.show_aci => Some(Duration::from_millis(250)),
+                _ if self.chat_history.is_thinking() => Some(Duration::from_millis(250)),
+                _ => None,
+            };
+            if self.dirty.swap(false, Ordering::Relaxed)
+                || frame_interval.is_some_and(|interval| last_draw.elapsed() > interval)
+            {
                 last_draw = Instant::now();
                 terminal.draw(|frame| {
                     ui(
@@ -1912,8 +3456,14 @@ impl App {
                         self.state.clone(),
                         &mut self.chat_history,
                         &self.input,
+                        self.show_aci,
                     )
                 })?;
+                if let Some(text) = self.chat_history.copied_selection.take() {
+                    if let Ok(mut clipboard_ctx) = copypasta::ClipboardContext::new() {
+                        let _ = clipboard_ctx.set_contents(text);
+                    }
+                }
             }
 
             if !tokio::task::spawn_blocking(move || event::poll(Duration::from_millis(40)))
@@ -1921,6 +3471,25 @@ impl App {
             {
                 continue;
             }
+            self.dirty.store(true, Ordering::Relaxed);
+
+            let event = event::read()?;
+            if let Event::Resize(_, _) = event {
+                // Re-layout at the new size straight away, whatever state we're in.
+                // Cached line counts are keyed on the width, so rewrapping happens on this redraw.
+                continue;
+            }
+            let size = terminal.size()?;
+            if terminal_too_small(Rect::new(0, 0, size.width, size.height)).is_some() {
+                // Whatever's behind the message can't be seen, so only allow getting out of it
+                let can_act = matches!(event, Event::Key(key)
+                    if key.code == KeyCode::Esc
+                        || (key.modifiers.contains(event::KeyModifiers::CONTROL)
+                            && key.code == KeyCode::Char('c')));
+                if !can_act {
+                    continue;
+                }
+            }
 
             match state {
                 AppState::Exit => {
@@ -1929,7 +3498,7 @@ impl App {
                 AppState::ChangeSession(new_session) => {
                     return Ok(Some(new_session));
                 }
-                AppState::ReviewDiff(diff) => match event::read()? {
+                AppState::ReviewDiff(diff) => match event {
                     Event::Key(key) if key.kind == event::KeyEventKind::Press => match key.code {
                         KeyCode::Char('y') if diff.can_apply => {
                             commit(&self.repo_path, diff.commit_message.as_deref())?;
@@ -1975,6 +3544,9 @@ impl App {
                             let mut state = self.state.lock().unwrap();
                             *state = AppState::Chat;
                         }
+                        KeyCode::Char('l') => {
+                            LINE_NUMBERS.fetch_xor(true, Ordering::Relaxed);
+                        }
                         KeyCode::Esc if !diff.can_apply => {
                             let mut state = self.state.lock().unwrap();
                             *state = AppState::Chat;
@@ -2060,12 +3632,12 @@ impl App {
                     _ => {}
                 },
                 AppState::Popup(_, _) => {
-                    if let Event::Key(_) = event::read()? {
+                    if let Event::Key(_) = event {
                         let mut state = self.state.lock().unwrap();
                         *state = AppState::Chat;
                     }
                 }
-                AppState::SelectSession(widget) => match event::read()? {
+                AppState::SelectSession(widget) => match event {
                     Event::Key(key) if key.kind == event::KeyEventKind::Press => match key.code {
                         KeyCode::Up => {
                             let mut state = self.state.lock().unwrap();
@@ -2097,7 +3669,70 @@ impl App {
                     },
                     _ => {}
                 },
-                AppState::Chat => {
+                AppState::ACI(_) if self.show_aci => match event {
+                    Event::Key(key) if key.kind == event::KeyEventKind::Press => match key.code {
+                        KeyCode::Char('c')
+                            if key.modifiers.contains(event::KeyModifiers::CONTROL) =>
+                        {
+                            write
+                                .send(Message::Text(serde_json::to_string(
+                                    &api::ws::Message::KillGeneration,
+                                )?))
+                                .await?;
+                            let mut state = self.state.lock().unwrap();
+                            *state = AppState::Chat;
+                        }
+                        KeyCode::Char('k')
+                            if key.modifiers.contains(event::KeyModifiers::CONTROL) =>
+                        {
+                            let state = self.state.lock().unwrap();
+                            if let AppState::ACI(widget) = &*state {
+                                if let Some(cancel) = &widget.cancel_cmd {
+                                    cancel.notify_one();
+                                }
+                            }
+                        }
+                        KeyCode::Char('l') => {
+                            LINE_NUMBERS.fetch_xor(true, Ordering::Relaxed);
+                        }
+                        KeyCode::Char('h') => {
+                            let mut state = self.state.lock().unwrap();
+                            if let AppState::ACI(ref mut widget) = &mut *state {
+                                widget.show_status_log = !widget.show_status_log;
+                            }
+                        }
+                        KeyCode::Char('s') => {
+                            let saved_to = {
+                                let mut state = self.state.lock().unwrap();
+                                if let AppState::ACI(ref mut widget) = &mut *state {
+                                    widget.saved_to = Some(
+                                        widget.save(&agent_views_dir()).map_err(|e| e.to_string()),
+                                    );
+                                    widget.saved_to.clone()
+                                } else {
+                                    None
+                                }
+                            };
+                            // The agent's view goes away when it's done, so keep a note of where it went.
+                            // Before the last message, which is the reply still being generated.
+                            if let Some(Ok(path)) = saved_to {
+                                let mut messages = self.chat_history.messages.lock().unwrap();
+                                let idx = messages.len().saturating_sub(1);
+                                messages.insert(
+                                    idx,
+                                    ChatMessage::new(
+                                        ChatMessageUser::AI,
+                                        &format!("Saved the agent's view to `{}`", path.display()),
+                                    ),
+                                );
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                },
+                // With its view hidden the agent works in the background, behind the chat
+                AppState::Chat | AppState::ACI(_) => {
                     let last_generation_done = self
                         .chat_history
                         .messages
@@ -2106,7 +3741,7 @@ impl App {
                         .last()
                         .map_or(true, |msg| msg.finalized);
                     if !last_generation_done {
-                        if let Event::Key(key) = event::read()? {
+                        if let Event::Key(key) = event {
                             if key.kind == event::KeyEventKind::Press
                                 && key.modifiers.contains(event::KeyModifiers::CONTROL)
                                 && key.code == KeyCode::Char('c')
@@ -2116,11 +3751,31 @@ impl App {
                                         &api::ws::Message::KillGeneration,
                                     )?))
                                     .await?;
+                                let mut state = self.state.lock().unwrap();
+                                if let AppState::ACI(_) = &*state {
+                                    *state = AppState::Chat;
+                                }
                             }
                         }
                     } else {
-                        match event::read()? {
+                        match event {
                             Event::Mouse(mouse) => match mouse.kind {
+                                event::MouseEventKind::ScrollUp
+                                    if mouse.modifiers.contains(event::KeyModifiers::SHIFT) =>
+                                {
+                                    self.chat_history.scroll_horizontal(-1);
+                                }
+                                event::MouseEventKind::ScrollDown
+                                    if mouse.modifiers.contains(event::KeyModifiers::SHIFT) =>
+                                {
+                                    self.chat_history.scroll_horizontal(1);
+                                }
+                                event::MouseEventKind::ScrollLeft => {
+                                    self.chat_history.scroll_horizontal(-1);
+                                }
+                                event::MouseEventKind::ScrollRight => {
+                                    self.chat_history.scroll_horizontal(1);
+                                }
                                 event::MouseEventKind::ScrollUp => {
                                     self.chat_history.scroll_position =
                                         self.chat_history.scroll_position.saturating_sub(1);
@@ -2132,9 +3787,21 @@ impl App {
                                         .saturating_add(1)
                                         .clamp(0, self.chat_history.scroll_max);
                                 }
+                                event::MouseEventKind::Down(MouseButton::Left) => {
+                                    self.chat_history.start_selection(mouse.column, mouse.row);
+                                }
+                                event::MouseEventKind::Drag(MouseButton::Left) => {
+                                    self.chat_history.extend_selection(mouse.column, mouse.row);
+                                }
                                 event::MouseEventKind::Up(MouseButton::Left) => {
+                                    if self.chat_history.finish_selection() {
+                                        continue;
+                                    }
+                                    let clicked_line =
+                                        self.chat_history.line_at(mouse.column, mouse.row);
                                     let mut messages = self.chat_history.messages.lock().unwrap();
 
+                                    let mut copied_code_block = false;
                                     if let Ok(mut clipboard_ctx) =
                                         copypasta::ClipboardContext::new()
                                     {
@@ -2144,18 +3811,50 @@ impl App {
                                             .iter()
                                             .zip(messages.iter())
                                         {
-                                            // -1 for the border of chat history
-                                            if (*start as isize
-                                                - self.chat_history.scroll_position as isize)
-                                                == (mouse.row as isize) - 1
-                                                && (mouse.column as usize == 1
-                                                    || mouse.column as usize == 2)
-                                            {
+                                            if self.chat_history.is_copy_icon_click(
+                                                mouse.column,
+                                                mouse.row,
+                                                *start,
+                                            ) {
                                                 clipboard_ctx
                                                     .set_contents(block.raw.clone())
                                                     .unwrap();
                                             }
                                         }
+
+                                        let code_blocks = messages
+                                            .iter()
+                                            .flat_map(|msg| msg.blocks.iter())
+                                            .filter_map(|block| match block {
+                                                MessageBlock::Code(code) => Some(code),
+                                                _ => None,
+                                            });
+                                        for ((start, _end), code) in self
+                                            .chat_history
+                                            .code_block_hitboxes
+                                            .iter()
+                                            .zip(code_blocks)
+                                        {
+                                            if self.chat_history.is_copy_icon_click(
+                                                mouse.column,
+                                                mouse.row,
+                                                *start,
+                                            ) {
+                                                clipboard_ctx
+                                                    .set_contents(code.raw_code.clone())
+                                                    .unwrap();
+                                                copied_code_block = true;
+                                            }
+                                        }
+                                    }
+
+                                    if copied_code_block {
+                                        let mut state = self.state.lock().unwrap();
+                                        *state = AppState::Popup(
+                                            "Copied".to_string(),
+                                            "Code block copied to clipboard".to_string(),
+                                        );
+                                        continue;
                                     }
 
                                     let mut hitboxes_iter =
@@ -2164,15 +3863,9 @@ impl App {
                                         for block in &mut msg.blocks {
                                             if let MessageBlock::Code(code) = block {
                                                 let (start, end) = hitboxes_iter.next().unwrap();
-                                                // -1 for the border of chat history
-                                                if (*start as isize
-                                                    - self.chat_history.scroll_position as isize)
-                                                    < (mouse.row as isize)
-                                                    && (*end as isize
-                                                        - self.chat_history.scroll_position
-                                                            as isize)
-                                                        > (mouse.row as isize) - 1
-                                                {
+                                                if clicked_line.is_some_and(|line| {
+                                                    (*start..*end).contains(&line)
+                                                }) {
                                                     code.folded = !code.folded;
                                                     msg.block_line_cache.1.clear();
                                                 }
@@ -2191,24 +3884,40 @@ impl App {
                                         *state = AppState::Exit;
                                     }
                                     KeyCode::Char('n')
-                                        if key.modifiers.contains(event::KeyModifiers::CONTROL) =>
+                                        if key.modifiers.contains(event::KeyModifiers::CONTROL)
+                                            && self.input.is_empty() =>
                                     {
-                                        let session = self
-                                            .client
-                                            .post(&format!(
-                                                "/projects/{}/features/{}/chat/sessions",
-                                                self.project.id, self.feature.id
-                                            ))
-                                            .json(&json!({ "name": None::<&str> }))
-                                            .send()
-                                            .await?
-                                            .error_body_for_status()
-                                            .await?
-                                            .json()
-                                            .await?;
+                                        // Prompt for the name in the message box,
+                                        // Enter creates the session (unnamed if left empty)
+                                        self.input.insert_str("/new-session ");
+                                    }
+                                    KeyCode::Char('?') if self.input.is_empty() => {
                                         let mut state = self.state.lock().unwrap();
-
-                                        *state = AppState::ChangeSession(session);
+                                        *state = AppState::Popup("Help".to_string(), help_text());
+                                    }
+                                    KeyCode::Tab if self.input.is_empty() => {
+                                        self.chat_history.select_code_block(1);
+                                    }
+                                    KeyCode::BackTab if self.input.is_empty() => {
+                                        self.chat_history.select_code_block(-1);
+                                    }
+                                    KeyCode::Esc => {
+                                        self.chat_history.selected_code_block = None;
+                                    }
+                                    KeyCode::Enter | KeyCode::Char(' ')
+                                        if self.input.is_empty()
+                                            && self.chat_history.toggle_selected_code_block() => {}
+                                    KeyCode::Left
+                                        if self.input.is_empty()
+                                            && !self.chat_history.wrap_code =>
+                                    {
+                                        self.chat_history.scroll_horizontal(-4);
+                                    }
+                                    KeyCode::Right
+                                        if self.input.is_empty()
+                                            && !self.chat_history.wrap_code =>
+                                    {
+                                        self.chat_history.scroll_horizontal(4);
                                     }
                                     KeyCode::Enter => {
                                         // ALT+enter for manual newlines
@@ -2234,23 +3943,6 @@ impl App {
                         }
                     }
                 }
-                AppState::ACI(_) => match event::read()? {
-                    Event::Key(key) if key.kind == event::KeyEventKind::Press => match key.code {
-                        KeyCode::Char('c')
-                            if key.modifiers.contains(event::KeyModifiers::CONTROL) =>
-                        {
-                            write
-                                .send(Message::Text(serde_json::to_string(
-                                    &api::ws::Message::KillGeneration,
-                                )?))
-                                .await?;
-                            let mut state = self.state.lock().unwrap();
-                            *state = AppState::Chat;
-                        }
-                        _ => {}
-                    },
-                    _ => {}
-                },
                 // Handled before event polling
                 AppState::TerminalReset => {}
             }
@@ -2266,6 +3958,7 @@ impl App {
         }
         let input = self.input.lines().to_vec().join("\n");
         if input.starts_with('/') {
+            let mut retry = false;
             {
                 let mut state = self.state.lock().unwrap();
                 match input.split(' ').next().unwrap() {
@@ -2273,26 +3966,21 @@ impl App {
                         *state = AppState::Exit;
                     }
                     "/help" => {
-                        *state = AppState::Popup(
-                            "Help".to_string(),
-                            r#"/exit, /quit, or Ctrl+C: Exit the chat
-/docs: Open the Bismuth documentation
-/new-session [NAME]: Start a new session
-/rename-session <NAME>: Rename the current session
-/session [NAME]: Switch to a different session
-/feedback <DESCRIPTION>: Send us feedback
-/diff: Review the last diff Bismuth made
-/refill: Open billing page to refill credits
-/quit or Ctrl+C: Exit the chat
-/help: Show this help"#
-                                .to_string(),
-                        );
+                        *state = AppState::Popup("Help".to_string(), help_text());
                     }
                     "/docs" => {
-                        open::that_detached("https://app.bismuth.cloud/docs")?;
+                        let url = "https://app.bismuth.cloud/docs";
+                        if can_launch_browser() {
+                            open::that_detached(url)?;
+                        } else {
+                            *state = AppState::Popup("Docs".to_string(), url.to_string());
+                        }
                     }
                     "/new-session" => {
-                        let session_name = input.split_once(' ').map(|(_, msg)| msg);
+                        let session_name = input
+                            .split_once(' ')
+                            .map(|(_, msg)| msg.trim())
+                            .filter(|name| !name.is_empty());
                         let session = self
                             .client
                             .post(&format!(
@@ -2401,6 +4089,9 @@ impl App {
                             )?;
                         }
                     }
+                    "/wrap" => {
+                        self.chat_history.toggle_wrap_code();
+                    }
                     "/diff" => {
                         let repo = git2::Repository::open(&self.repo_path)?;
                         let last = repo.revparse_single("HEAD")?;
@@ -2439,7 +4130,14 @@ impl App {
                             .await?
                             .text()
                             .await?;
-                        open::that_detached(url)?;
+                        if can_launch_browser() {
+                            open::that_detached(url)?;
+                        } else {
+                            *state = AppState::Popup("Refill credits".to_string(), url);
+                        }
+                    }
+                    "/retry" => {
+                        retry = true;
                     }
                     _ => {
                         *state = AppState::Popup(
@@ -2450,15 +4148,65 @@ impl App {
                 }
             }
             self.clear_input();
+            self.save_draft();
+            if retry {
+                self.retry_last_message(write).await?;
+            }
             return Ok(());
         }
 
         {
             let mut scrollback = self.chat_history.messages.lock().unwrap();
-
             let mut msg = ChatMessage::new(ChatMessageUser::User(self.user.name.clone()), &input);
             msg.finalized = true;
             scrollback.push(msg);
+        }
+        self.send_chat_message(&input, write).await?;
+
+        self.clear_input();
+        self.save_draft();
+
+        Ok(())
+    }
+
+    /// Resend the last user message, replacing the AI response to it
+    async fn retry_last_message(
+        &mut self,
+        write: &mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
+    ) -> Result<()> {
+        let input = {
+            let mut scrollback = self.chat_history.messages.lock().unwrap();
+            if scrollback.last().is_some_and(|msg| !msg.finalized) {
+                *self.state.lock().unwrap() = AppState::Popup(
+                    "Error".to_string(),
+                    "Wait for the current response to finish before retrying".to_string(),
+                );
+                return Ok(());
+            }
+            let Some(last_user_idx) = scrollback
+                .iter()
+                .rposition(|msg| matches!(msg.user, ChatMessageUser::User(_)))
+            else {
+                *self.state.lock().unwrap() = AppState::Popup(
+                    "Error".to_string(),
+                    "There is no previous message to retry".to_string(),
+                );
+                return Ok(());
+            };
+            scrollback.truncate(last_user_idx + 1);
+            scrollback[last_user_idx].raw.clone()
+        };
+        self.send_chat_message(&input, write).await
+    }
+
+    /// Send a message to the agent, adding a placeholder for the response to the scrollback
+    async fn send_chat_message(
+        &mut self,
+        input: &str,
+        write: &mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
+    ) -> Result<()> {
+        {
+            let mut scrollback = self.chat_history.messages.lock().unwrap();
 
             let mut ai_msg = ChatMessage::new(ChatMessageUser::AI, "");
             ai_msg.blocks.clear();
@@ -2467,41 +4215,140 @@ impl App {
                 .push(MessageBlock::Thinking("Planning".to_string()));
             scrollback.push(ai_msg);
 
-            let modified_files = if self.project.has_pushed {
-                list_changed_files(&self.repo_path)?
-                    .into_iter()
-                    .map(|path| {
-                        let content = std::fs::read_to_string(self.repo_path.join(&path))
-                            .unwrap_or("".to_string());
-                        api::ws::ChatModifiedFile {
-                            name: path.file_name().unwrap().to_str().unwrap().to_string(),
-                            project_path: path.to_str().unwrap().to_string(),
-                            content,
-                            deleted: Some(!self.repo_path.join(&path).exists()),
-                        }
-                    })
-                    .collect()
-            } else {
-                vec![]
-            };
-
             write
-                .send(Message::Text(serde_json::to_string(
-                    &api::ws::Message::Chat(api::ws::ChatMessage {
-                        message: input.clone(),
-                        modified_files,
-                        request_type_analysis: false,
-                    }),
-                )?))
+                .send(chat_request(&self.repo_path, &self.project, input)?)
                 .await?;
         }
 
-        self.clear_input();
-
         Ok(())
     }
 }
 
+/// The websocket message sending `input` to the agent, along with any local changes.
+fn chat_request(repo_path: &Path, project: &api::Project, input: &str) -> Result<Message> {
+    let modified_files = if project.has_pushed {
+        list_changed_files(repo_path, project)?
+            .into_iter()
+            .filter_map(|path| modified_file(repo_path, &path))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    Ok(Message::Text(serde_json::to_string(
+        &api::ws::Message::Chat(api::ws::ChatMessage {
+            message: input.to_string(),
+            modified_files,
+            request_type_analysis: false,
+        }),
+    )?))
+}
+
+/// Send a single message and print the response, without the interactive UI.
+async fn one_shot_chat(
+    repo_path: &Path,
+    project: &api::Project,
+    feature: &api::Feature,
+    session: &api::ChatSession,
+    client: &APIClient,
+    ws_url: &str,
+    options: &ChatOptions,
+) -> Result<()> {
+    let message = options.message.as_deref().unwrap_or_default();
+    let auth = api::ws::AuthMessage {
+        feature_id: feature.id,
+        session_id: session.id,
+        token: client.token.clone(),
+    };
+    let (mut write_sink, mut read) = connect_chat(ws_url, &auth).await?.split();
+    let (write, mut write_source) = mpsc::channel(1);
+    let _writer = AbortOnDrop(tokio::spawn(async move {
+        while let Some(msg) = write_source.recv().await {
+            if write_sink.send(msg).await.is_err() {
+                break;
+            }
+        }
+    }));
+
+    let scrollback = Arc::new(Mutex::new(vec![ChatMessage::new(ChatMessageUser::AI, "")]));
+    let state = Arc::new(Mutex::new(AppState::Chat));
+    let (dead_tx, mut dead_rx) = tokio::sync::oneshot::channel();
+    let _reader = {
+        let repo_path = repo_path.to_path_buf();
+        let write = write.clone();
+        let scrollback = scrollback.clone();
+        let state = state.clone();
+        let dry_run = options.dry_run;
+        AbortOnDrop(tokio::spawn(async move {
+            let res = App::read_loop(
+                &mut read,
+                &write,
+                scrollback,
+                Arc::new(Mutex::new(Credits::default())),
+                &repo_path,
+                dry_run,
+                state,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(Mutex::new(Instant::now())),
+            )
+            .await;
+            let _ = dead_tx.send(res);
+        }))
+    };
+
+    write
+        .send(chat_request(repo_path, project, message)?)
+        .await?;
+
+    loop {
+        if scrollback
+            .lock()
+            .unwrap()
+            .last()
+            .is_some_and(|m| m.finalized)
+        {
+            break;
+        }
+        if let Ok(res) = dead_rx.try_recv() {
+            res?;
+            return Err(anyhow!(
+                "Chat connection closed before a response was received"
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    println!("{}", scrollback.lock().unwrap().last().unwrap().raw);
+
+    let state = state.lock().unwrap().clone();
+    if let AppState::ReviewDiff(diff) = state {
+        println!("{}", diff.text());
+        if !diff.can_apply {
+            return Ok(());
+        }
+        if options.auto_apply {
+            commit(repo_path, diff.commit_message.as_deref())?;
+            eprintln!("Changes committed");
+        } else {
+            revert(repo_path)?;
+            eprintln!("Changes discarded (use --auto-apply to commit them)");
+        }
+        let _ = client
+            .post(&format!(
+                "/projects/{}/features/{}/chat/accepted",
+                project.id, feature.id,
+            ))
+            .json(&api::GenerationAcceptedRequest {
+                message_id: diff.msg_id,
+                accepted: options.auto_apply,
+            })
+            .send()
+            .await;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn start_chat(
     current_user: &api::User,
     project: &api::Project,
@@ -2510,11 +4357,12 @@ pub async fn start_chat(
     session: &api::ChatSession,
     repo_path: &Path,
     client: &APIClient,
+    options: &ChatOptions,
 ) -> Result<()> {
     let repo_path = repo_path.to_path_buf();
 
     if project.has_pushed
-        && list_changed_files(&repo_path)?
+        && list_changed_files(&repo_path, project)?
             .into_iter()
             .map(|path| {
                 std::fs::metadata(&repo_path.join(&path))
@@ -2525,7 +4373,37 @@ pub async fn start_chat(
             > 8 * 1024 * 1024
     {
         return Err(anyhow!(
-            "There are too many unpushed changes. Please commit, `git push bismuth`, and try again."
+            "There are too many unpushed changes. Please commit, run `bismuth project upload {}`, and try again.",
+            project.name
+        ));
+    }
+
+    let config = bismuth_toml::parse_config(&repo_path)?;
+    config.chat.check_shell()?;
+
+    let _ = THEME.set(Theme::new(options.theme));
+
+    // An explicit --websocket-url wins over the repository's bismuth.toml
+    let ws_url = match crate::GLOBAL_OPTS
+        .get()
+        .and_then(|opts| opts.websocket_url.clone())
+        .or(config.backend.websocket_url)
+    {
+        Some(url) => url.to_string(),
+        None => websocket_url(&client.base_url).to_string(),
+    };
+
+    if options.message.is_some() {
+        let _marker = OpenSessionMarker::new(session);
+        return one_shot_chat(
+            &repo_path, project, feature, session, client, &ws_url, options,
+        )
+        .await;
+    }
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(anyhow!(
+            "Interactive chat requires a terminal. Use `bismuth chat --message <prompt>` to send a single message non-interactively."
         ));
     }
 
@@ -2533,21 +4411,7 @@ pub async fn start_chat(
     let mut terminal = terminal::init()?;
 
     let status = loop {
-        let url = websocket_url(&client.base_url);
-        let (mut ws_stream, _) = connect_async(url).await.expect("Failed to connect");
-
-        ws_stream
-            .send(Message::Text(serde_json::to_string(
-                &api::ws::Message::Auth(api::ws::AuthMessage {
-                    feature_id: feature.id,
-                    session_id: session.id,
-                    token: client.token.clone(),
-                }),
-            )?))
-            .await?;
-
-        debug!("Connected to chat");
-
+        let _marker = OpenSessionMarker::new(&session);
         let mut app = App::new(
             &repo_path,
             project,
@@ -2555,12 +4419,14 @@ pub async fn start_chat(
             &session,
             current_user,
             sessions.clone(),
-            ws_stream,
+            &ws_url,
             client,
+            options,
         )
         .await?;
 
         let status = app.run(&mut terminal).await;
+        app.save_draft();
         match status {
             Ok(Some(new_session)) => {
                 session = new_session;
@@ -2577,14 +4443,305 @@ pub async fn start_chat(
 
     terminal::restore();
 
+    if status.is_ok() {
+        resolve_temp_commit(&repo_path)?;
+    }
+
     status
 }
 
+/// How often the chat input is saved as a draft, so it survives crashes.
+const DRAFT_SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where unsent input for a session is kept between chats.
+fn draft_file(session_id: u64) -> PathBuf {
+    crate::GLOBAL_OPTS
+        .get()
+        .unwrap()
+        .config_file
+        .with_file_name(format!("bismuth-chat-draft-{}.txt", session_id))
+}
+
+fn load_draft(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok()
+}
+
+/// Write a draft, or remove it if it's empty.
+fn write_draft(path: &Path, draft: &str) -> std::io::Result<()> {
+    if !draft.is_empty() {
+        return std::fs::write(path, draft);
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// Remove the draft of a deleted session.
+pub fn remove_draft(session_id: u64) {
+    let path = draft_file(session_id);
+    if let Err(e) = write_draft(&path, "") {
+        debug!("Failed to remove draft {}: {}", path.display(), e);
+    }
+}
+
+/// Where the agent's view is saved to with `s`, one subdirectory per save.
+fn agent_views_dir() -> PathBuf {
+    crate::GLOBAL_OPTS
+        .get()
+        .unwrap()
+        .config_file
+        .with_file_name("bismuth-agent-views")
+}
+
+/// Marks a session as open in this chat, so pruning sessions leaves it alone.
+/// The marker is removed when dropped.
+struct OpenSessionMarker(PathBuf);
+
+impl OpenSessionMarker {
+    fn new(session: &api::ChatSession) -> Self {
+        let path = open_session_marker(session.id);
+        let _ = std::fs::write(&path, std::process::id().to_string());
+        OpenSessionMarker(path)
+    }
+}
+
+impl Drop for OpenSessionMarker {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn open_session_marker(session_id: u64) -> PathBuf {
+    crate::GLOBAL_OPTS
+        .get()
+        .unwrap()
+        .config_file
+        .with_file_name(format!("bismuth-chat-open-{}", session_id))
+}
+
+/// Whether the session is open in a running chat.
+pub fn session_is_open(session_id: u64) -> bool {
+    marker_is_live(&open_session_marker(session_id))
+}
+
+/// Whether the process that wrote a session marker is still running.
+/// Markers left behind by a chat that was killed or crashed are stale.
+fn marker_is_live(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok())
+        .is_some_and(process_is_running)
+}
+
+#[cfg(not(windows))]
+fn process_is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists. EPERM means it does, but belongs to another user.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn process_is_running(_pid: u32) -> bool {
+    // No cheap way to check, so err on the side of leaving the session alone
+    true
+}
+
+/// The chat server rejected our token. Reconnecting won't help.
+#[derive(Debug)]
+pub struct ChatAuthError;
+
+impl std::fmt::Display for ChatAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Chat authentication failed: your session token is invalid or expired. Run `bismuth login` and try again."
+        )
+    }
+}
+
+impl std::error::Error for ChatAuthError {}
+
+/// How many times to try re-establishing a dropped chat connection before giving up.
+const CHAT_RECONNECT_ATTEMPTS: u32 = 5;
+
+/// How soon after connecting a dropped connection is taken as the server rejecting our Auth.
+const CHAT_AUTH_REJECT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Aborts the task when dropped, so background connections don't outlive the chat session.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The proxy to connect to `url` through: --proxy, or the standard environment variables.
+fn websocket_proxy(url: &Url) -> Option<Url> {
+    if let Some(proxy) = crate::GLOBAL_OPTS.get().and_then(|opts| opts.proxy.clone()) {
+        return Some(proxy);
+    }
+    let env = |name: &str| {
+        std::env::var(name)
+            .or_else(|_| std::env::var(name.to_lowercase()))
+            .ok()
+            .filter(|v| !v.is_empty())
+    };
+    let host = url.host_str()?;
+    if let Some(no_proxy) = env("NO_PROXY") {
+        let excluded = no_proxy.split(',').map(str::trim).any(|pattern| {
+            let pattern = pattern.trim_start_matches('.');
+            pattern == "*" || host == pattern || host.ends_with(&format!(".{}", pattern))
+        });
+        if excluded {
+            return None;
+        }
+    }
+    let proxy = if url.scheme() == "wss" {
+        env("HTTPS_PROXY")
+    } else {
+        env("HTTP_PROXY")
+    }
+    .or_else(|| env("ALL_PROXY"))?;
+    // Like curl, allow the scheme to be omitted
+    Url::parse(&proxy)
+        .or_else(|_| Url::parse(&format!("http://{}", proxy)))
+        .ok()
+}
+
+/// Open a tunnel to `url`'s host through an HTTP proxy with CONNECT.
+async fn proxy_tunnel(proxy: &Url, url: &Url) -> Result<TcpStream> {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    if proxy.scheme() != "http" {
+        return Err(anyhow!(
+            "Unsupported proxy {} for chat (only http:// proxies are supported)",
+            proxy
+        ));
+    }
+    let target = format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or(443)
+    );
+    let mut stream = TcpStream::connect((
+        proxy.host_str().unwrap_or_default(),
+        proxy.port_or_known_default().unwrap_or(80),
+    ))
+    .await
+    .map_err(|e| anyhow!("Failed to connect to proxy {}: {}", proxy, e))?;
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if !proxy.username().is_empty() {
+        use base64::Engine as _;
+        let credentials = format!(
+            "{}:{}",
+            proxy.username(),
+            proxy.password().unwrap_or_default()
+        );
+        request += &format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        );
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read just the response head, leaving the tunnel untouched
+    let mut response = vec![];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8 * 1024 {
+            return Err(anyhow!("Invalid response from proxy"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let status_line = String::from_utf8_lossy(&response);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(anyhow!("Proxy refused to connect to chat: {}", status_line));
+    }
+    Ok(stream)
+}
+
+/// A TLS connector trusting --ca-cert (or skipping verification with --insecure),
+/// or None to use the default system roots.
+fn websocket_tls_connector() -> Result<Option<Connector>> {
+    let insecure = crate::GLOBAL_OPTS.get().is_some_and(|opts| opts.insecure);
+    let ca_cert = crate::ca_cert_pem()?;
+    if !insecure && ca_cert.is_none() {
+        return Ok(None);
+    }
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(pem) = ca_cert {
+        builder.add_root_certificate(
+            native_tls::Certificate::from_pem(&pem)
+                .map_err(|e| CliError::ConfigError(format!("Invalid CA certificate: {}", e)))?,
+        );
+    }
+    builder.danger_accept_invalid_certs(insecure);
+    Ok(Some(Connector::NativeTls(builder.build()?)))
+}
+
+/// Connect to the chat websocket and authenticate for a session.
+async fn connect_chat(
+    ws_url: &str,
+    auth: &api::ws::AuthMessage,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    let url = Url::parse(ws_url)?;
+    let connector = websocket_tls_connector()?;
+    let connection = match websocket_proxy(&url) {
+        Some(proxy) => {
+            debug!(
+                "Connecting to chat through proxy {}",
+                proxy.host_str().unwrap_or_default()
+            );
+            let stream = proxy_tunnel(&proxy, &url).await?;
+            tokio_tungstenite::client_async_tls_with_config(ws_url, stream, None, connector).await
+        }
+        None => connect_async_tls_with_config(ws_url, None, false, connector).await,
+    };
+    let (mut ws_stream, _) = connection.map_err(|e| match e {
+        tokio_tungstenite::tungstenite::Error::Http(resp)
+            if resp.status() == 401 || resp.status() == 403 =>
+        {
+            anyhow!(ChatAuthError)
+        }
+        e => anyhow!("Failed to connect to chat: {}", e),
+    })?;
+    ws_stream
+        .send(Message::Text(serde_json::to_string(
+            &api::ws::Message::Auth(auth.clone()),
+        )?))
+        .await?;
+    debug!("Connected to chat");
+    Ok(ws_stream)
+}
+
+/// Below this the chat layout doesn't fit, so just a message asking for a bigger terminal is shown
+const MIN_TERMINAL_WIDTH: u16 = 30;
+const MIN_TERMINAL_HEIGHT: u16 = 10;
+
+/// The message to show instead of the chat if the terminal is too small for it
+fn terminal_too_small(area: Rect) -> Option<String> {
+    if area.width >= MIN_TERMINAL_WIDTH && area.height >= MIN_TERMINAL_HEIGHT {
+        return None;
+    }
+    Some(format!(
+        "Terminal too small ({}x{}). Please resize it to at least {}x{}.",
+        area.width, area.height, MIN_TERMINAL_WIDTH, MIN_TERMINAL_HEIGHT
+    ))
+}
+
 fn ui(
     frame: &mut ratatui::Frame,
     state: Arc<Mutex<AppState>>,
     chat_history: &mut ChatHistoryWidget,
     input: &tui_textarea::TextArea,
+    show_aci: bool,
 ) {
     let _ = match &*state.lock().unwrap() {
         AppState::Chat => {
@@ -2595,6 +4752,23 @@ fn ui(
         }
     };
 
+    if let Some(message) = terminal_too_small(frame.area()) {
+        // Nothing clickable is on screen
+        chat_history.area = Rect::default();
+        let paragraph = Paragraph::new(message)
+            .alignment(ratatui::layout::Alignment::Center)
+            .wrap(ratatui::widgets::Wrap { trim: true });
+        let height = paragraph.line_count(frame.area().width) as u16;
+        let [_, message_area, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(height),
+            Constraint::Fill(1),
+        ])
+        .areas(frame.area());
+        frame.render_widget(paragraph, message_area);
+        return;
+    }
+
     let vertical = ratatui::layout::Layout::vertical([
         ratatui::layout::Constraint::Percentage(100),
         ratatui::layout::Constraint::Min((input.lines().len().clamp(1, 3) + 2) as u16),
@@ -2613,7 +4787,7 @@ fn ui(
         AppState::Popup(title, text) => {
             let paragraph = Paragraph::new(text.clone()).block(Block::bordered().title(vec![
                 format!(" {} ", title).into(),
-                Span::styled("(press any key to close) ", ratatui::style::Color::Yellow),
+                Span::styled("(press any key to close) ", theme().notice),
             ]));
             let area = centered_paragraph(&paragraph, frame.area());
             frame.render_widget(Clear, area);
@@ -2622,8 +4796,25 @@ fn ui(
         AppState::SelectSession(widget) => {
             frame.render_widget(widget, frame.area());
         }
+        AppState::ACI(widget) if show_aci => {
+            frame.render_widget(&mut **widget, frame.area());
+        }
         AppState::ACI(widget) => {
-            frame.render_widget(widget, frame.area());
+            // On the message box's top border, so it's clear why input is ignored
+            let status_area = Rect {
+                x: input_area.x + 1,
+                y: input_area.y,
+                width: input_area.width.saturating_sub(2),
+                height: 1,
+            };
+            frame.render_widget(
+                Paragraph::new(Span::styled(
+                    format!(" Agent working: {} (Ctrl+C to stop) ", widget.status),
+                    theme().notice,
+                ))
+                .alignment(ratatui::layout::Alignment::Right),
+                status_area,
+            );
         }
         _ => {}
     }
@@ -2678,6 +4869,15 @@ mod terminal {
 
     pub fn init() -> io::Result<Terminal> {
         set_panic_hook();
+        let terminal = setup();
+        if terminal.is_err() {
+            // Don't leave the terminal half set up
+            restore();
+        }
+        terminal
+    }
+
+    fn setup() -> io::Result<Terminal> {
         enable_raw_mode()?;
         execute!(
             io::stdout(),
@@ -2749,8 +4949,108 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_incremental_markdown() {
+        let mut content = String::new();
+        for i in 0..200 {
+            content += &format!("Paragraph {} with some **bold** text.\n\n", i);
+            content += "- a list\n- of items\n\n";
+            content += &format!("```python\nprint({})\n\nprint('more')\n```\n\n", i);
+        }
+        content += "Heading\n---\n";
+
+        let mut msg = ChatMessage::new(ChatMessageUser::AI, "");
+        let mut max_tail = 0;
+        for token in content.split_inclusive(' ') {
+            msg.append(token);
+            max_tail = max_tail.max(msg.raw.len() - msg.stable_raw_len);
+        }
+
+        // Only a bounded tail is reparsed, no matter how long the message gets
+        assert!(max_tail < 200, "reparsed tail grew to {} bytes", max_tail);
+        assert_eq!(msg.raw, content);
+        assert_eq!(
+            msg.blocks,
+            ChatMessage::new(ChatMessageUser::AI, &content).blocks
+        );
+    }
+
+    #[test]
+    fn test_binary_files() -> Result<()> {
+        let tmpdir = tempfile::tempdir()?;
+        git2::Repository::init(tmpdir.path())?;
+        fs::write(tmpdir.path().join("text.txt"), "hello")?;
+        fs::write(
+            tmpdir.path().join("image.png"),
+            [0x89, b'P', b'N', b'G', 0x00, 0xff],
+        )?;
+        fs::write(tmpdir.path().join("latin1.txt"), [b'c', b'a', b'f', 0xe9])?;
+
+        assert_eq!(
+            read_text_file(&tmpdir.path().join("text.txt")),
+            Some("hello".to_string())
+        );
+        assert_eq!(read_text_file(&tmpdir.path().join("image.png")), None);
+        assert_eq!(read_text_file(&tmpdir.path().join("latin1.txt")), None);
+
+        let modified = command_modified_files(tmpdir.path())?;
+        assert_eq!(
+            modified
+                .iter()
+                .map(|f| f.project_path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["text.txt"]
+        );
+        assert_eq!(modified[0].content, "hello");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_preserving_permissions() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let tmpdir = tempfile::tempdir()?;
+
+        let script = tmpdir.path().join("run.sh");
+        fs::write(&script, "echo hi")?;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750))?;
+        write_preserving_permissions(&script, "echo bye")?;
+        assert_eq!(fs::metadata(&script)?.permissions().mode() & 0o777, 0o750);
+
+        let new_script = tmpdir.path().join("new.sh");
+        write_preserving_permissions(&new_script, "#!/bin/sh\necho hi")?;
+        assert_ne!(fs::metadata(&new_script)?.permissions().mode() & 0o100, 0);
+
+        let new_file = tmpdir.path().join("new.txt");
+        write_preserving_permissions(&new_file, "hi")?;
+        assert_eq!(fs::metadata(&new_file)?.permissions().mode() & 0o111, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_output_limit() {
+        let mut output = CommandOutput::new(1024);
+        for i in 0..1_000_000 {
+            output.push_line(&format!("y {}", i));
+        }
+        assert!(output.len <= 1024);
+        assert!(output.lines.len() < 1024);
+
+        let text = output.to_string();
+        assert!(text.starts_with("(Output truncated"));
+        assert!(text.ends_with("y 999999\n"));
+
+        let mut output = CommandOutput::new(1024);
+        output.push_line("short");
+        assert_eq!(output.to_string(), "short\n");
+    }
+
     #[test]
     fn test_changed_files() -> Result<()> {
+        let project = test_project();
         let tmpdir = tempfile::tempdir()?;
         let remote_tmpdir = tempfile::tempdir()?;
 
@@ -2821,7 +5121,7 @@ mod test {
 
         fs::write(tmpdir.path().join("untracked"), "untracked")?;
 
-        let changed_files: HashSet<_> = list_changed_files(tmpdir.path())?
+        let changed_files: HashSet<_> = list_changed_files(tmpdir.path(), &project)?
             .into_iter()
             .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
             .collect();
@@ -2835,4 +5135,574 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_changed_files_errors() -> Result<()> {
+        let project = test_project();
+        let tmpdir = tempfile::tempdir()?;
+        let repo = git2::Repository::init(tmpdir.path())?;
+
+        let signature = git2::Signature::now("Bismuth-Test", "test@app.bismuth.cloud")?;
+        let commit = {
+            let mut index = repo.index()?;
+            let tree_id = index.write_tree()?;
+            let tree = repo.find_tree(tree_id)?;
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "Initial commit",
+                &tree,
+                &[],
+            )?
+        };
+
+        let err = list_changed_files(tmpdir.path(), &project).unwrap_err();
+        assert!(err.to_string().contains("has not been pushed"));
+        assert!(err
+            .to_string()
+            .contains("bismuth project upload p --branch"));
+
+        repo.set_head_detached(commit)?;
+        let err = list_changed_files(tmpdir.path(), &project).unwrap_err();
+        assert!(err.to_string().contains("HEAD is detached"));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_marker_is_live() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let marker = tmpdir.path().join("marker");
+        assert!(!marker_is_live(&marker));
+
+        std::fs::write(&marker, std::process::id().to_string()).unwrap();
+        assert!(marker_is_live(&marker));
+
+        // A process which has exited and been reaped
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        std::fs::write(&marker, pid.to_string()).unwrap();
+        assert!(!marker_is_live(&marker));
+
+        std::fs::write(&marker, "garbage").unwrap();
+        assert!(!marker_is_live(&marker));
+    }
+
+    #[test]
+    fn test_draft_save_restore() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let draft = tmpdir.path().join("draft.txt");
+        assert_eq!(load_draft(&draft), None);
+
+        write_draft(&draft, "first line\nsecond line").unwrap();
+        assert_eq!(
+            load_draft(&draft).as_deref(),
+            Some("first line\nsecond line")
+        );
+
+        write_draft(&draft, "").unwrap();
+        assert!(!draft.exists());
+        assert_eq!(load_draft(&draft), None);
+        // Removing a draft that doesn't exist is fine
+        write_draft(&draft, "").unwrap();
+    }
+
+    fn test_aci_widget() -> ACIVizWidget {
+        ACIVizWidget::new(
+            vec!["src/main.rs".to_string()],
+            "src/main.rs",
+            "fn main() {}\n",
+            0,
+            0,
+        )
+    }
+
+    fn test_project() -> api::Project {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "p",
+            "hash": "abc123",
+            "features": [],
+            "cloneToken": "",
+            "hasPushed": true,
+        }))
+        .unwrap()
+    }
+
+    fn test_history_widget(messages: Vec<ChatMessage>) -> ChatHistoryWidget {
+        ChatHistoryWidget {
+            messages: Arc::new(Mutex::new(messages)),
+            scroll_position: 0,
+            scroll_max: 0,
+            scroll_state: ratatui::widgets::ScrollbarState::default(),
+            area: ratatui::layout::Rect::default(),
+            selection: None,
+            copy_selection: false,
+            copied_selection: None,
+            code_block_hitboxes: vec![],
+            message_hitboxes: vec![],
+            wrap_code: true,
+            h_scroll_position: 0,
+            h_scroll_max: 0,
+            selected_code_block: None,
+            sessions: vec![],
+            session: serde_json::from_value(serde_json::json!({"id": 1, "name": null})).unwrap(),
+            feature: serde_json::from_value(serde_json::json!({"id": 1, "name": "feature"}))
+                .unwrap(),
+            project: serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": "project",
+                "hash": "",
+                "features": [],
+                "cloneToken": "",
+                "githubRepo": null,
+                "githubAppInstall": null,
+                "hasPushed": false,
+            }))
+            .unwrap(),
+            credits: Arc::new(Mutex::new(Credits {
+                remaining: 0,
+                session_used: 0,
+                generation_used: 0,
+            })),
+            model: None,
+            dry_run: false,
+            connection_status: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    #[test]
+    fn test_line_cache_invalidation() {
+        let mut message = ChatMessage::new(
+            ChatMessageUser::AI,
+            &format!(
+                "{}\n\n```rust\n{}\n```\n",
+                "word ".repeat(40),
+                "x".repeat(100)
+            ),
+        );
+        message.finalized = true;
+        let mut widget = test_history_widget(vec![message]);
+
+        let render = |widget: &mut ChatHistoryWidget, width: u16| {
+            let area = ratatui::layout::Rect::new(0, 0, width, 10);
+            let mut buf = ratatui::buffer::Buffer::empty(area);
+            widget.render(area, &mut buf);
+            let messages = widget.messages.lock().unwrap();
+            let (key, counts) = messages[0].block_line_cache.clone();
+            (key, counts, widget.scroll_max)
+        };
+
+        let (key, wide_counts, wide_max) = render(&mut widget, 120);
+        assert_eq!(key.width, 120);
+        let total = widget.message_hitboxes.last().unwrap().1;
+        assert_eq!(total, wide_counts.iter().sum::<usize>());
+        assert_eq!(wide_max, (total + 3).saturating_sub(10));
+
+        let (key, narrow_counts, narrow_max) = render(&mut widget, 40);
+        assert_eq!(key.width, 40);
+        assert!(narrow_counts.iter().sum::<usize>() > wide_counts.iter().sum::<usize>());
+        let total = widget.message_hitboxes.last().unwrap().1;
+        assert_eq!(total, narrow_counts.iter().sum::<usize>());
+        assert_eq!(narrow_max, (total + 3).saturating_sub(10));
+        assert!(narrow_max > wide_max);
+
+        // Unwrapping code clips the long line, so the code block shrinks back down
+        widget.toggle_wrap_code();
+        let (key, unwrapped_counts, _) = render(&mut widget, 40);
+        assert!(!key.wrap_code);
+        assert!(unwrapped_counts.iter().sum::<usize>() < narrow_counts.iter().sum::<usize>());
+
+        widget.toggle_wrap_code();
+        let (key, rewrapped_counts, rewrapped_max) = render(&mut widget, 40);
+        assert!(key.wrap_code);
+        assert_eq!(rewrapped_counts, narrow_counts);
+        assert_eq!(rewrapped_max, narrow_max);
+    }
+
+    #[test]
+    fn test_line_at() {
+        let mut widget = test_history_widget(vec![]);
+        widget.area = ratatui::layout::Rect::new(0, 0, 40, 10);
+
+        // Borders never map to a line
+        assert_eq!(widget.line_at(5, 0), None);
+        assert_eq!(widget.line_at(5, 9), None);
+        assert_eq!(widget.line_at(0, 3), None);
+        assert_eq!(widget.line_at(39, 3), None);
+        assert_eq!(widget.line_at(5, 20), None);
+
+        assert_eq!(widget.line_at(1, 1), Some(0));
+        assert_eq!(widget.line_at(20, 8), Some(7));
+
+        widget.scroll_position = 12;
+        assert_eq!(widget.line_at(5, 0), None);
+        assert_eq!(widget.line_at(1, 1), Some(12));
+        assert_eq!(widget.line_at(20, 8), Some(19));
+
+        // Not rendered at the origin
+        widget.area = ratatui::layout::Rect::new(10, 5, 20, 6);
+        assert_eq!(widget.line_at(10, 6), None);
+        assert_eq!(widget.line_at(11, 5), None);
+        assert_eq!(widget.line_at(11, 6), Some(12));
+        assert_eq!(widget.line_at(28, 9), Some(15));
+        assert_eq!(widget.line_at(29, 9), None);
+    }
+
+    #[test]
+    fn test_is_copy_icon_click() {
+        let mut widget = test_history_widget(vec![]);
+        widget.area = ratatui::layout::Rect::new(0, 0, 40, 10);
+
+        assert!(widget.is_copy_icon_click(1, 1, 0));
+        assert!(widget.is_copy_icon_click(2, 1, 0));
+        assert!(!widget.is_copy_icon_click(3, 1, 0));
+        assert!(!widget.is_copy_icon_click(0, 1, 0));
+        assert!(widget.is_copy_icon_click(1, 4, 3));
+        assert!(!widget.is_copy_icon_click(1, 4, 4));
+
+        widget.scroll_position = 5;
+        assert!(!widget.is_copy_icon_click(1, 4, 3));
+        assert!(widget.is_copy_icon_click(1, 4, 8));
+        // Hitbox scrolled up behind the top border
+        assert!(!widget.is_copy_icon_click(1, 0, 4));
+
+        widget.area = ratatui::layout::Rect::new(10, 5, 20, 6);
+        assert!(widget.is_copy_icon_click(11, 6, 5));
+        assert!(widget.is_copy_icon_click(12, 6, 5));
+        assert!(!widget.is_copy_icon_click(1, 6, 5));
+        assert!(!widget.is_copy_icon_click(13, 6, 5));
+    }
+
+    #[test]
+    fn test_selection() {
+        let selection = Selection {
+            anchor: (3, 5),
+            cursor: (1, 2),
+        };
+        assert_eq!(selection.ordered(), ((1, 2), (3, 5)));
+        assert!(!selection.is_empty());
+        assert!(selection.contains(1, 2));
+        assert!(selection.contains(2, 0));
+        assert!(selection.contains(3, 5));
+        assert!(!selection.contains(1, 1));
+        assert!(!selection.contains(3, 6));
+        assert!(!selection.contains(4, 0));
+
+        // Nowhere to select in a history with no room inside its border
+        let mut widget = test_history_widget(vec![]);
+        widget.selection = Some(selection);
+        widget.area = ratatui::layout::Rect::new(0, 0, 2, 10);
+        widget.extend_selection(1, 3);
+        widget.area = ratatui::layout::Rect::new(0, 0, 40, 1);
+        widget.extend_selection(5, 0);
+    }
+
+    #[test]
+    fn test_selected_text() {
+        let lines = vec![
+            Line::raw("hello world foo"),
+            Line::raw(""),
+            Line::raw("abcdefghijklmnop"),
+            Line::raw("last"),
+        ];
+        // Wrapped to 10 columns this renders as:
+        // 0: hello
+        // 1: world foo
+        // 2:
+        // 3: abcdefghij
+        // 4: klmnop
+        // 5: last
+        assert_eq!(selected_text(&lines, 10, (0, 0), (0, 4)), "hello");
+        assert_eq!(selected_text(&lines, 0, (0, 0), (0, 4)), "");
+        assert_eq!(selected_text(&lines, 10, (0, 2), (1, 4)), "llo world");
+        assert_eq!(selected_text(&lines, 10, (1, 6), (2, 0)), "foo\n");
+        assert_eq!(
+            selected_text(&lines, 10, (0, 0), (5, 9)),
+            "hello world foo\n\nabcdefghijklmnop\nlast"
+        );
+        assert_eq!(selected_text(&lines, 10, (3, 8), (4, 1)), "ijkl");
+        assert_eq!(selected_text(&lines, 10, (4, 0), (5, 1)), "klmnop\nla");
+    }
+
+    #[test]
+    fn test_drag_selection() {
+        let mut widget = test_history_widget(vec![]);
+        widget.area = ratatui::layout::Rect::new(0, 0, 40, 10);
+        widget.scroll_max = 20;
+
+        // A click without dragging isn't a selection
+        widget.start_selection(5, 3);
+        assert!(!widget.finish_selection());
+        assert_eq!(widget.selection, None);
+
+        // Starting on the border doesn't select anything
+        widget.start_selection(0, 3);
+        widget.extend_selection(5, 5);
+        assert_eq!(widget.selection, None);
+
+        widget.scroll_position = 5;
+        widget.start_selection(5, 3);
+        widget.extend_selection(10, 4);
+        assert_eq!(
+            widget.selection,
+            Some(Selection {
+                anchor: (7, 4),
+                cursor: (8, 9),
+            })
+        );
+
+        // Dragging past the bottom scrolls down, keeping the anchor in place
+        widget.extend_selection(10, 12);
+        widget.extend_selection(10, 12);
+        assert_eq!(widget.scroll_position, 7);
+        assert_eq!(
+            widget.selection,
+            Some(Selection {
+                anchor: (7, 4),
+                cursor: (14, 9),
+            })
+        );
+
+        // And past the top scrolls back up
+        widget.extend_selection(0, 0);
+        assert_eq!(widget.scroll_position, 6);
+        assert_eq!(widget.selection.unwrap().cursor, (6, 0));
+
+        assert!(widget.finish_selection());
+        assert!(widget.copy_selection);
+    }
+
+    #[test]
+    fn test_theme() {
+        assert_eq!(colorfgbg_is_light("15;0"), Some(false));
+        assert_eq!(colorfgbg_is_light("0;15"), Some(true));
+        assert_eq!(colorfgbg_is_light("0;7"), Some(true));
+        assert_eq!(colorfgbg_is_light("7;8"), Some(false));
+        assert_eq!(colorfgbg_is_light("0;default;15"), Some(true));
+        assert_eq!(colorfgbg_is_light("default"), None);
+        assert_eq!(colorfgbg_is_light(""), None);
+
+        assert_eq!(Theme::new(ThemeChoice::Light), Theme::LIGHT);
+        assert_eq!(Theme::new(ThemeChoice::Dark), Theme::DARK);
+    }
+
+    #[test]
+    fn test_intraline_diff() {
+        let (old, new) = intraline_diff("let x = foo(1);", "let x = bar(1);").unwrap();
+        assert_eq!(
+            old,
+            vec![
+                ("let x = ".to_string(), false),
+                ("foo(1);".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            new,
+            vec![
+                ("let x = ".to_string(), false),
+                ("bar(1);".to_string(), true)
+            ]
+        );
+
+        assert_eq!(intraline_diff("fn main() {}", "use std::fs;"), None);
+
+        let diff = "diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n context\n-let x = foo;\n+let x = bar;\n-removed\n";
+        let lines = highlight_diff(diff);
+        assert_eq!(lines.len(), 8);
+        // Headers and context are a single unhighlighted span
+        for line in &lines[..5] {
+            assert_eq!(line.spans.len(), 1);
+        }
+        let spans = |line: &OwnedLine| {
+            line.spans
+                .iter()
+                .map(|(s, style)| {
+                    (
+                        s.clone(),
+                        style
+                            .add_modifier
+                            .contains(ratatui::style::Modifier::REVERSED),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            spans(&lines[5]),
+            vec![
+                ("-".to_string(), false),
+                ("let x = ".to_string(), false),
+                ("foo;".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            spans(&lines[6]),
+            vec![
+                ("+".to_string(), false),
+                ("let x = ".to_string(), false),
+                ("bar;".to_string(), true)
+            ]
+        );
+        // No added line to pair with
+        assert_eq!(spans(&lines[7]), vec![("-removed".to_string(), false)]);
+    }
+
+    #[test]
+    fn test_diff_line_numbers() {
+        let diff = "diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -9,4 +9,5 @@ fn main() {\n context\n-old\n+new\n+added\n \n\\ No newline at end of file\n@@ -100 +101,2 @@\n-x\n+y\n";
+        assert_eq!(
+            diff_line_numbers(diff),
+            vec![
+                (None, None),
+                (None, None),
+                (None, None),
+                (None, None),
+                (Some(9), Some(9)),
+                (Some(10), None),
+                (None, Some(10)),
+                (None, Some(11)),
+                (Some(11), Some(12)),
+                (None, None),
+                (None, None),
+                (Some(100), None),
+                (None, Some(101)),
+            ]
+        );
+
+        assert_eq!(line_number_width(0), 1);
+        assert_eq!(line_number_width(9), 1);
+        assert_eq!(line_number_width(10), 2);
+        assert_eq!(line_number_width(1234), 4);
+    }
+
+    #[test]
+    fn test_aci_save() -> Result<()> {
+        let mut widget = test_aci_widget();
+        for i in 0..ACI_OUTPUT_HISTORY_LEN + 2 {
+            widget.push_output(format!("$ echo {}\n{}", i, i));
+        }
+        assert_eq!(widget.output_history.len(), ACI_OUTPUT_HISTORY_LEN);
+        assert_eq!(widget.output_history[0], "$ echo 2\n2");
+
+        let tmpdir = tempfile::tempdir()?;
+        let dir = widget.save(tmpdir.path())?;
+        assert!(dir.starts_with(tmpdir.path()));
+        assert_eq!(fs::read_to_string(dir.join("main.rs"))?, "fn main() {}\n");
+        let log = fs::read_to_string(dir.join("output.log"))?;
+        assert!(log.starts_with("$ echo 2\n2\n\n$ echo 3\n3"));
+        assert!(log.ends_with(&format!("$ echo {0}\n{0}", ACI_OUTPUT_HISTORY_LEN + 1)));
+        assert_eq!(
+            fs::read_to_string(dir.join("status.log"))?,
+            "00:00 Looking through src/main.rs\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aci_status_log() {
+        let mut widget = test_aci_widget();
+        widget.started_at -= Duration::from_secs(65);
+        widget.set_status("Scrolling".to_string());
+        widget.set_status("Scrolling".to_string());
+        widget.set_status("Editing".to_string());
+        assert_eq!(widget.status, "Editing");
+        assert_eq!(
+            widget.status_log_lines(),
+            vec![
+                (
+                    "01:05".to_string(),
+                    "Looking through src/main.rs".to_string()
+                ),
+                ("01:05".to_string(), "Scrolling".to_string()),
+                ("01:05".to_string(), "Editing".to_string()),
+            ]
+        );
+
+        for i in 0..ACI_STATUS_LOG_LEN {
+            widget.set_status(i.to_string());
+        }
+        assert_eq!(widget.status_log.len(), ACI_STATUS_LOG_LEN);
+        assert_eq!(widget.status_log[0].1, "0");
+    }
+
+    #[test]
+    fn test_aci_is_scrolling() {
+        let mut widget = test_aci_widget();
+        widget.contents = CodeBlock::new(Some("src/main.rs"), None, &"line\n".repeat(100));
+        widget.scroll_speed = 30;
+        assert!(widget.in_scroll);
+        // Reading through the file, but caught up with where the agent is looking
+        assert!(!widget.is_scrolling());
+
+        widget.target_scroll_position = 1000;
+        assert!(widget.is_scrolling());
+        // Rendering clamps the target to the end of the file, and the view stops there
+        let mut buf = ratatui::buffer::Buffer::empty(ratatui::layout::Rect::new(0, 0, 80, 40));
+        for _ in 0..50 {
+            widget.anim_frame_time -= Duration::from_secs(1);
+            (&mut widget).render(buf.area, &mut buf);
+        }
+        assert!(widget.target_scroll_position < 100);
+        assert!(widget.in_scroll);
+        assert!(!widget.is_scrolling());
+
+        assert_eq!(widget.effective_scroll_speed(10), 30);
+        assert_eq!(widget.effective_scroll_speed(55), 110);
+        widget.scroll_speed = 0;
+        assert_eq!(widget.effective_scroll_speed(55), 0);
+        assert!(!widget.is_scrolling());
+    }
+
+    #[test]
+    fn test_scroll_toward() {
+        let second = Duration::from_secs(1);
+        assert_eq!(scroll_toward(0.0, 100, second, 0), 100.0);
+        assert_eq!(scroll_toward(0.0, 100, second, 30), 30.0);
+        assert_eq!(scroll_toward(0.0, 100, second / 10, 30), 3.0);
+        assert_eq!(scroll_toward(90.0, 100, second, 30), 100.0);
+        assert_eq!(scroll_toward(100.0, 20, second, 30), 70.0);
+        assert_eq!(scroll_toward(25.0, 20, second, 30), 20.0);
+        assert_eq!(scroll_toward(20.0, 20, second, 30), 20.0);
+    }
+
+    #[test]
+    fn test_resize_clamps_scroll() {
+        let mut message = ChatMessage::new(ChatMessageUser::AI, &"line\n".repeat(30));
+        message.finalized = true;
+        let mut widget = test_history_widget(vec![message]);
+
+        let render = |widget: &mut ChatHistoryWidget, height: u16| {
+            let area = ratatui::layout::Rect::new(0, 0, 40, height);
+            let mut buf = ratatui::buffer::Buffer::empty(area);
+            widget.render(area, &mut buf);
+        };
+
+        render(&mut widget, 10);
+        assert!(widget.scroll_max > 0);
+        assert_eq!(widget.scroll_position, widget.scroll_max);
+
+        // Scrolled up a bit, then the terminal gets tall enough to fit everything
+        widget.scroll_position -= 1;
+        render(&mut widget, 100);
+        assert_eq!(widget.scroll_max, 0);
+        assert_eq!(widget.scroll_position, 0);
+    }
+
+    #[test]
+    fn test_terminal_too_small() {
+        assert_eq!(terminal_too_small(Rect::new(0, 0, 80, 24)), None);
+        assert_eq!(
+            terminal_too_small(Rect::new(0, 0, MIN_TERMINAL_WIDTH, MIN_TERMINAL_HEIGHT)),
+            None
+        );
+        assert_eq!(
+            terminal_too_small(Rect::new(0, 0, 20, 24)).unwrap(),
+            "Terminal too small (20x24). Please resize it to at least 30x10."
+        );
+        assert!(terminal_too_small(Rect::new(0, 0, 80, 5)).is_some());
+        assert!(terminal_too_small(Rect::new(0, 0, 0, 0)).is_some());
+    }
 }
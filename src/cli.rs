@@ -1,6 +1,6 @@
 use std::{path::PathBuf, str::FromStr};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use url::Url;
 
 /// The CLI for Bismuth Cloud
@@ -24,6 +24,23 @@ pub fn default_config_file() -> PathBuf {
     }
 }
 
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The config file for a profile. The default profile uses the original config file.
+pub fn profile_config_file(profile: &str) -> PathBuf {
+    let default = default_config_file();
+    if profile == DEFAULT_PROFILE {
+        default
+    } else {
+        default.with_file_name(format!("bismuth.{}.json", profile))
+    }
+}
+
+/// The file recording which profile `profile use` selected.
+pub fn active_profile_file() -> PathBuf {
+    default_config_file().with_file_name("bismuth-profile")
+}
+
 #[derive(Debug, Clone)]
 pub enum IdOrName {
     Id(u64),
@@ -41,39 +58,209 @@ impl FromStr for IdOrName {
 
 #[derive(Clone, Debug, Args)]
 pub struct FeatureRef {
+    #[clap(
+        help = "The feature to operate on, specified as 'project/feature', where project is a name or id. Defaults to the feature for the current repository and branch"
+    )]
+    pub feature: Option<String>,
+
+    /// Use the project and feature of this repository (its Bismuth remote and current branch)
+    /// instead of specifying 'project/feature'
+    #[clap(long, conflicts_with = "feature")]
+    pub repo: Option<PathBuf>,
+}
+
+// A feature reference that must be given explicitly, for commands where it's followed by other positional arguments.
+#[derive(Clone, Debug, Args)]
+pub struct RequiredFeatureRef {
     #[clap(help = "The feature to operate on, specified as 'project/feature'")]
     pub feature: String,
 }
 
+impl From<&RequiredFeatureRef> for FeatureRef {
+    fn from(feature: &RequiredFeatureRef) -> Self {
+        FeatureRef {
+            feature: Some(feature.feature.clone()),
+            repo: None,
+        }
+    }
+}
+
 impl FromStr for FeatureRef {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(FeatureRef {
-            feature: s.to_string(),
+            feature: Some(s.to_string()),
+            repo: None,
         })
     }
 }
 
 impl FeatureRef {
     pub fn as_str(&self) -> &str {
-        &self.feature
+        self.feature.as_deref().unwrap_or_default()
     }
-    pub fn split(&self) -> (IdOrName, IdOrName) {
-        let parts: Vec<&str> = self.feature.splitn(2, '/').collect();
-        if parts.len() != 2 {
-            // TODO: nice error message
-            panic!(
+    pub fn split(&self) -> Result<(IdOrName, IdOrName), String> {
+        match self.as_str().split_once('/') {
+            Some((project, feature)) if !project.is_empty() && !feature.is_empty() => {
+                // Always a name here, resolve_project_id falls back to an id if no project has it
+                Ok((
+                    IdOrName::Name(project.to_string()),
+                    IdOrName::Name(feature.to_string()),
+                ))
+            }
+            _ => Err(format!(
                 "Invalid feature reference (use `project/feature`): {}",
-                self.feature
-            );
+                self.as_str()
+            )),
+        }
+    }
+}
+
+/// A calendar date, parsed from YYYY-MM-DD (or with / or . separators), YYYYMMDD, YYYY-MM, or `today`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn today() -> Self {
+        let days = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+            / 86400;
+        // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        Date { year, month, day }
+    }
+
+    pub fn start_of_month(self) -> Self {
+        Date { day: 1, ..self }
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("today") {
+            return Ok(Date::today());
+        }
+        let parts: Vec<&str> = if s.len() == 8 && s.chars().all(|c| c.is_ascii_digit()) {
+            vec![&s[0..4], &s[4..6], &s[6..8]]
+        } else {
+            s.split(['-', '/', '.']).collect()
+        };
+        let invalid = || format!("Invalid date {} (expected YYYY-MM-DD)", s);
+        let (year, month, day) = match parts.as_slice() {
+            [y, m] => (y.parse().ok(), m.parse().ok(), Some(1)),
+            [y, m, d] => (y.parse().ok(), m.parse().ok(), d.parse().ok()),
+            _ => return Err(invalid()),
+        };
+        match (year, month, day) {
+            (Some(year), Some(month @ 1..=12), Some(day @ 1..=31)) => Ok(Date { year, month, day }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// An environment variable assignment, parsed from KEY=VALUE.
+#[derive(Clone, Debug)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(EnvVar {
+                key: key.trim().to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!("Invalid variable {} (expected KEY=VALUE)", s)),
+        }
+    }
+}
+
+/// An inclusive byte range, parsed from START-END or START- (to the end).
+#[derive(Clone, Copy, Debug)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid range {} (expected START-END or START-)", s);
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = start.parse().map_err(|_| invalid())?;
+        let end = match end {
+            "" => None,
+            end => Some(end.parse().map_err(|_| invalid())?),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(invalid());
         }
-        (
-            IdOrName::Name(parts[0].to_string()),
-            IdOrName::Name(parts[1].to_string()),
-        )
+        Ok(ByteRange { start, end })
     }
 }
 
+impl std::fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeChoice {
+    /// Light on dark if the terminal background can't be detected
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum EnvFormat {
+    /// KEY=VALUE, exactly as stored
+    #[default]
+    Plain,
+    /// Quoted where needed, suitable for a .env file
+    Dotenv,
+}
+
 #[derive(Debug, Args)]
 #[group(required = true, multiple = false)]
 pub struct LiteralOrFile {
@@ -86,29 +273,130 @@ pub struct LiteralOrFile {
     pub file: Option<PathBuf>,
 }
 
+impl LiteralOrFile {
+    /// The Content-Type to upload this value with, guessed from the file extension
+    /// if there is one.
+    pub fn content_type(&self) -> String {
+        match &self.file {
+            Some(file) => mime_guess::from_path(file)
+                .first_or_octet_stream()
+                .to_string(),
+            None => "application/octet-stream".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Args)]
 pub struct GlobalOpts {
-    #[arg(long, hide = true, default_value = std::env::var("BISMUTH_API").unwrap_or("https://api.bismuth.cloud".to_string()))]
+    /// The API to use for this command, instead of the one the profile logged in to.
+    /// Can also be set with BISMUTH_API.
+    #[arg(long, global = true, default_value = std::env::var("BISMUTH_API").unwrap_or("https://api.bismuth.cloud".to_string()))]
     pub api_url: Url,
 
+    /// The chat websocket to use for this command, instead of the one for the API
+    #[arg(long, global = true, alias = "daneel-url")]
+    pub websocket_url: Option<Url>,
+
+    /// The OpenID Connect server to log in with, instead of the one for the API
+    #[arg(long, global = true)]
+    pub oidc_url: Option<Url>,
+
+    /// HTTP proxy to send all requests through.
+    /// Defaults to the HTTPS_PROXY, HTTP_PROXY, or ALL_PROXY environment variables, respecting NO_PROXY.
+    #[arg(long, global = true)]
+    pub proxy: Option<Url>,
+
+    /// PEM file with an additional CA certificate to trust, for backends using an internal CA
+    #[arg(long, global = true)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Disable TLS certificate verification. INSECURE, only for local development backends
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
     #[arg(long, hide = true, default_value = default_config_file().into_os_string())]
     pub config_file: PathBuf,
 
+    /// Automatically answer yes to all confirmation prompts
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+
+    /// The profile to use, instead of the one selected by `profile use`.
+    /// Can also be set with BISMUTH_PROFILE.
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Answer selection prompts (e.g. organization) non-interactively, by index or name.
+    /// Can also be set with BISMUTH_SELECT.
+    #[arg(long, global = true)]
+    pub select: Option<String>,
+
+    /// Print URLs instead of opening them in a browser
+    #[arg(long, global = true)]
+    pub no_browser: bool,
+
+    /// Don't warn about low credits before deploying or chatting
+    #[arg(long, global = true)]
+    pub no_credit_check: bool,
+
+    /// Print errors to stderr as JSON objects with `error` and `kind` fields
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+
+    /// Maximum number of API requests in flight when fetching details for many projects or features
+    #[arg(
+        long,
+        global = true,
+        default_value = "8",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub concurrency: usize,
+
+    // -q/--quiet also suppresses informational output, see `GlobalOpts::quiet`
     #[command(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity,
 }
 
+impl GlobalOpts {
+    /// Whether to suppress informational output (progress, banners),
+    /// printing only primary results and errors.
+    pub fn quiet(&self) -> bool {
+        self.verbose.is_silent()
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
     /// Login to Bismuth Cloud
     Login,
     /// Show the CLI version
     Version,
+    /// Update the CLI to the latest release
+    Update {
+        /// Only check whether an update is available
+        #[clap(long)]
+        check_only: bool,
+    },
     /// Configure the CLI
     Configure {
         #[clap(subcommand)]
         command: ConfigureCommand,
     },
+    /// Check a repository's bismuth.toml for errors
+    Validate {
+        /// The repository to check. Defaults to the current directory.
+        repo: Option<PathBuf>,
+    },
+    /// Inspect or change the CLI configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage login profiles, e.g. for different backends
+    Profile {
+        #[clap(subcommand)]
+        command: ProfileCommand,
+    },
     /// Manage projects
     Project {
         #[clap(subcommand)]
@@ -149,16 +437,40 @@ pub enum Command {
     Deploy {
         #[clap(flatten)]
         feature: FeatureRef,
-        #[clap(long, default_value = "false")]
+        /// Wait for the deployment to be healthy (the default)
+        #[clap(long, overrides_with = "no_wait")]
+        wait: bool,
+        /// Return as soon as the deployment is started
+        #[clap(long, overrides_with = "wait")]
         no_wait: bool,
+        /// Seconds to wait for the deployment to be healthy
         #[clap(long, default_value = "15")]
         timeout: u64,
+        /// Seconds between deployment status checks while waiting
+        #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+        poll_interval: u64,
+        /// Force push unpushed commits, overwriting any history on Bismuth that isn't present locally
+        #[clap(long)]
+        force: bool,
+        #[clap(flatten)]
+        env: DeployEnv,
     },
     /// Get the status of a deployment. Alias of `feature deploy-status`.
     #[clap(hide = true)]
     DeployStatus {
         #[clap(flatten)]
         feature: FeatureRef,
+        /// Keep checking the status until the deployment is running or has failed
+        #[clap(long)]
+        watch: bool,
+        /// Seconds between status checks with --watch
+        #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+        poll_interval: u64,
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+        /// Exit with an error unless the deployment is running (with a distinct code if it failed)
+        #[clap(long)]
+        fail_if_not_running: bool,
     },
     /// Teardown a feature. Alias of `feature teardown`.
     #[clap(hide = true)]
@@ -171,6 +483,12 @@ pub enum Command {
     GetURL {
         #[clap(flatten)]
         feature: FeatureRef,
+        /// Open the URL in a browser
+        #[clap(long)]
+        open: bool,
+        /// Print the URL as JSON
+        #[clap(long)]
+        json: bool,
     },
     /// Get logs from a deployment. Alias of `feature logs`.
     #[clap(hide = true)]
@@ -189,8 +507,26 @@ pub enum Command {
         #[clap(long)]
         repo: Option<PathBuf>,
         /// Specify a chat session name to use.
-        #[clap(short, long = "session")]
+        /// A new session with this name is created if it doesn't exist, otherwise you're asked for a name.
+        #[clap(short, long = "session", visible_alias = "name")]
         session_name: Option<String>,
+        /// Preview the changes Bismuth makes without writing them to the repository.
+        /// Commands the agent wants to run are skipped.
+        #[clap(long)]
+        dry_run: bool,
+        /// Send a single message without the interactive UI, print the response, and exit.
+        #[clap(short, long)]
+        message: Option<String>,
+        /// With --message, apply the resulting changes instead of discarding them.
+        #[clap(long, requires = "message")]
+        auto_apply: bool,
+        /// Don't show the agent's file view while it works, only the resulting changes.
+        /// Same as `show_aci = false` in the [chat] section of bismuth.toml.
+        #[clap(long)]
+        no_aci: bool,
+        /// Color theme for the chat UI. Defaults to the `theme` set in the config file, or auto.
+        #[clap(long, value_enum)]
+        theme: Option<ThemeChoice>,
         #[clap(subcommand)]
         command: Option<ChatSubcommand>,
     },
@@ -202,14 +538,79 @@ pub enum ConfigureCommand {
     #[clap(hide = true)]
     /// OAuth via OpenRouter.
     /// Required to use chat on free tier.
-    OpenRouter {},
+    OpenRouter {
+        /// Use this OpenRouter API key instead of authenticating in the browser.
+        /// Pass `-` to read the key from stdin. Can also be set with BISMUTH_OPENROUTER_KEY.
+        #[clap(long)]
+        key: Option<String>,
+    },
+    /// Show the current LLM configuration
+    Show,
+    /// Remove the configured LLM key
+    Clear,
+    /// Choose the model used by the agent
+    Model {
+        /// The model ID to use. Lists the available models if not specified.
+        model: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommand {
+    /// List profiles
+    List,
+    /// Use a profile for subsequent commands. Run `bismuth login` afterwards if it's new.
+    Use { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration
+    Show {
+        /// Print the API token instead of redacting it
+        #[clap(long)]
+        show_token: bool,
+    },
+    /// Change a setting, kept across logins
+    Set {
+        #[clap(subcommand)]
+        setting: ConfigSetting,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigSetting {
+    /// Warn before deploying or chatting when remaining credits drop below this
+    LowCreditThreshold { credits: i32 },
+    /// The default for `chat --theme`
+    Theme {
+        #[clap(value_enum)]
+        theme: ThemeChoice,
+    },
 }
 
 #[derive(Debug, Subcommand)]
 pub enum ChatSubcommand {
     ListSessions,
-    RenameSession { old_name: String, new_name: String },
-    DeleteSession { name: String },
+    RenameSession {
+        old_name: String,
+        new_name: String,
+    },
+    /// Delete a session by name, or prune sessions with --all or --older-than.
+    /// Sessions open in a running chat are never deleted.
+    DeleteSession {
+        #[clap(required_unless_present_any = ["all", "older_than"])]
+        name: Option<String>,
+        /// Delete every session
+        #[clap(long, conflicts_with_all = ["name", "older_than"])]
+        all: bool,
+        /// Delete sessions with no activity within this long, e.g. 30d or 12h
+        #[clap(long, conflicts_with = "name")]
+        older_than: Option<humantime::Duration>,
+        /// Delete sessions even if they're open in a running chat
+        #[clap(long)]
+        force: bool,
+    },
 }
 
 #[derive(Debug, Args)]
@@ -219,16 +620,57 @@ pub struct ImportSource {
     #[arg(group = "source")]
     pub repo: Option<PathBuf>,
 
+    /// Clone and import a remote Git repository (e.g. https://github.com/foo/bar).
+    /// Private repositories use your existing git credentials.
+    #[arg(long, group = "source")]
+    pub url: Option<String>,
+
     /// Import a repository from GitHub
     #[arg(long, group = "source", hide = true)]
     pub github: bool,
 }
 
+/// Which branches to push to Bismuth. Defaults to the current branch.
+#[derive(Debug, Args)]
+#[group(multiple = false)]
+pub struct PushBranches {
+    /// Push only this branch. Can be specified multiple times.
+    #[arg(long = "branch")]
+    pub branches: Vec<String>,
+
+    /// Push only the currently checked out branch (the default)
+    #[arg(long)]
+    pub current_only: bool,
+
+    /// Push all local branches
+    #[arg(long)]
+    pub all: bool,
+}
+
+/// Environment variables to set on the feature before deploying.
+#[derive(Debug, Args)]
+pub struct DeployEnv {
+    /// Set variables from a .env file
+    #[arg(long)]
+    pub env_file: Option<PathBuf>,
+
+    /// Set a variable. Can be specified multiple times, and takes precedence over --env-file.
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    pub vars: Vec<EnvVar>,
+}
+
 #[derive(Debug, Args)]
 pub struct ImportArgs {
     #[clap(flatten)]
     pub source: ImportSource,
 
+    #[clap(flatten)]
+    pub branches: PushBranches,
+
+    /// Force push, overwriting any history on Bismuth that isn't present locally
+    #[arg(long)]
+    pub force: bool,
+
     /// Implicitly upload to Bismuth Cloud
     #[arg(long)]
     pub upload: bool,
@@ -253,6 +695,11 @@ pub enum ProjectCommand {
         project: IdOrName,
         /// The path to the Git repository to upload
         repo: Option<PathBuf>,
+        #[clap(flatten)]
+        branches: PushBranches,
+        /// Force push, overwriting any history on Bismuth that isn't present locally
+        #[clap(long)]
+        force: bool,
     },
     /// Link a project to a GitHub repository
     #[clap(hide = true)]
@@ -260,11 +707,32 @@ pub enum ProjectCommand {
         /// The project to link
         project: IdOrName,
     },
+    /// Disconnect a project from its GitHub repository
+    #[clap(hide = true)]
+    Unlink {
+        /// The project to unlink
+        project: IdOrName,
+    },
     /// Clone the project for local development
     Clone {
-        project: IdOrName,
+        #[clap(required_unless_present = "all")]
+        project: Option<IdOrName>,
         /// The target directory to clone the project into. Defaults to the project name.
         outdir: Option<PathBuf>,
+        /// Clone every project into its own subdirectory of DIR (default the current directory),
+        /// named after the project, plus its id if several projects share the name.
+        /// Projects that are already present are skipped.
+        #[clap(
+            long,
+            value_name = "DIR",
+            num_args = 0..=1,
+            default_missing_value = ".",
+            conflicts_with_all = ["project", "outdir"]
+        )]
+        all: Option<PathBuf>,
+        /// Create a shallow clone with only the last N commits
+        #[clap(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
     },
     /// Delete a project
     Delete { project: IdOrName },
@@ -272,8 +740,19 @@ pub enum ProjectCommand {
 
 #[derive(Debug, Subcommand)]
 pub enum FeatureCommand {
-    /// List all features in a project
-    List { project: IdOrName },
+    /// List all features in a project, or in every project with --all-projects
+    List {
+        #[clap(required_unless_present = "all_projects")]
+        project: Option<IdOrName>,
+        /// List features in every project, as project/feature
+        #[clap(long, conflicts_with = "project")]
+        all_projects: bool,
+        /// Include each feature's deployment status
+        #[clap(long)]
+        status: bool,
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
     /// Manage feature configuration
     #[clap(hide = true)]
     Config {
@@ -282,21 +761,52 @@ pub enum FeatureCommand {
         #[clap(subcommand)]
         command: FeatureConfigCommand,
     },
+    /// Manage environment variables for deployments
+    Env {
+        #[clap(flatten)]
+        feature: FeatureRef,
+        #[clap(subcommand)]
+        command: FeatureEnvCommand,
+    },
     /// Deploy project/feature to the cloud
     #[clap(hide = true)]
     Deploy {
         #[clap(flatten)]
         feature: FeatureRef,
-        #[clap(long, default_value = "false")]
+        /// Wait for the deployment to be healthy (the default)
+        #[clap(long, overrides_with = "no_wait")]
+        wait: bool,
+        /// Return as soon as the deployment is started
+        #[clap(long, overrides_with = "wait")]
         no_wait: bool,
+        /// Seconds to wait for the deployment to be healthy
         #[clap(long, default_value = "15")]
         timeout: u64,
+        /// Seconds between deployment status checks while waiting
+        #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+        poll_interval: u64,
+        /// Force push unpushed commits, overwriting any history on Bismuth that isn't present locally
+        #[clap(long)]
+        force: bool,
+        #[clap(flatten)]
+        env: DeployEnv,
     },
     /// Get the status of a deployment
     #[clap(hide = true)]
     DeployStatus {
         #[clap(flatten)]
         feature: FeatureRef,
+        /// Keep checking the status until the deployment is running or has failed
+        #[clap(long)]
+        watch: bool,
+        /// Seconds between status checks with --watch
+        #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+        poll_interval: u64,
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+        /// Exit with an error unless the deployment is running (with a distinct code if it failed)
+        #[clap(long)]
+        fail_if_not_running: bool,
     },
     /// Teardown a feature
     #[clap(hide = true)]
@@ -309,6 +819,23 @@ pub enum FeatureCommand {
     GetURL {
         #[clap(flatten)]
         feature: FeatureRef,
+        /// Open the URL in a browser
+        #[clap(long)]
+        open: bool,
+        /// Print the URL as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Check that a deployed feature is responding
+    Ping {
+        #[clap(flatten)]
+        feature: RequiredFeatureRef,
+        /// The path to request
+        #[clap(default_value = "/")]
+        path: String,
+        /// Exit with an error unless the response has this status code
+        #[clap(long)]
+        expect_status: Option<u16>,
     },
     /// Get logs from a deployment
     #[clap(hide = true)]
@@ -323,8 +850,42 @@ pub enum FeatureCommand {
 
 #[derive(Debug, Subcommand)]
 pub enum FeatureConfigCommand {
-    Get { key: Option<String> },
-    Set { key: String, value: String },
+    Get {
+        key: Option<String>,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    /// Remove a key
+    Unset {
+        key: String,
+        /// Don't error if the key isn't set
+        #[clap(long)]
+        ignore_missing: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FeatureEnvCommand {
+    /// Print one or all variables
+    Get {
+        key: Option<String>,
+        #[clap(long, value_enum, default_value_t)]
+        format: EnvFormat,
+    },
+    /// Set one or more variables
+    Set {
+        #[clap(required = true, value_name = "KEY=VALUE")]
+        vars: Vec<EnvVar>,
+    },
+    /// Remove one or more variables
+    Unset {
+        #[clap(required = true)]
+        keys: Vec<String>,
+    },
+    /// Set all variables from a .env file
+    Import { file: PathBuf },
 }
 
 #[derive(Debug, Subcommand)]
@@ -332,20 +893,44 @@ pub enum FeatureConfigCommand {
 pub enum KVCommand {
     Get {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
+        /// Print the value's size, content type, and last modified time instead of the value
+        #[clap(long)]
+        metadata: bool,
     },
     Set {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
         value: String,
     },
     Delete {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
     },
+    /// Export all keys to a JSON object of key to value. Binary values are base64 encoded.
+    Export {
+        #[clap(flatten)]
+        feature: RequiredFeatureRef,
+        /// The path to write the export to. Defaults to writing to stdout.
+        output: Option<PathBuf>,
+    },
+    /// Set all keys from a file created by `kv export`.
+    /// Fails without changing anything if any key already exists, unless --overwrite or --skip-existing is given.
+    Import {
+        #[clap(flatten)]
+        feature: RequiredFeatureRef,
+        /// The file to import
+        input: PathBuf,
+        /// Replace the values of keys that already exist
+        #[clap(long, conflicts_with = "skip_existing")]
+        overwrite: bool,
+        /// Leave keys that already exist unchanged
+        #[clap(long)]
+        skip_existing: bool,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -357,28 +942,39 @@ pub enum BlobCommand {
     },
     Create {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
         #[clap(flatten)]
         value: LiteralOrFile,
+        /// The blob's Content-Type. Defaults to guessing from the file extension,
+        /// or application/octet-stream
+        #[clap(long)]
+        content_type: Option<String>,
     },
     Get {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
         /// The path to write the blob to. Defaults to writing to stdout.
         output: Option<PathBuf>,
+        /// Only download the given bytes, e.g. 0-1023 for the first KiB (inclusive)
+        #[clap(long)]
+        range: Option<ByteRange>,
     },
     Set {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
         #[clap(flatten)]
         value: LiteralOrFile,
+        /// The blob's Content-Type. Defaults to guessing from the file extension,
+        /// or application/octet-stream
+        #[clap(long)]
+        content_type: Option<String>,
     },
     Delete {
         #[clap(flatten)]
-        feature: FeatureRef,
+        feature: RequiredFeatureRef,
         key: String,
     },
 }
@@ -402,4 +998,15 @@ pub enum BillingCommand {
     CreditsRemaining,
     /// Open credit purchase page
     Refill,
+    /// Show credit usage over time
+    Usage {
+        /// Start of the range (inclusive). Defaults to the start of the current month.
+        #[clap(long)]
+        since: Option<Date>,
+        /// End of the range (inclusive). Defaults to today.
+        #[clap(long)]
+        until: Option<Date>,
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+    },
 }
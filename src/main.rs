@@ -1,17 +1,20 @@
 use anyhow::{anyhow, Result};
-use clap::Parser as _;
+use clap::{CommandFactory as _, FromArgMatches as _};
 use colored::Colorize;
 use futures::{StreamExt as _, TryStreamExt};
 use log::debug;
 use once_cell::sync::OnceCell;
+use reqwest::ResponseBuilderExt as _;
 use reqwest_eventsource::EventSource;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
-use std::io::{Read as _, Write as _};
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal as _, Read as _, Write as _};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
@@ -26,11 +29,176 @@ use chat::start_chat;
 mod bismuth_toml;
 
 static GLOBAL_OPTS: OnceCell<cli::GlobalOpts> = OnceCell::new();
+/// --json-errors, set before anything else so that every error respects it.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+fn quiet() -> bool {
+    GLOBAL_OPTS.get().is_some_and(|opts| opts.quiet())
+}
+
+/// `println!` for informational output, which is suppressed by --quiet.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !quiet() {
+            println!($($arg)*);
+        }
+    };
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
     organization_id: u64,
     token: String,
+    /// The API the token is for, used unless overridden
+    #[serde(default)]
+    api_url: Option<String>,
+    /// Warn before deploying or chatting when remaining credits drop below this
+    #[serde(default = "default_low_credit_threshold")]
+    low_credit_threshold: i32,
+    /// Default for `chat --theme`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    theme: Option<cli::ThemeChoice>,
+}
+
+fn default_low_credit_threshold() -> i32 {
+    50
+}
+
+async fn write_config(path: &Path, config: &Config) -> Result<()> {
+    let config_str = serde_json::to_string(config)?;
+    let mut config_file = File::create(path).await?;
+    config_file.write_all(config_str.as_bytes()).await?;
+    Ok(())
+}
+
+fn validate_profile_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid profile name '{}' (use letters, numbers, - and _)",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The profile selected by --profile, BISMUTH_PROFILE, or `profile use`, in that order.
+fn active_profile(opts: &cli::GlobalOpts) -> Result<String> {
+    let profile = opts
+        .profile
+        .clone()
+        .or_else(|| std::env::var("BISMUTH_PROFILE").ok())
+        .or_else(|| {
+            std::fs::read_to_string(cli::active_profile_file())
+                .ok()
+                .map(|s| s.trim().to_string())
+        })
+        .unwrap_or(cli::DEFAULT_PROFILE.to_string());
+    validate_profile_name(&profile)?;
+    Ok(profile)
+}
+
+/// All profiles with a config file, sorted by name.
+fn list_profiles() -> Vec<String> {
+    let default = cli::default_config_file();
+    let mut profiles = vec![];
+    if default.exists() {
+        profiles.push(cli::DEFAULT_PROFILE.to_string());
+    }
+    if let Ok(entries) = default
+        .parent()
+        .map_or(Ok(None), |dir| std::fs::read_dir(dir).map(Some))
+    {
+        for entry in entries.into_iter().flatten().flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(name) = file_name
+                .strip_prefix("bismuth.")
+                .and_then(|n| n.strip_suffix(".json"))
+            {
+                if validate_profile_name(name).is_ok() && name != cli::DEFAULT_PROFILE {
+                    profiles.push(name.to_string());
+                }
+            }
+        }
+    }
+    profiles.sort();
+    profiles
+}
+
+/// The API URL saved in a config file at login, if any.
+fn stored_api_url(config_file: &Path) -> Option<Url> {
+    let config: Config = serde_json::from_str(&std::fs::read_to_string(config_file).ok()?).ok()?;
+    Url::parse(&config.api_url?).ok()
+}
+
+/// Warn (without blocking) if the organization is running low on credits.
+async fn warn_low_credits(client: &APIClient, threshold: i32) {
+    if GLOBAL_OPTS.get().is_some_and(|opts| opts.no_credit_check) {
+        return;
+    }
+    let credits = match client.get("/billing/credits/usage").send().await {
+        Ok(resp) => resp.json::<api::CreditUsage>().await,
+        Err(e) => Err(e),
+    };
+    match credits {
+        Ok(credits) => {
+            let remaining = credits.plan_included - credits.plan_used + credits.purchased_remaining;
+            if remaining < threshold {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Only {} credits remaining. Run `bismuth billing refill` to buy more.",
+                        remaining
+                    )
+                    .yellow()
+                );
+            }
+        }
+        Err(e) => debug!("Failed to check credits: {}", redact_error(e)),
+    }
+}
+
+/// The URL with any password masked, safe to log or show in errors.
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("****"));
+    }
+    url.to_string()
+}
+
+/// Mask passwords in any URLs embedded in `text`, e.g. in error messages from reqwest.
+fn redact_urls(text: &str) -> String {
+    static URL_PASSWORD: once_cell::sync::Lazy<regex::Regex> =
+        once_cell::sync::Lazy::new(|| regex::Regex::new(r"(://[^:/@\s]*:)[^@/\s]+@").unwrap());
+    URL_PASSWORD.replace_all(text, "${1}****@").to_string()
+}
+
+/// Mask the password in the URL attached to a reqwest error, which is included in its message.
+fn redact_error(mut e: reqwest::Error) -> reqwest::Error {
+    if let Some(url) = e.url_mut() {
+        if url.password().is_some() {
+            let _ = url.set_password(Some("****"));
+        }
+    }
+    e
+}
+
+/// Mask all but the first and last 4 characters of a secret.
+fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    format!(
+        "{}{}{}",
+        chars[..4].iter().collect::<String>(),
+        "*".repeat(chars.len() - 8),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
 }
 
 #[derive(Clone)]
@@ -45,35 +213,289 @@ impl APIClient {
         let mut base_url = api_url.clone();
         base_url.set_password(Some(token)).unwrap();
         Ok(Self {
-            client: reqwest::ClientBuilder::new()
+            client: http_client_builder()?
                 .user_agent("bismuthcloud-cli")
                 .build()?,
             base_url,
             token: token.to_string(),
         })
     }
-    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+    fn get(&self, path: &str) -> APIRequest {
         debug!("GET {}", path);
-        self.client
-            .get(self.base_url.join(path.trim_start_matches('/')).unwrap())
+        APIRequest(
+            self.client
+                .get(self.base_url.join(path.trim_start_matches('/')).unwrap()),
+        )
     }
-    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+    fn post(&self, path: &str) -> APIRequest {
         debug!("POST {}", path);
-        self.client
-            .post(self.base_url.join(path.trim_start_matches('/')).unwrap())
+        APIRequest(
+            self.client
+                .post(self.base_url.join(path.trim_start_matches('/')).unwrap()),
+        )
     }
-    fn put(&self, path: &str) -> reqwest::RequestBuilder {
+    fn put(&self, path: &str) -> APIRequest {
         debug!("PUT {}", path);
-        self.client
-            .put(self.base_url.join(path.trim_start_matches('/')).unwrap())
+        APIRequest(
+            self.client
+                .put(self.base_url.join(path.trim_start_matches('/')).unwrap()),
+        )
     }
-    fn delete(&self, path: &str) -> reqwest::RequestBuilder {
+    fn delete(&self, path: &str) -> APIRequest {
         debug!("DELETE {}", path);
-        self.client
-            .delete(self.base_url.join(path.trim_start_matches('/')).unwrap())
+        APIRequest(
+            self.client
+                .delete(self.base_url.join(path.trim_start_matches('/')).unwrap()),
+        )
+    }
+    fn head(&self, path: &str) -> APIRequest {
+        debug!("HEAD {}", path);
+        APIRequest(
+            self.client
+                .head(self.base_url.join(path.trim_start_matches('/')).unwrap()),
+        )
+    }
+}
+
+/// Read the PEM CA certificate given with --ca-cert, if any.
+fn ca_cert_pem() -> Result<Option<Vec<u8>>> {
+    match GLOBAL_OPTS.get().and_then(|opts| opts.ca_cert.as_ref()) {
+        Some(path) => std::fs::read(path).map(Some).map_err(|e| {
+            CliError::ConfigError(format!(
+                "Failed to read CA certificate {}: {}",
+                path.display(),
+                e
+            ))
+            .into()
+        }),
+        None => Ok(None),
+    }
+}
+
+/// A reqwest client builder with the global network options (e.g. --proxy) applied.
+/// Without --proxy, reqwest uses the proxy environment variables itself.
+fn http_client_builder() -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::ClientBuilder::new();
+    if let Some(opts) = GLOBAL_OPTS.get() {
+        if let Some(proxy) = &opts.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+        }
+        if opts.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+    }
+    if let Some(pem) = ca_cert_pem()? {
+        builder =
+            builder
+                .add_root_certificate(reqwest::Certificate::from_pem(&pem).map_err(|e| {
+                    CliError::ConfigError(format!("Invalid CA certificate: {}", e))
+                })?);
+    }
+    Ok(builder)
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    Ok(http_client_builder()?.build()?)
+}
+
+/// Log target for full HTTP request/response tracing, enabled with -vv.
+const HTTP_LOG_TARGET: &str = "bismuth::http";
+
+/// Bodies larger than this (or of unknown length) aren't logged when tracing.
+const HTTP_TRACE_BODY_LIMIT: usize = 16 * 1024;
+
+/// A request to the Bismuth API, which is traced when HTTP logging is enabled.
+struct APIRequest(reqwest::RequestBuilder);
+
+impl APIRequest {
+    fn json<T: Serialize + ?Sized>(self, json: &T) -> Self {
+        APIRequest(self.0.json(json))
+    }
+    fn query<T: Serialize + ?Sized>(self, query: &T) -> Self {
+        APIRequest(self.0.query(query))
+    }
+    fn body<T: Into<reqwest::Body>>(self, body: T) -> Self {
+        APIRequest(self.0.body(body))
+    }
+    fn header(self, key: reqwest::header::HeaderName, value: reqwest::header::HeaderValue) -> Self {
+        APIRequest(self.0.header(key, value))
+    }
+    /// The underlying request, for APIs that need to send it themselves. These aren't traced.
+    fn into_inner(self) -> reqwest::RequestBuilder {
+        self.0
+    }
+
+    async fn send(self) -> reqwest::Result<reqwest::Response> {
+        self.send_traced().await.map_err(redact_error)
+    }
+
+    async fn send_traced(self) -> reqwest::Result<reqwest::Response> {
+        if !log::log_enabled!(target: HTTP_LOG_TARGET, log::Level::Trace) {
+            return self.0.send().await;
+        }
+
+        let (client, request) = self.0.build_split();
+        let request = request?;
+        log::trace!(
+            target: HTTP_LOG_TARGET,
+            "> {} {}",
+            request.method(),
+            redact_url(request.url())
+        );
+        trace_headers("> ", request.headers());
+        match request.body().map(|body| body.as_bytes()) {
+            Some(Some(bytes)) => trace_body("> ", bytes),
+            Some(None) => log::trace!(target: HTTP_LOG_TARGET, "> (streaming body)"),
+            None => {}
+        }
+
+        let resp = client.execute(request).await?;
+        log::trace!(target: HTTP_LOG_TARGET, "< {}", resp.status());
+        trace_headers("< ", resp.headers());
+        let bufferable = resp
+            .content_length()
+            .is_some_and(|len| len as usize <= HTTP_TRACE_BODY_LIMIT)
+            && !resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .is_some_and(|ct| ct.as_bytes().starts_with(b"text/event-stream"));
+        if !bufferable {
+            log::trace!(target: HTTP_LOG_TARGET, "< (body not logged)");
+            return Ok(resp);
+        }
+
+        // Read the body to log it, then hand back an equivalent response
+        let mut builder = http::Response::builder()
+            .status(resp.status())
+            .version(resp.version())
+            .url(resp.url().clone());
+        *builder.headers_mut().unwrap() = resp.headers().clone();
+        let bytes = resp.bytes().await?;
+        trace_body("< ", &bytes);
+        Ok(builder
+            .body(bytes)
+            .expect("parts taken from a valid response")
+            .into())
+    }
+}
+
+fn trace_headers(prefix: &str, headers: &reqwest::header::HeaderMap) {
+    for (name, value) in headers {
+        let value = if name == reqwest::header::AUTHORIZATION {
+            redact(value.to_str().unwrap_or_default())
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).to_string()
+        };
+        log::trace!(target: HTTP_LOG_TARGET, "{}{}: {}", prefix, name, value);
+    }
+}
+
+fn trace_body(prefix: &str, body: &[u8]) {
+    if body.len() > HTTP_TRACE_BODY_LIMIT {
+        log::trace!(target: HTTP_LOG_TARGET, "{}({} byte body not logged)", prefix, body.len());
+    } else {
+        log::trace!(target: HTTP_LOG_TARGET, "{}{}", prefix, String::from_utf8_lossy(body));
+    }
+}
+
+/// Errors with a known category, each of which exits with its own status code.
+/// Anything else (including errors from reqwest, git2, and config parsing further down the chain)
+/// is categorized by `CliError::exit_code`.
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("Unauthorized - maybe you need to login?")]
+    Unauthorized,
+    #[error("{message} ({status}{})", request_id_suffix(.request_id))]
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+        request_id: Option<String>,
+    },
+    #[error("{0}")]
+    NetworkError(String),
+    #[error("{0}")]
+    GitError(String),
+    #[error("{0}")]
+    ConfigError(String),
+    #[error("{0}")]
+    NotRunning(String),
+    #[error("{0}")]
+    DeployFailed(String),
+    #[error("{0}")]
+    NotConfirmed(String),
+}
+
+impl CliError {
+    const EXIT_NOT_FOUND: i32 = 3;
+    const EXIT_UNAUTHORIZED: i32 = 4;
+    const EXIT_API: i32 = 5;
+    const EXIT_NETWORK: i32 = 6;
+    const EXIT_GIT: i32 = 7;
+    const EXIT_CONFIG: i32 = 8;
+    const EXIT_NOT_RUNNING: i32 = 9;
+    const EXIT_DEPLOY_FAILED: i32 = 10;
+    const EXIT_NOT_CONFIRMED: i32 = 11;
+
+    /// The `kind` and exit code of this error.
+    fn category(&self) -> (&'static str, i32) {
+        match self {
+            CliError::NotFound(_) => ("not_found", Self::EXIT_NOT_FOUND),
+            CliError::Unauthorized => ("unauthorized", Self::EXIT_UNAUTHORIZED),
+            CliError::Api { .. } => ("api", Self::EXIT_API),
+            CliError::NetworkError(_) => ("network", Self::EXIT_NETWORK),
+            CliError::GitError(_) => ("git", Self::EXIT_GIT),
+            CliError::ConfigError(_) => ("config", Self::EXIT_CONFIG),
+            CliError::NotRunning(_) => ("not_running", Self::EXIT_NOT_RUNNING),
+            CliError::DeployFailed(_) => ("deploy_failed", Self::EXIT_DEPLOY_FAILED),
+            CliError::NotConfirmed(_) => ("not_confirmed", Self::EXIT_NOT_CONFIRMED),
+        }
+    }
+
+    /// The category of an error, based on the first categorizable error in its chain.
+    fn categorize(e: &anyhow::Error) -> (&'static str, i32) {
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<CliError>() {
+                return e.category();
+            }
+            if cause.is::<chat::ChatAuthError>() {
+                return CliError::Unauthorized.category();
+            }
+            if cause.is::<reqwest::Error>() || cause.is::<tokio_tungstenite::tungstenite::Error>() {
+                return CliError::NetworkError(String::new()).category();
+            }
+            if cause.is::<git2::Error>() {
+                return CliError::GitError(String::new()).category();
+            }
+            if cause.is::<toml::de::Error>() {
+                return CliError::ConfigError(String::new()).category();
+            }
+        }
+        ("other", 1)
+    }
+
+    /// Reported as `kind` with --json-errors.
+    fn kind(e: &anyhow::Error) -> &'static str {
+        Self::categorize(e).0
+    }
+
+    /// The process exit code for an error.
+    fn exit_code(e: &anyhow::Error) -> i32 {
+        Self::categorize(e).1
     }
 }
 
+/// Headers the API may use to identify a request, in order of preference.
+const REQUEST_ID_HEADERS: [&str; 3] = ["x-request-id", "x-trace-id", "x-amzn-trace-id"];
+
+fn request_id_suffix(request_id: &Option<String>) -> String {
+    request_id
+        .as_ref()
+        .map(|id| format!(", request id {}", id))
+        .unwrap_or_default()
+}
+
 trait ResponseErrorExt {
     async fn error_body_for_status(self) -> Result<reqwest::Response>;
 }
@@ -82,26 +504,95 @@ impl ResponseErrorExt for reqwest::Response {
     async fn error_body_for_status(self) -> Result<reqwest::Response> {
         let status = self.status();
         if status.is_success() {
-            Ok(self)
-        } else if status == reqwest::StatusCode::UNAUTHORIZED {
-            Err(anyhow!("Unauthorized - maybe you need to login?",))
+            return Ok(self);
+        }
+        let request_id = REQUEST_ID_HEADERS.iter().find_map(|header| {
+            self.headers()
+                .get(*header)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        });
+        debug!(
+            "{} failed with {} (request id {})",
+            self.url().path(),
+            status,
+            request_id.as_deref().unwrap_or("none")
+        );
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            Err(CliError::Unauthorized.into())
         } else {
-            let body = self.text().await?;
-            Err(anyhow!("{} ({})", body, status))
+            let message = self.text().await?;
+            if status == reqwest::StatusCode::NOT_FOUND {
+                Err(CliError::NotFound(format!(
+                    "{} ({}{})",
+                    message,
+                    status,
+                    request_id_suffix(&request_id)
+                ))
+                .into())
+            } else {
+                Err(CliError::Api {
+                    status,
+                    message,
+                    request_id,
+                }
+                .into())
+            }
         }
     }
 }
 
-macro_rules! can_launch_browser {
-    () => {
-        cfg!(target_os = "macos") || cfg!(target_os = "windows")
-    };
+/// Whether URLs should be opened in a browser rather than printed.
+pub(crate) fn can_launch_browser() -> bool {
+    (cfg!(target_os = "macos") || cfg!(target_os = "windows"))
+        && !GLOBAL_OPTS.get().is_some_and(|opts| opts.no_browser)
+}
+
+/// Find the thing referred to by `selector`, either as a 1-based index or by name.
+fn select<'a, T>(things: &'a [T], selector: &str) -> Option<&'a T>
+where
+    T: ToString,
+{
+    match selector.trim().parse::<usize>() {
+        Ok(idx) if idx >= 1 => things.get(idx - 1),
+        _ => things
+            .iter()
+            .find(|thing| thing.to_string() == selector.trim()),
+    }
 }
 
 async fn choice<'a, 'b, T>(things: &'a [T], name: &'b str) -> Result<&'a T>
 where
     T: ToString,
 {
+    let selector = GLOBAL_OPTS
+        .get()
+        .and_then(|opts| opts.select.clone())
+        .or_else(|| std::env::var("BISMUTH_SELECT").ok());
+    if let Some(selector) = selector {
+        return select(things, &selector).ok_or_else(|| {
+            anyhow!(
+                "No such {} '{}'. Available: {}",
+                name,
+                selector,
+                things
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        });
+    }
+    if things.len() == 1 {
+        println!("Using {} {}", name, things[0].to_string());
+        return Ok(&things[0]);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Multiple {}s available but stdin is not a terminal. Use --select to pick one.",
+            name
+        ));
+    }
     loop {
         println!("Select a {}:", name);
         for (i, thing) in things.iter().enumerate() {
@@ -114,28 +605,34 @@ where
             .next()
             .unwrap_or(Ok("".to_string()))?;
 
-        if let Ok(idx) = selector.trim().parse::<usize>() {
-            if idx > things.len() {
+        match select(things, &selector) {
+            Some(thing) => break Ok(thing),
+            None if selector.trim().parse::<usize>().is_ok() => {
                 eprintln!("Invalid index");
-                continue;
             }
-            break Ok(&things[idx - 1]);
-        } else {
-            match things
-                .iter()
-                .find(|thing| thing.to_string() == selector.trim())
-            {
-                Some(thing) => break Ok(thing),
-                None => {
-                    eprintln!("No such {}", name);
-                    continue;
-                }
+            None => {
+                eprintln!("No such {}", name);
             }
         }
     }
 }
 
 async fn confirm(prompt: impl Into<String>, default: bool) -> Result<bool> {
+    if GLOBAL_OPTS.get().is_some_and(|opts| opts.yes) {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        // Nobody to ask, so don't treat EOF as an answer
+        if !default {
+            return Err(CliError::NotConfirmed(format!(
+                "Refusing without --yes (stdin is not a terminal): {}",
+                prompt.into()
+            ))
+            .into());
+        }
+        debug!("stdin is not a terminal, using default confirmation answer");
+        return Ok(default);
+    }
     print!(
         "{} [{}/{}] ",
         prompt.into(),
@@ -154,6 +651,21 @@ async fn confirm(prompt: impl Into<String>, default: bool) -> Result<bool> {
     Ok(confirm == "y")
 }
 
+/// Ask for a line of text. Returns None if the answer is empty, or if there's nobody to ask.
+async fn prompt_text(prompt: impl Into<String>) -> Result<Option<String>> {
+    if GLOBAL_OPTS.get().is_some_and(|opts| opts.yes) || !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    print!("{} ", prompt.into());
+    std::io::stdout().flush()?;
+    let answer = std::io::stdin()
+        .lines()
+        .next()
+        .unwrap_or(Ok("".to_string()))?;
+    let answer = answer.trim();
+    Ok((!answer.is_empty()).then(|| answer.to_string()))
+}
+
 #[cfg(not(target_os = "windows"))]
 async fn press_any_key(msg: &str) -> Result<()> {
     println!("{}", msg);
@@ -203,6 +715,57 @@ fn github_app_url(api_url: &Url) -> &'static str {
     }
 }
 
+/// The --concurrency limit for `fetch_concurrently`.
+fn concurrency() -> usize {
+    GLOBAL_OPTS.get().map_or(8, |opts| opts.concurrency)
+}
+
+/// Run `fetch` for every item with at most `concurrency` running at once,
+/// returning the results in the same order as `items`. Fails on the first error.
+async fn fetch_concurrently<T, R, F, Fut>(
+    concurrency: usize,
+    items: impl IntoIterator<Item = T>,
+    mut fetch: F,
+) -> Result<Vec<R>>
+where
+    F: FnMut(T) -> Fut,
+    Fut: std::future::Future<Output = Result<R>>,
+{
+    let mut results: Vec<(usize, R)> = futures::stream::iter(items.into_iter().enumerate())
+        .map(|(i, item)| {
+            let fut = fetch(item);
+            async move { Ok::<_, anyhow::Error>((i, fut.await?)) }
+        })
+        .buffer_unordered(concurrency)
+        .try_collect()
+        .await?;
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/// Fetch every project in the organization, with full details.
+async fn list_projects_detailed(client: &APIClient) -> Result<Vec<api::Project>> {
+    let projects: api::ListProjectsResponse = client
+        .get("/projects/list")
+        .send()
+        .await?
+        .error_body_for_status()
+        .await?
+        .json()
+        .await?;
+    fetch_concurrently(concurrency(), projects.projects, |project| async move {
+        Ok(client
+            .get(&format!("projects/{}", project.id))
+            .send()
+            .await?
+            .error_body_for_status()
+            .await?
+            .json()
+            .await?)
+    })
+    .await
+}
+
 async fn resolve_project_id(client: &APIClient, id: &IdOrName) -> Result<api::Project> {
     let project_id = match id {
         cli::IdOrName::Name(name) => {
@@ -214,12 +777,29 @@ async fn resolve_project_id(client: &APIClient, id: &IdOrName) -> Result<api::Pr
                 .await?
                 .json()
                 .await?;
-            let project = projects
+            let matching: Vec<&api::Project> = projects
                 .projects
                 .iter()
-                .find(|p| p.name == *name)
-                .ok_or_else(|| anyhow!("No such project"))?;
-            project.id
+                .filter(|p| p.name == *name)
+                .collect();
+            match matching.as_slice() {
+                [] => match name.parse::<u64>() {
+                    // Nothing by that name, but it may be an id
+                    Ok(id) if projects.projects.iter().any(|p| p.id == id) => id,
+                    _ => return Err(CliError::NotFound("No such project".to_string()).into()),
+                },
+                [project] => project.id,
+                _ => {
+                    eprintln!("There are multiple projects named {}", name);
+                    let labels: Vec<String> = matching
+                        .iter()
+                        .map(|p| format!("{} (id {})", p.name, p.id))
+                        .collect();
+                    let label = choice(&labels, "project").await?;
+                    let idx = labels.iter().position(|l| l == label).unwrap();
+                    matching[idx].id
+                }
+            }
         }
         cli::IdOrName::Id(id) => *id,
     };
@@ -243,7 +823,7 @@ async fn resolve_feature_id(
                 .features
                 .iter()
                 .find(|f| f.name == *name)
-                .ok_or_else(|| anyhow!("No such feature"))?;
+                .ok_or_else(|| CliError::NotFound("No such feature".to_string()))?;
             feature.id
         }
         cli::IdOrName::Id(id) => *id,
@@ -257,6 +837,116 @@ async fn resolve_feature_id(
     Ok(get_feature.json().await?)
 }
 
+/// Resolve a feature given either as `project/feature` or with `--repo`.
+/// If neither is given, the feature is inferred from the repository in the current directory.
+async fn resolve_feature_ref(
+    client: &APIClient,
+    feature: &cli::FeatureRef,
+) -> Result<(api::Project, api::Feature)> {
+    if let Some(repo) = &feature.repo {
+        return get_project_and_feature_for_repo(client, repo).await;
+    }
+    if feature.feature.is_none() {
+        let cwd = std::env::current_dir()?;
+        if git2::Repository::discover(&cwd).is_err() {
+            return Err(anyhow!(
+                "No feature specified. Pass 'project/feature', use --repo, or run this inside a repository imported to Bismuth"
+            ));
+        }
+        return get_project_and_feature_for_repo(client, &cwd).await;
+    }
+    let (project_name, feature_name) = feature.split().map_err(|e| anyhow!(e))?;
+    let project = resolve_project_id(client, &project_name).await?;
+    let feature = resolve_feature_id(client, &project, &feature_name).await?;
+    Ok((project, feature))
+}
+
+/// Parse a timestamp from the API. Only UTC timestamps are supported.
+fn parse_api_timestamp(timestamp: &str) -> Option<std::time::SystemTime> {
+    humantime::parse_rfc3339_weak(timestamp.trim_end_matches("+00:00")).ok()
+}
+
+/// Delete every session not open in a running chat (or every session with `force`),
+/// or only those with no activity within `older_than`.
+async fn prune_chat_sessions(
+    client: &APIClient,
+    project: &api::Project,
+    feature: &api::Feature,
+    older_than: Option<Duration>,
+    force: bool,
+) -> Result<()> {
+    let sessions: Vec<api::ChatSession> = client
+        .get(&format!(
+            "/projects/{}/features/{}/chat/sessions",
+            project.id, feature.id
+        ))
+        .send()
+        .await?
+        .error_body_for_status()
+        .await?
+        .json()
+        .await?;
+
+    let now = std::time::SystemTime::now();
+    let mut open = 0;
+    let mut unknown_age = 0;
+    let to_delete: Vec<&api::ChatSession> = sessions
+        .iter()
+        .filter(|session| {
+            if !force && chat::session_is_open(session.id) {
+                open += 1;
+                return false;
+            }
+            let Some(older_than) = older_than else {
+                return true;
+            };
+            match session.updated_at.as_deref().and_then(parse_api_timestamp) {
+                Some(updated_at) => now
+                    .duration_since(updated_at)
+                    .is_ok_and(|age| age > older_than),
+                None => {
+                    unknown_age += 1;
+                    false
+                }
+            }
+        })
+        .collect();
+
+    if open > 0 {
+        status!(
+            "Skipping {} session(s) open in a running chat. Use --force to delete them anyway",
+            open
+        );
+    }
+    if unknown_age > 0 {
+        status!("Skipping {} session(s) with no activity time", unknown_age);
+    }
+    if to_delete.is_empty() {
+        println!("No sessions to delete");
+        return Ok(());
+    }
+    if !confirm(format!("Delete {} session(s)?", to_delete.len()), false).await? {
+        return Ok(());
+    }
+
+    fetch_concurrently(concurrency(), &to_delete, |session| async move {
+        client
+            .delete(&format!(
+                "/projects/{}/features/{}/chat/sessions/{}",
+                project.id, feature.id, session.id
+            ))
+            .send()
+            .await?
+            .error_body_for_status()
+            .await?;
+        chat::remove_draft(session.id);
+        Ok(())
+    })
+    .await?;
+    println!("Deleted {} session(s)", to_delete.len());
+    Ok(())
+}
+
 async fn resolve_chat_session(
     client: &APIClient,
     project: &api::Project,
@@ -279,18 +969,25 @@ async fn resolve_chat_session(
         .find(|s| s.name() == session_name)
         .cloned()
         .ok_or_else(|| {
-            anyhow!(
+            CliError::NotFound(format!(
                 "No such chat session. Available sessions: {}",
                 sessions
                     .iter()
                     .map(|s| s.name())
                     .collect::<Vec<String>>()
                     .join(", ")
-            )
+            ))
+            .into()
         })
 }
 
-fn set_bismuth_remote(repo: &Path, project: &api::Project) -> Result<()> {
+/// The URL the Bismuth remote should point at, according to the repo's `git.remote_auth`.
+fn bismuth_remote_url(project: &api::Project, auth: bismuth_toml::RemoteAuth) -> Result<String> {
+    if let (bismuth_toml::RemoteAuth::Ssh, Some(_), Some(github_repo)) =
+        (auth, &project.github_app_install, &project.github_repo)
+    {
+        return Ok(format!("git@github.com:{}.git", github_repo));
+    }
     let mut git_url = GLOBAL_OPTS
         .get()
         .unwrap()
@@ -298,19 +995,287 @@ fn set_bismuth_remote(repo: &Path, project: &api::Project) -> Result<()> {
         .clone()
         .join(&format!("/git/{}", project.hash))?;
     git_url.set_username("git").unwrap();
-    git_url.set_password(Some(&project.clone_token)).unwrap();
+    Ok(git_url.to_string())
+}
 
-    let git_repo = git2::Repository::discover(repo)?;
-    match git_repo.find_remote("bismuth") {
-        Ok(_) => {
-            debug!("Updating existing bismuth remote URL");
-            git_repo.remote_set_url("bismuth", git_url.as_ref())?;
-        }
-        Err(_) => {
-            debug!("Adding new bismuth remote");
-            git_repo.remote("bismuth", git_url.as_ref())?;
-        }
-    }
+/// A `git` command which can authenticate to Bismuth.
+/// With token auth, the clone token is handed to an inline credential helper through the environment,
+/// so it never ends up in `.git/config` or the process arguments.
+fn bismuth_git(project: &api::Project, auth: bismuth_toml::RemoteAuth) -> Command {
+    let mut cmd = Command::new("git");
+    if auth == bismuth_toml::RemoteAuth::Token {
+        let helper_key = format!(
+            "credential.{}.helper",
+            GLOBAL_OPTS
+                .get()
+                .unwrap()
+                .api_url
+                .origin()
+                .ascii_serialization()
+        );
+        cmd.arg("-c")
+            .arg(format!("{}=", helper_key))
+            .arg("-c")
+            .arg(format!(
+                "{}=!f() {{ test \"$1\" = get && echo username=git && echo \"password=$BISMUTH_CLONE_TOKEN\"; }}; f",
+                helper_key
+            ))
+            .env("BISMUTH_CLONE_TOKEN", &project.clone_token);
+    }
+    cmd
+}
+
+/// Result of a `git push --porcelain`.
+#[derive(Debug, Default)]
+struct PushSummary {
+    pushed: usize,
+    up_to_date: usize,
+    rejected: Vec<String>,
+    size: Option<String>,
+}
+
+impl PushSummary {
+    fn parse(stdout: &str, stderr: &str) -> Self {
+        let mut summary = PushSummary::default();
+        // Porcelain ref lines are `<flag>\t<from>:<to>\t<summary>`
+        for line in stdout.lines() {
+            let mut parts = line.splitn(3, '\t');
+            let (Some(flag), Some(refs)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            match flag {
+                " " | "+" | "-" | "*" => summary.pushed += 1,
+                "=" => summary.up_to_date += 1,
+                "!" => summary.rejected.push(format!(
+                    "{} {}",
+                    refs.split(':').next_back().unwrap_or(refs),
+                    parts.next().unwrap_or("")
+                )),
+                _ => {}
+            }
+        }
+        summary.size = stderr
+            .split(['\r', '\n'])
+            .filter_map(|l| l.strip_prefix("Writing objects: 100% "))
+            .next_back()
+            .and_then(|l| l.split(", ").nth(1))
+            .map(|s| s.split(" | ").next().unwrap().trim().to_string());
+        summary
+    }
+}
+
+/// The `git push` arguments selecting which branches to push.
+fn push_branch_args(branches: &cli::PushBranches, repo: &Path) -> Result<Vec<String>> {
+    if branches.all {
+        Ok(vec!["--all".to_string()])
+    } else if !branches.branches.is_empty() {
+        Ok(branches.branches.clone())
+    } else {
+        Ok(vec![chat::current_branch(&git2::Repository::discover(
+            repo,
+        )?)?])
+    }
+}
+
+/// Files larger than this are flagged before pushing.
+const PUSH_WARN_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Tracked files which look like they shouldn't be pushed: likely secrets, or large binaries.
+fn flagged_push_files(repo: &Path) -> Result<Vec<String>> {
+    let secret_globs = {
+        let mut builder = globset::GlobSetBuilder::new();
+        for glob in [
+            "**/.env",
+            "**/.env.*",
+            "**/*.pem",
+            "**/*.key",
+            "**/*.p12",
+            "**/*.pfx",
+            "**/id_rsa*",
+            "**/id_ed25519*",
+            "**/credentials.json",
+            "**/.npmrc",
+            "**/.pypirc",
+        ] {
+            builder.add(globset::Glob::new(glob)?);
+        }
+        builder.build()?
+    };
+
+    let repo = git2::Repository::discover(repo)?;
+    let mut flagged = vec![];
+    for entry in repo.index()?.iter() {
+        let path = String::from_utf8_lossy(&entry.path).to_string();
+        if secret_globs.is_match(&path) {
+            flagged.push(format!("{} (may contain secrets)", path));
+        } else if entry.file_size as u64 > PUSH_WARN_FILE_SIZE {
+            flagged.push(format!(
+                "{} ({:.1} MiB)",
+                path,
+                entry.file_size as f64 / (1024.0 * 1024.0)
+            ));
+        }
+    }
+    Ok(flagged)
+}
+
+/// Warn about files that probably shouldn't be uploaded, and ask whether to push anyway.
+async fn confirm_push_contents(repo: &Path) -> Result<bool> {
+    let flagged = flagged_push_files(repo)?;
+    if flagged.is_empty() {
+        return Ok(true);
+    }
+    println!(
+        "{}",
+        "The following tracked files look like secrets or large binaries:".yellow()
+    );
+    for file in &flagged {
+        println!("  {}", file);
+    }
+    confirm("Push them to Bismuth anyway?", false).await
+}
+
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Run a `git push` to Bismuth, showing a spinner while it runs and a summary when done.
+/// Git's error output is printed if the push fails.
+fn push_to_bismuth(cmd: &mut Command) -> Result<()> {
+    cmd.arg("--porcelain").arg("--progress");
+
+    let done = Arc::new(AtomicBool::new(false));
+    let spinner = (std::io::stderr().is_terminal() && !quiet()).then(|| {
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let mut i = 0;
+            while !done.load(Ordering::Relaxed) {
+                eprint!(
+                    "\r{} Pushing to Bismuth...",
+                    SPINNER_FRAMES[i % SPINNER_FRAMES.len()]
+                );
+                i += 1;
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            eprint!("\r\x1b[2K");
+        })
+    });
+    let output = cmd.output();
+    done.store(true, Ordering::Relaxed);
+    if let Some(spinner) = spinner {
+        let _ = spinner.join();
+    }
+    let output = output.map_err(|e| anyhow!("Failed to run git: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let summary = PushSummary::parse(&stdout, &stderr);
+
+    if !output.status.success() || !summary.rejected.is_empty() {
+        for line in stderr.split(['\r', '\n']) {
+            if line.is_empty()
+                || [
+                    "Enumerating objects",
+                    "Counting objects",
+                    "Delta compression",
+                    "Compressing objects",
+                    "Writing objects",
+                    "Total ",
+                ]
+                .iter()
+                .any(|p| line.starts_with(p))
+            {
+                continue;
+            }
+            eprintln!("{}", line);
+        }
+        for rejected in &summary.rejected {
+            eprintln!("{} {}", "Rejected:".red(), rejected);
+        }
+        if !summary.rejected.is_empty() || stderr.contains("Updates were rejected") {
+            eprintln!(
+                "{}",
+                "Bismuth has commits that aren't present locally. Pull them first, or re-run with --force to overwrite them."
+                    .yellow()
+            );
+        }
+        return Err(
+            CliError::GitError(format!("Failed to push to Bismuth ({})", output.status)).into(),
+        );
+    }
+
+    status!(
+        "{}",
+        format!(
+            "✓ Pushed {} ref{} to Bismuth{}{}",
+            summary.pushed,
+            if summary.pushed == 1 { "" } else { "s" },
+            summary
+                .size
+                .map(|s| format!(" ({})", s))
+                .unwrap_or_default(),
+            if summary.up_to_date > 0 {
+                format!(", {} already up to date", summary.up_to_date)
+            } else {
+                "".to_string()
+            }
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Whether a remote URL is the project's Bismuth git remote (with or without an inlined token).
+fn is_bismuth_remote(remote_url: &str, project: &api::Project) -> bool {
+    remote_url
+        .trim_end_matches('/')
+        .ends_with(&format!("/git/{}", project.hash))
+}
+
+/// Whether a remote URL points at the given project, regardless of how it's authenticated.
+fn remote_matches_project(remote_url: &str, project: &api::Project) -> bool {
+    if is_bismuth_remote(remote_url, project) {
+        return true;
+    }
+    match (&project.github_app_install, &project.github_repo) {
+        (Some(_), Some(github_repo)) => remote_url == format!("git@github.com:{}.git", github_repo),
+        _ => false,
+    }
+}
+
+/// Whether `path` is the root of a git repository with any remote pointing at the project.
+fn is_clone_of(path: &Path, project: &api::Project) -> bool {
+    let Ok(repo) = git2::Repository::open(path) else {
+        return false;
+    };
+    let Ok(remotes) = repo.remotes() else {
+        return false;
+    };
+    remotes.iter().flatten().any(|name| {
+        repo.find_remote(name)
+            .ok()
+            .and_then(|remote| remote.url().map(|url| remote_matches_project(url, project)))
+            .unwrap_or(false)
+    })
+}
+
+fn set_bismuth_remote(repo: &Path, project: &api::Project) -> Result<()> {
+    let git_repo = git2::Repository::discover(repo)?;
+    let config = bismuth_toml::parse_config(
+        git_repo
+            .workdir()
+            .ok_or_else(|| anyhow!("Repository has no working directory"))?,
+    )?;
+    let git_url = bismuth_remote_url(project, config.git.remote_auth)?;
+    let remote = config.git.remote_name;
+    match git_repo.find_remote(&remote) {
+        Ok(_) => {
+            debug!("Updating existing {} remote URL", remote);
+            git_repo.remote_set_url(&remote, &git_url)?;
+        }
+        Err(_) => {
+            debug!("Adding new {} remote", remote);
+            git_repo.remote(&remote, &git_url)?;
+        }
+    }
     Ok(())
 }
 
@@ -318,11 +1283,15 @@ async fn get_project_and_feature_for_repo(
     client: &APIClient,
     repo: &Path,
 ) -> Result<(api::Project, api::Feature)> {
+    let remote = bismuth_toml::remote_name(repo);
     let repo = git2::Repository::discover(repo).map_err(|_| {
-        anyhow!("Unable to determine project and feature (path is not a git repository)")
+        CliError::GitError(
+            "Unable to determine project and feature (path is not a git repository)".to_string(),
+        )
     })?;
+    let remote = remote?;
     let remote_url = repo
-        .find_remote("bismuth")
+        .find_remote(&remote)
         .map_err(|e| {
             anyhow!(
                 "You must import this repository to Bismuth before using it ({})",
@@ -332,7 +1301,7 @@ async fn get_project_and_feature_for_repo(
         .url()
         .unwrap()
         .to_string();
-    let branch_name = repo.head()?.shorthand().unwrap().to_string();
+    let branch_name = chat::current_branch(&repo)?;
 
     for project in &client
         .get("/projects/list")
@@ -344,7 +1313,7 @@ async fn get_project_and_feature_for_repo(
         .await?
         .projects
     {
-        if remote_url.contains(&project.clone_token) {
+        if remote_matches_project(&remote_url, project) {
             for feature in &project.features {
                 if branch_name == feature.name {
                     return Ok((project.clone(), feature.clone()));
@@ -373,6 +1342,51 @@ async fn get_project_and_feature_for_repo(
     ))
 }
 
+/// A temporary clone of a remote repository, deleted when dropped.
+struct TempClone {
+    root: PathBuf,
+    repo: PathBuf,
+}
+
+impl Drop for TempClone {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+fn clone_for_import(url: &str) -> Result<TempClone> {
+    let name = url
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(['/', ':'])
+        .next()
+        .filter(|n| !n.is_empty())
+        .ok_or_else(|| anyhow!("Unable to determine repository name from {}", url))?;
+    let root = std::env::temp_dir().join(format!("bismuth-import-{}", std::process::id()));
+    let clone = TempClone {
+        repo: root.join(name),
+        root,
+    };
+    debug!("Cloning {} to {:?}", url, clone.repo);
+
+    Command::new("git")
+        .arg("clone")
+        .arg(url)
+        .arg(&clone.repo)
+        .stdout(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| anyhow!(e))
+        .and_then(|o| {
+            if o.status.success() {
+                Ok(())
+            } else {
+                Err(anyhow!("Failed to clone {} ({})", url, o.status))
+            }
+        })?;
+    Ok(clone)
+}
+
 async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()> {
     let gh_enabled = client
         .get("/projects/connect/github/enabled")
@@ -386,7 +1400,16 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
         .unwrap();
 
     if !args.source.github {
-        let repo = args.source.repo.clone().unwrap_or(PathBuf::from("."));
+        let temp_clone = args
+            .source
+            .url
+            .as_deref()
+            .map(clone_for_import)
+            .transpose()?;
+        let repo = match &temp_clone {
+            Some(clone) => clone.repo.clone(),
+            None => args.source.repo.clone().unwrap_or(PathBuf::from(".")),
+        };
         if !repo.exists() {
             return Err(anyhow!("Repo does not exist"));
         }
@@ -426,27 +1449,29 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
         }
         set_bismuth_remote(&repo, &project)?;
 
-        if args.upload || confirm(
+        // A temporary clone is useless unless it's uploaded
+        if args.upload || temp_clone.is_some() || confirm(
             "Would you like to upload your code to Bismuth Cloud for analysis?\nThis will improve the accuracy and intelligence of Bismuth on your code (but will not be used for training).",
             true,
         )
         .await?
         {
-            if !Command::new("git")
-                .arg("-C")
-                .arg(repo.as_path())
-                .arg("push")
-                .arg("--force")
-                .arg("bismuth")
-                .arg("--all")
-                //.arg("refs/remotes/origin/*")
-                //.arg("refs/heads/*")
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .output()
-                .map_err(|e| anyhow!(e))?.status.success() {
+            let git_config = bismuth_toml::git_config(&repo)?;
+            if !confirm_push_contents(&repo).await? {
+                println!("Skipping upload. You can upload later with `bismuth project upload {}`", project.name);
+            } else if push_to_bismuth(
+                bismuth_git(&project, git_config.remote_auth)
+                    .arg("-C")
+                    .arg(repo.as_path())
+                    .arg("push")
+                    .args(args.force.then_some("--force"))
+                    .arg(&git_config.remote_name)
+                    .args(push_branch_args(&args.branches, &repo)?),
+                    //.arg("refs/remotes/origin/*")
+                    //.arg("refs/heads/*")
+            ).is_err() {
                     if confirm("Failed to push to Bismuth. Would you like to continue without pushing?", true).await? {
-                        println!(
+                        status!(
                             "{}",
                             format!(
                                 "🎉 Successfully created project {}",
@@ -456,7 +1481,7 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
                         );
                         return Ok(());
                     } else {
-                        println!("Cleaning up project...");
+                        status!("Cleaning up project...");
                         client
                             .delete(&format!("/projects/{}", project.id))
                             .send()
@@ -467,7 +1492,7 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
                     }
                 }
         }
-        println!(
+        status!(
             "{}",
             format!(
                 "🎉 Successfully imported {} to project {}",
@@ -495,7 +1520,7 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
             println!("You'll need to install the GitHub App first.");
 
             let url = github_app_url(&client.base_url);
-            if can_launch_browser!() {
+            if can_launch_browser() {
                 press_any_key("Press any key to open the installation page.").await?;
                 open::that_detached(url)?;
             } else {
@@ -505,12 +1530,16 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
                 );
             }
 
-            print!("Waiting for app install");
-            std::io::stdout().flush()?;
+            if !quiet() {
+                print!("Waiting for app install");
+                std::io::stdout().flush()?;
+            }
             loop {
                 tokio::time::sleep(Duration::from_secs(2)).await;
-                print!(".");
-                std::io::stdout().flush()?;
+                if !quiet() {
+                    print!(".");
+                    std::io::stdout().flush()?;
+                }
                 gh_repos = client
                     .get("/projects/connect/github/repo")
                     .send()
@@ -536,20 +1565,33 @@ async fn project_import(args: &cli::ImportArgs, client: &APIClient) -> Result<()
     }
 }
 
-fn project_clone(project: &api::Project, outdir: Option<&Path>) -> Result<PathBuf> {
-    let mut auth_url = GLOBAL_OPTS.get().unwrap().api_url.clone();
-    auth_url.set_username("git").unwrap();
-    auth_url.set_password(Some(&project.clone_token)).unwrap();
-
+/// Clone the project into `outdir` (default the project name), shallowly if `depth` is given.
+fn project_clone(
+    project: &api::Project,
+    outdir: Option<&Path>,
+    depth: Option<u32>,
+) -> Result<PathBuf> {
     let outdir = outdir
         .map(|p| p.to_owned())
         .unwrap_or(PathBuf::from(&project.name));
+    if outdir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        if !is_clone_of(&outdir, project) {
+            return Err(CliError::GitError(format!(
+                "{} already exists and is not a clone of {}",
+                outdir.display(),
+                project.name
+            ))
+            .into());
+        }
+        debug!("{:?} is already a clone, only updating the remote", outdir);
+        set_bismuth_remote(&outdir, project)?;
+        return Ok(outdir);
+    }
     debug!("Cloning project to {:?}", outdir);
 
-    let bismuth_remote_url = auth_url
-        .join(&format!("/git/{}", project.hash))?
-        .to_string();
-
     let clone_url = match &project.github_app_install {
         Some(_) => {
             format!(
@@ -557,11 +1599,12 @@ fn project_clone(project: &api::Project, outdir: Option<&Path>) -> Result<PathBu
                 project.github_repo.as_ref().unwrap()
             )
         }
-        None => bismuth_remote_url.clone(),
+        None => bismuth_remote_url(project, bismuth_toml::RemoteAuth::Token)?,
     };
 
-    Command::new("git")
+    bismuth_git(project, bismuth_toml::RemoteAuth::Token)
         .arg("clone")
+        .args(depth.map(|depth| format!("--depth={}", depth)))
         .arg(&clone_url)
         .arg(&outdir)
         .stdout(std::process::Stdio::inherit())
@@ -576,25 +1619,146 @@ fn project_clone(project: &api::Project, outdir: Option<&Path>) -> Result<PathBu
             }
         })?;
 
+    let config = bismuth_toml::parse_config(&outdir)?;
     let repo = git2::Repository::open(&outdir)?;
-    repo.remote("bismuth", &bismuth_remote_url)?;
+    repo.remote(
+        &config.git.remote_name,
+        &bismuth_remote_url(project, config.git.remote_auth)?,
+    )?;
 
     Ok(outdir)
 }
 
+/// The directory each project is cloned into by `project clone --all`: its name,
+/// with the id appended for projects which share a name.
+fn clone_dir_names(projects: &[api::Project]) -> Vec<String> {
+    projects
+        .iter()
+        .map(|project| {
+            if projects.iter().filter(|p| p.name == project.name).count() > 1 {
+                format!("{}-{}", project.name, project.id)
+            } else {
+                project.name.clone()
+            }
+        })
+        .collect()
+}
+
+/// Clone every project into a subdirectory of `dir`, skipping any that already exist.
+/// Failures don't stop the remaining clones, and are reported together at the end.
+async fn project_clone_all(client: &APIClient, dir: &Path, depth: Option<u32>) -> Result<()> {
+    let projects = list_projects_detailed(client).await?;
+    std::fs::create_dir_all(dir)?;
+
+    let mut cloned = 0;
+    let mut skipped = 0;
+    let mut failed = vec![];
+    for (project, dir_name) in projects.iter().zip(clone_dir_names(&projects)) {
+        let outdir = dir.join(dir_name);
+        if outdir.exists() {
+            status!(
+                "Skipping {}, {} already exists",
+                project.name,
+                outdir.display()
+            );
+            skipped += 1;
+            continue;
+        }
+        status!("Cloning {}", project.name);
+        match project_clone(project, Some(&outdir), depth) {
+            Ok(_) => cloned += 1,
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!("Failed to clone {}: {}", project.name, e).red()
+                );
+                failed.push(project.name.as_str());
+            }
+        }
+    }
+
+    status!(
+        "Cloned {} projects, skipped {}, {} failed",
+        cloned,
+        skipped,
+        failed.len()
+    );
+    if !failed.is_empty() {
+        return Err(CliError::GitError(format!("Failed to clone {}", failed.join(", "))).into());
+    }
+    Ok(())
+}
+
+/// Chat diffs the working tree against the Bismuth branch, which a shallow clone
+/// may not have all of. A shallow clone is also single-branch, so it may not have the branch at all.
+/// Fetch the branch with its full history if its commit isn't available.
+fn deepen_if_needed(project: &api::Project, repo_path: &Path) -> Result<()> {
+    let repo = git2::Repository::open(repo_path)?;
+    if !repo.is_shallow() {
+        return Ok(());
+    }
+    let git_config = bismuth_toml::git_config(repo_path)?;
+    let branch = chat::current_branch(&repo)?;
+    let upstream_ref = format!("refs/remotes/{}/{}", git_config.remote_name, branch);
+    let has_commit = repo
+        .refname_to_id(&upstream_ref)
+        .and_then(|oid| repo.find_commit(oid))
+        .and_then(|commit| commit.tree())
+        .is_ok();
+    if has_commit {
+        debug!("Shallow clone already has the upstream branch");
+        return Ok(());
+    }
+    if repo.refname_to_id(&upstream_ref).is_err() {
+        // A branch that was never pushed has nothing to fetch, and chat explains how to push it
+        let ls_remote = bismuth_git(project, git_config.remote_auth)
+            .arg("-C")
+            .arg(repo_path)
+            .arg("ls-remote")
+            .arg("--exit-code")
+            .arg(&git_config.remote_name)
+            .arg(format!("refs/heads/{}", branch))
+            .output()?;
+        if ls_remote.status.code() == Some(2) {
+            debug!("{} is not on the Bismuth remote, not deepening", branch);
+            return Ok(());
+        }
+    }
+    status!("Fetching full history of {} for this shallow clone", branch);
+    let output = bismuth_git(project, git_config.remote_auth)
+        .arg("-C")
+        .arg(repo_path)
+        .arg("fetch")
+        .arg("--unshallow")
+        .arg(&git_config.remote_name)
+        .arg(format!("+refs/heads/{}:{}", branch, upstream_ref))
+        .output()?;
+    if !output.status.success() {
+        return Err(CliError::GitError(format!(
+            "Failed to fetch {} from Bismuth ({}): {}",
+            branch,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 /// Returns true if the specified repository has changes in the checked out branch
 /// that have not been pushed to a Bismuth remote.
 fn check_not_pushed(repo: &Path, project: &api::Project, feature: &api::Feature) -> Result<bool> {
     let repo = git2::Repository::discover(repo)?;
     let origin_url = repo.find_remote("origin")?.url().unwrap().to_string();
-    if origin_url.contains(&project.clone_token) {
+    if is_bismuth_remote(&origin_url, project) {
         return Ok(false);
     }
 
-    let remote_url = repo.find_remote("bismuth")?.url().unwrap().to_string();
-    let branch_name = repo.head()?.shorthand().unwrap().to_string();
+    let remote = bismuth_toml::remote_name(repo.workdir().unwrap())?;
+    let remote_url = repo.find_remote(&remote)?.url().unwrap().to_string();
+    let branch_name = chat::current_branch(&repo)?;
 
-    if !remote_url.contains(&project.clone_token) {
+    if !remote_matches_project(&remote_url, project) {
         return Err(anyhow!("Repository does not correspond to project"));
     }
 
@@ -612,21 +1776,194 @@ fn check_not_pushed(repo: &Path, project: &api::Project, feature: &api::Feature)
         .ok_or(anyhow!("No such branch in origin remote?"))?;
     let bismuth_commit = repo
         .find_branch(
-            &format!("bismuth/{}", &branch_name),
+            &format!("{}/{}", &remote, &branch_name),
             git2::BranchType::Remote,
         )?
         .get()
         .target()
-        .ok_or(anyhow!("No such branch in bismuth remote?"))?;
+        .ok_or(anyhow!("No such branch in {} remote?", remote))?;
 
     Ok(origin_commit != bismuth_commit)
 }
 
+async fn get_feature_config(
+    client: &APIClient,
+    project: &api::Project,
+    feature: &api::Feature,
+) -> Result<Vec<api::FeatureConfig>> {
+    Ok(client
+        .get(&format!(
+            "/projects/{}/features/{}/config",
+            project.id, feature.id
+        ))
+        .send()
+        .await?
+        .error_body_for_status()
+        .await?
+        .json()
+        .await?)
+}
+
+const FEATURE_CONFIG_UPDATE_ATTEMPTS: usize = 3;
+
+/// Read the feature's config, apply `update` to it, and write the whole list back.
+///
+/// The API only supports replacing the whole list, so there is a window between the read and the write
+/// where a concurrent update could be lost. To close it, the write is conditional on the ETag from the read
+/// (`If-Match`), and if the config changed in between (412), `update` is re-applied to the fresh config.
+/// If the server doesn't send an ETag the write is unconditional, and the last writer wins.
+async fn update_feature_config(
+    client: &APIClient,
+    project: &api::Project,
+    feature: &api::Feature,
+    mut update: impl FnMut(&mut Vec<api::FeatureConfig>) -> Result<()>,
+) -> Result<()> {
+    let path = format!("/projects/{}/features/{}/config", project.id, feature.id);
+    for _ in 0..FEATURE_CONFIG_UPDATE_ATTEMPTS {
+        let resp = client
+            .get(&path)
+            .send()
+            .await?
+            .error_body_for_status()
+            .await?;
+        let etag = resp.headers().get(reqwest::header::ETAG).cloned();
+        let mut feature_config: Vec<api::FeatureConfig> = resp.json().await?;
+        update(&mut feature_config)?;
+
+        let mut req = client.post(&path).json(&feature_config);
+        if let Some(etag) = etag {
+            req = req.header(reqwest::header::IF_MATCH, etag);
+        }
+        let resp = req.send().await?;
+        if resp.status() == reqwest::StatusCode::PRECONDITION_FAILED {
+            debug!("Feature config changed during update, retrying");
+            continue;
+        }
+        resp.error_body_for_status().await?;
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Feature config is being modified concurrently, please try again"
+    ))
+}
+
+fn set_feature_config(feature_config: &mut Vec<api::FeatureConfig>, key: &str, value: &str) {
+    feature_config.retain(|c| c.key != key);
+    feature_config.push(api::FeatureConfig {
+        key: key.to_string(),
+        value: value.to_string(),
+    });
+}
+
+/// Parse the contents of a .env file.
+/// Supports comments, `export` prefixes, and single or double quoted values.
+fn parse_dotenv(contents: &str) -> Result<Vec<cli::EnvVar>> {
+    let mut vars = vec![];
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Line {}: expected KEY=VALUE", lineno + 1))?;
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some('r') => unescaped.push('\r'),
+                        Some(c) => unescaped.push(c),
+                        None => unescaped.push('\\'),
+                    },
+                    Some(c) => unescaped.push(c),
+                    None => return Err(anyhow!("Line {}: unterminated quote", lineno + 1)),
+                }
+            }
+            check_dotenv_trailing(chars.as_str(), lineno)?;
+            unescaped
+        } else if let Some(quoted) = value.strip_prefix('\'') {
+            let (quoted, rest) = quoted
+                .split_once('\'')
+                .ok_or_else(|| anyhow!("Line {}: unterminated quote", lineno + 1))?;
+            check_dotenv_trailing(rest, lineno)?;
+            quoted.to_string()
+        } else {
+            value
+                .split_once(" #")
+                .map_or(value, |(v, _)| v)
+                .trim_end()
+                .to_string()
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(anyhow!("Line {}: missing key", lineno + 1));
+        }
+        vars.push(cli::EnvVar {
+            key: key.to_string(),
+            value,
+        });
+    }
+    Ok(vars)
+}
+
+/// Only a comment may follow a quoted .env value.
+fn check_dotenv_trailing(rest: &str, lineno: usize) -> Result<()> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Line {}: unexpected text after closing quote",
+            lineno + 1
+        ))
+    }
+}
+
+/// The variables to set before deploying, with --env taking precedence over --env-file.
+fn deploy_env_vars(env: &cli::DeployEnv) -> Result<Vec<cli::EnvVar>> {
+    let mut vars = match &env.env_file {
+        Some(file) => parse_dotenv(&std::fs::read_to_string(file)?)
+            .map_err(|e| anyhow!("{}: {}", file.display(), e))?,
+        None => vec![],
+    };
+    vars.extend(env.vars.iter().cloned());
+    Ok(vars)
+}
+
+/// Quote `value` for a .env file if it contains anything other than simple characters.
+fn dotenv_value(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@,+".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!(
+            "\"{}\"",
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        )
+    }
+}
+
 async fn feature_deploy(
     project: &api::Project,
     feature: &api::Feature,
     client: &APIClient,
     timeout: Option<Duration>,
+    poll_interval: Duration,
+    force: bool,
+    env: &[cli::EnvVar],
 ) -> Result<()> {
     if let Ok(true) = check_not_pushed(&std::env::current_dir()?, project, feature) {
         println!(
@@ -635,81 +1972,156 @@ async fn feature_deploy(
                 .yellow()
         );
         if confirm("Would you like to push changes now?", true).await? {
-            Command::new("git")
-                .arg("push")
-                .arg("--force")
-                .arg("bismuth")
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .output()
-                .map_err(|e| anyhow!(e))
-                .and_then(|o| {
-                    if o.status.success() {
-                        Ok(())
-                    } else {
-                        Err(anyhow!("Failed to push to Bismuth"))
-                    }
-                })?;
+            let git_config = bismuth_toml::git_config(&std::env::current_dir()?)?;
+            push_to_bismuth(
+                bismuth_git(project, git_config.remote_auth)
+                    .arg("push")
+                    .args(force.then_some("--force"))
+                    .arg(&git_config.remote_name),
+            )?;
         }
     }
 
-    client
-        .post(&format!(
-            "/projects/{}/features/{}/deploy",
-            project.id, feature.id
-        ))
-        .send()
-        .await?
-        .error_body_for_status()
+    let mut previous_config = vec![];
+    if !env.is_empty() {
+        update_feature_config(client, project, feature, |feature_config| {
+            previous_config = feature_config.clone();
+            for var in env {
+                set_feature_config(feature_config, &var.key, &var.value);
+            }
+            Ok(())
+        })
         .await?;
-
-    if timeout.is_none() {
-        return Ok(());
     }
 
-    print!("Waiting for deployment to be healthy");
-    std::io::stdout().flush()?;
-    for _ in 0..timeout.unwrap().as_secs() {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        print!(".");
-        std::io::stdout().flush()?;
-
-        let status: api::DeployStatusResponse = client
-            .get(&format!(
-                "/projects/{}/features/{}/deploy/status",
+    let deploy = async {
+        client
+            .post(&format!(
+                "/projects/{}/features/{}/deploy",
                 project.id, feature.id
             ))
             .send()
             .await?
             .error_body_for_status()
-            .await?
-            .json()
-            .await?;
+            .await
+    };
+    if let Err(e) = deploy.await {
+        if !env.is_empty() {
+            // Only undo the keys we set, so unrelated concurrent changes are kept
+            let rollback = update_feature_config(client, project, feature, |feature_config| {
+                for var in env {
+                    match previous_config.iter().find(|c| c.key == var.key) {
+                        Some(prev) => set_feature_config(feature_config, &prev.key, &prev.value),
+                        None => feature_config.retain(|c| c.key != var.key),
+                    }
+                }
+                Ok(())
+            })
+            .await;
+            if let Err(rollback_err) = rollback {
+                eprintln!(
+                    "{}",
+                    format!("Failed to roll back config changes: {}", rollback_err).red()
+                );
+            }
+        }
+        return Err(e);
+    }
 
-        match status.status {
-            api::ContainerState::Running => {
-                let url = feature_get_url(project, feature, client).await?;
-                println!("\nDeployed to {}", url);
-                return Ok(());
+    let Some(timeout) = timeout else {
+        return Ok(());
+    };
+
+    let show_spinner = std::io::stderr().is_terminal() && !quiet();
+    let clear_spinner = || {
+        if show_spinner {
+            eprint!("\r\x1b[2K");
+        }
+    };
+    if !show_spinner {
+        status!("Waiting for deployment to be healthy");
+    }
+    let start = tokio::time::Instant::now();
+    let mut spinner = tokio::time::interval(Duration::from_millis(100));
+    // Each wait is cut short at the deadline, so there's always a final check before timing out
+    let deadline = start + timeout;
+    let poll = tokio::time::sleep_until((start + poll_interval).min(deadline));
+    tokio::pin!(poll);
+    let mut frame = 0;
+    let mut last_state = None;
+    loop {
+        tokio::select! {
+            _ = spinner.tick(), if show_spinner => {
+                eprint!(
+                    "\r{} Waiting for deployment to be healthy ({}s)",
+                    SPINNER_FRAMES[frame % SPINNER_FRAMES.len()],
+                    start.elapsed().as_secs()
+                );
+                frame += 1;
             }
-            api::ContainerState::Failed => {
-                // TODO: print logs?
-                return Err(anyhow!("Deployment failed"));
+            _ = &mut poll => {
+                let status: api::DeployStatusResponse = client
+                    .get(&format!(
+                        "/projects/{}/features/{}/deploy/status",
+                        project.id, feature.id
+                    ))
+                    .send()
+                    .await?
+                    .error_body_for_status()
+                    .await?
+                    .json()
+                    .await?;
+
+                if last_state != Some(status.status) {
+                    clear_spinner();
+                    status!(
+                        "{} {} ({}s)",
+                        "→".bold(),
+                        status.status,
+                        start.elapsed().as_secs()
+                    );
+                    last_state = Some(status.status);
+                }
+
+                match status.status {
+                    api::ContainerState::Running => {
+                        clear_spinner();
+                        let url = feature_get_url(project, feature, client).await?;
+                        if quiet() {
+                            println!("{}", url);
+                        } else {
+                            println!("Deployed to {}", url);
+                        }
+                        return Ok(());
+                    }
+                    api::ContainerState::Failed => {
+                        clear_spinner();
+                        // TODO: print logs?
+                        return Err(CliError::DeployFailed("Deployment failed".to_string()).into());
+                    }
+                    _ => {}
+                }
+
+                let now = tokio::time::Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                poll.as_mut().reset((now + poll_interval).min(deadline));
             }
-            _ => {}
         }
     }
 
-    println!("");
+    clear_spinner();
 
     Err(anyhow!("Timed out waiting for deployment"))
 }
 
-async fn feature_deploy_status(
+/// The current deployment status, or None if the feature isn't deployed.
+async fn get_deploy_status(
     project: &api::Project,
     feature: &api::Feature,
     client: &APIClient,
-) -> Result<()> {
+) -> Result<Option<api::DeployStatusResponse>> {
     let resp = client
         .get(&format!(
             "/projects/{}/features/{}/deploy/status",
@@ -718,49 +2130,435 @@ async fn feature_deploy_status(
         .send()
         .await?;
     if resp.status().as_u16() == 404 {
-        println!("Status: Not Deployed");
+        return Ok(None);
+    }
+    Ok(Some(resp.error_body_for_status().await?.json().await?))
+}
+
+/// List the features of `projects`, optionally with their deployment status.
+/// With `qualified`, features are printed as project/feature.
+async fn feature_list(
+    client: &APIClient,
+    projects: &[api::Project],
+    qualified: bool,
+    status: bool,
+    output: cli::OutputFormat,
+) -> Result<()> {
+    let features: Vec<_> = projects
+        .iter()
+        .flat_map(|project| {
+            project
+                .features
+                .iter()
+                .map(move |feature| (project, feature))
+        })
+        .collect();
+    let statuses: Vec<Option<String>> = if status {
+        fetch_concurrently(concurrency(), &features, |(project, feature)| async move {
+            Ok(get_deploy_status(project, feature, client)
+                .await?
+                .map(|s| s.status.to_string()))
+        })
+        .await?
+    } else {
+        vec![None; features.len()]
+    };
+
+    if let cli::OutputFormat::Json = output {
+        let json: Vec<_> = projects
+            .iter()
+            .map(|project| {
+                let features: Vec<_> = features
+                    .iter()
+                    .zip(&statuses)
+                    .filter(|((p, _), _)| p.id == project.id)
+                    .map(|((_, feature), deploy_status)| {
+                        let mut json = json!({"id": feature.id, "name": feature.name});
+                        if status {
+                            json["status"] = json!(deploy_status);
+                        }
+                        json
+                    })
+                    .collect();
+                json!({"id": project.id, "name": project.name, "features": features})
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&json)?);
         return Ok(());
     }
-    let status: api::DeployStatusResponse = resp.error_body_for_status().await?.json().await?;
-    println!("Status: {:?}", status.status);
-    println!("Deployed Commit: {}", status.commit);
+
+    for ((project, feature), deploy_status) in features.iter().zip(&statuses) {
+        let name = if qualified {
+            format!("{}/{}", project.name, feature.name)
+        } else {
+            feature.name.clone()
+        };
+        if status {
+            println!(
+                "{}\t{}",
+                name,
+                deploy_status.as_deref().unwrap_or("Not Deployed")
+            );
+        } else {
+            println!("{}", name);
+        }
+    }
+    Ok(())
+}
+
+fn deploy_status_name(status: &Option<api::DeployStatusResponse>) -> String {
+    match status {
+        Some(status) => status.status.to_string(),
+        None => "Not Deployed".to_string(),
+    }
+}
+
+fn deploy_status_json(status: &Option<api::DeployStatusResponse>) -> serde_json::Value {
+    json!({
+        "status": deploy_status_name(status),
+        "commit": status.as_ref().map(|s| &s.commit),
+    })
+}
+
+/// Print the deployment status.
+/// With `watch`, keep checking on that interval until the deployment is running or has failed.
+/// With `fail_if_not_running`, the final status must be Running.
+async fn feature_deploy_status(
+    project: &api::Project,
+    feature: &api::Feature,
+    client: &APIClient,
+    watch: Option<Duration>,
+    output: cli::OutputFormat,
+    fail_if_not_running: bool,
+) -> Result<()> {
+    let json = matches!(output, cli::OutputFormat::Json);
+    let status = match watch {
+        None => {
+            let status = get_deploy_status(project, feature, client).await?;
+            match &status {
+                _ if json => println!("{}", deploy_status_json(&status)),
+                Some(status) => {
+                    println!("Status: {}", status.status);
+                    println!("Deployed Commit: {}", status.commit);
+                }
+                None => println!("Status: Not Deployed"),
+            }
+            status
+        }
+        Some(poll_interval) => {
+            let redraw = std::io::stdout().is_terminal() && !json;
+            let mut last_line = String::new();
+            let status = loop {
+                let status = get_deploy_status(project, feature, client).await?;
+                let line = match &status {
+                    _ if json => deploy_status_json(&status).to_string(),
+                    Some(status) => format!(
+                        "Status: {}  Deployed Commit: {}",
+                        status.status, status.commit
+                    ),
+                    None => "Status: Not Deployed".to_string(),
+                };
+                if redraw {
+                    print!("\r\x1b[2K{}", line);
+                    std::io::stdout().flush()?;
+                } else if line != last_line {
+                    println!("{}", line);
+                }
+                last_line = line;
+
+                if let Some(api::DeployStatusResponse {
+                    status: api::ContainerState::Running | api::ContainerState::Failed,
+                    ..
+                }) = status
+                {
+                    break status;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(poll_interval) => {}
+                    _ = tokio::signal::ctrl_c() => break status,
+                }
+            };
+            if redraw {
+                println!();
+            }
+            status
+        }
+    };
+
+    if fail_if_not_running {
+        match status.as_ref().map(|s| s.status) {
+            Some(api::ContainerState::Running) => {}
+            Some(api::ContainerState::Failed) => {
+                return Err(CliError::DeployFailed("Deployment failed".to_string()).into())
+            }
+            _ => {
+                return Err(CliError::NotRunning(format!(
+                    "Deployment is not running ({})",
+                    deploy_status_name(&status)
+                ))
+                .into())
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn feature_teardown(
+    project: &api::Project,
+    feature: &api::Feature,
+    client: &APIClient,
+) -> Result<()> {
+    client
+        .delete(&format!(
+            "/projects/{}/features/{}/deploy",
+            project.id, feature.id
+        ))
+        .send()
+        .await?
+        .error_body_for_status()
+        .await?;
+    Ok(())
+}
+
+async fn feature_get_url(
+    project: &api::Project,
+    feature: &api::Feature,
+    client: &APIClient,
+) -> Result<String> {
+    let resp: api::InvokeURLResponse = client
+        .get(&format!(
+            "/projects/{}/features/{}/invoke_url",
+            project.id, feature.id
+        ))
+        .send()
+        .await?
+        .error_body_for_status()
+        .await?
+        .json()
+        .await?;
+    Ok(resp.url)
+}
+
+/// A value in a `kv export` file. Values that aren't valid UTF-8 are base64 encoded.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum KVExportValue {
+    Text(String),
+    Binary { base64: String },
+}
+
+impl KVExportValue {
+    fn new(value: Vec<u8>) -> Self {
+        use base64::Engine as _;
+        match String::from_utf8(value) {
+            Ok(text) => KVExportValue::Text(text),
+            Err(e) => KVExportValue::Binary {
+                base64: base64::engine::general_purpose::STANDARD.encode(e.into_bytes()),
+            },
+        }
+    }
+
+    fn into_bytes(self) -> Result<Vec<u8>> {
+        use base64::Engine as _;
+        match self {
+            KVExportValue::Text(text) => Ok(text.into_bytes()),
+            KVExportValue::Binary { base64 } => {
+                Ok(base64::engine::general_purpose::STANDARD.decode(base64)?)
+            }
+        }
+    }
+}
+
+async fn kv_list(
+    client: &APIClient,
+    project: &api::Project,
+    feature: &api::Feature,
+) -> Result<HashMap<String, Vec<u8>>> {
+    Ok(client
+        .get(&format!(
+            "/projects/{}/features/{}/svcprovider/kv/v1/",
+            project.id, feature.id
+        ))
+        .send()
+        .await?
+        .error_body_for_status()
+        .await?
+        .json()
+        .await?)
+}
+
+/// Write all keys as a JSON object in key order.
+/// The KV list endpoint returns every entry in one response, so they're all held in memory.
+async fn kv_export(
+    client: &APIClient,
+    project: &api::Project,
+    feature: &api::Feature,
+    output: Option<&Path>,
+) -> Result<()> {
+    let mut entries: Vec<_> = kv_list(client, project, feature)
+        .await?
+        .into_iter()
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let count = entries.len();
+
+    let mut out: Box<dyn std::io::Write> = match output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    write!(out, "{{")?;
+    for (i, (key, value)) in entries.into_iter().enumerate() {
+        write!(
+            out,
+            "{}\n  {}: {}",
+            if i == 0 { "" } else { "," },
+            serde_json::to_string(&key)?,
+            serde_json::to_string(&KVExportValue::new(value))?
+        )?;
+    }
+    writeln!(out, "\n}}")?;
+    out.flush()?;
+
+    if let Some(path) = output {
+        status!("Exported {} keys to {}", count, path.display());
+    }
+    Ok(())
+}
+
+async fn kv_import(
+    client: &APIClient,
+    project: &api::Project,
+    feature: &api::Feature,
+    input: &Path,
+    overwrite: bool,
+    skip_existing: bool,
+) -> Result<()> {
+    let entries: HashMap<String, KVExportValue> =
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(input)?))
+            .map_err(|e| anyhow!("Invalid KV export {}: {}", input.display(), e))?;
+
+    let existing: HashSet<String> = kv_list(client, project, feature)
+        .await?
+        .into_keys()
+        .collect();
+    if !overwrite && !skip_existing {
+        let mut conflicts: Vec<_> = entries
+            .keys()
+            .filter(|key| existing.contains(*key))
+            .collect();
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            return Err(anyhow!(
+                "Keys already exist: {}. Use --overwrite or --skip-existing",
+                conflicts
+                    .iter()
+                    .map(|key| key.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+    let mut imported = 0;
+    let mut skipped = 0;
+    for (key, value) in entries {
+        if skip_existing && existing.contains(&key) {
+            skipped += 1;
+            continue;
+        }
+        client
+            .post(&format!(
+                "/projects/{}/features/{}/svcprovider/kv/v1/{}",
+                project.id, feature.id, key
+            ))
+            .body(value.into_bytes()?)
+            .send()
+            .await?
+            .error_body_for_status()
+            .await?;
+        imported += 1;
+    }
+    status!("Imported {} keys ({} skipped)", imported, skipped);
+    Ok(())
+}
+
+/// Print the size, type, and modification time of a stored value from its response headers.
+/// Uses a HEAD request so the value itself is never downloaded, unless HEAD isn't supported.
+async fn print_object_metadata(client: &APIClient, path: &str) -> Result<()> {
+    let resp = client.head(path).send().await?;
+    let resp = if resp.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+        // Only the headers are read, the body is dropped unread
+        client.get(path).send().await?
+    } else {
+        resp
+    };
+    let resp = resp.error_body_for_status().await?;
+    let header = |name: reqwest::header::HeaderName| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    match header(reqwest::header::CONTENT_LENGTH) {
+        Some(length) => println!("Size: {} bytes", length),
+        None => println!("Size: unknown"),
+    }
+    if let Some(content_type) = header(reqwest::header::CONTENT_TYPE) {
+        println!("Content-Type: {}", content_type);
+    }
+    if let Some(last_modified) = header(reqwest::header::LAST_MODIFIED) {
+        println!("Last-Modified: {}", last_modified);
+    }
+    Ok(())
+}
+
+/// Print the feature's URL, or open it in a browser if possible when `open` is set.
+fn show_feature_url(url: String, open: bool, json: bool) -> Result<()> {
+    let open = open && can_launch_browser();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&api::InvokeURLResponse { url: url.clone() })?
+        );
+    } else if !open {
+        println!("{}", url);
+    }
+    if open {
+        open::that_detached(url)?;
+    }
     Ok(())
 }
 
-async fn feature_teardown(
+async fn feature_ping(
     project: &api::Project,
     feature: &api::Feature,
     client: &APIClient,
+    path: &str,
+    expect_status: Option<u16>,
 ) -> Result<()> {
-    client
-        .delete(&format!(
-            "/projects/{}/features/{}/deploy",
-            project.id, feature.id
-        ))
-        .send()
-        .await?
-        .error_body_for_status()
-        .await?;
-    Ok(())
-}
+    let mut url = Url::parse(&feature_get_url(project, feature, client).await?)?;
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    let url = url.join(path.trim_start_matches('/'))?;
+    let start = std::time::Instant::now();
+    let resp = client.client.get(url.clone()).send().await?;
+    let latency = start.elapsed();
+    let status = resp.status();
 
-async fn feature_get_url(
-    project: &api::Project,
-    feature: &api::Feature,
-    client: &APIClient,
-) -> Result<String> {
-    let resp: api::InvokeURLResponse = client
-        .get(&format!(
-            "/projects/{}/features/{}/invoke_url",
-            project.id, feature.id
-        ))
-        .send()
-        .await?
-        .error_body_for_status()
-        .await?
-        .json()
-        .await?;
-    Ok(resp.url)
+    let summary = format!("{} {} in {}ms", url, status, latency.as_millis());
+    match expect_status {
+        Some(expected) if status.as_u16() != expected => {
+            Err(anyhow!("{} (expected {})", summary, expected))
+        }
+        _ if status.is_success() => {
+            println!("{}", summary.green());
+            Ok(())
+        }
+        _ => {
+            println!("{}", summary.yellow());
+            Ok(())
+        }
+    }
 }
 
 async fn feature_logs(
@@ -770,10 +2568,14 @@ async fn feature_logs(
     client: &APIClient,
 ) -> Result<()> {
     if follow {
-        let mut es = EventSource::new(client.get(&format!(
-            "/projects/{}/features/{}/logs/streaming",
-            project.id, feature.id
-        )))?;
+        let mut es = EventSource::new(
+            client
+                .get(&format!(
+                    "/projects/{}/features/{}/logs/streaming",
+                    project.id, feature.id
+                ))
+                .into_inner(),
+        )?;
 
         while let Some(event) = es.next().await {
             match event {
@@ -809,6 +2611,9 @@ async fn feature_logs(
 }
 
 fn oidc_url(api_url: &Url) -> Url {
+    if let Some(url) = GLOBAL_OPTS.get().and_then(|opts| opts.oidc_url.clone()) {
+        return url;
+    }
     let base = match api_url.host_str() {
         Some("localhost") => Url::parse("http://localhost:8543/").unwrap(),
         Some("api-staging.bismuth.cloud") => {
@@ -834,7 +2639,7 @@ async fn oidc_server(api_url: &Url) -> Result<String> {
         .unwrap()
         .to_string();
 
-    if can_launch_browser!() {
+    if can_launch_browser() {
         press_any_key("Press any key to open the login page.").await?;
         open::that_detached(url)?;
     } else {
@@ -861,7 +2666,7 @@ async fn oidc_server(api_url: &Url) -> Result<String> {
         .unwrap()
         .to_string();
     debug!("Got code: {}", code);
-    let client = reqwest::Client::new();
+    let client = http_client()?;
     let tokens: Tokens = client
         .post(oidc_url(api_url).join("token").unwrap())
         .header("Content-Type", "application/x-www-form-urlencoded")
@@ -898,42 +2703,205 @@ async fn oidc_server(api_url: &Url) -> Result<String> {
     Ok(api_key)
 }
 
-async fn check_version() -> Result<()> {
-    let client = reqwest::Client::new();
+async fn fetch_latest_version(timeout: Duration) -> Result<String> {
+    let client = http_client()?;
     let resp = client
         .get("https://bismuthcloud.github.io/cli/LATEST")
-        .timeout(Duration::from_secs(1))
+        .timeout(timeout)
         .send()
-        .await?;
-    if let [latest_maj, latest_min, latest_patch] = resp
-        .text()
         .await?
-        .trim()
+        .error_for_status()?;
+    Ok(resp.text().await?.trim().to_string())
+}
+
+fn parse_version(version: &str) -> Result<(u64, u64, u64)> {
+    match version
         .split('.')
         .map(|s| -> Result<u64> { Ok(s.split('-').next().unwrap().parse::<u64>()?) })
         .collect::<Result<Vec<u64>, _>>()?
         .as_slice()
     {
-        if let [this_maj, this_min, this_patch] = env!("CARGO_PKG_VERSION")
-            .split('.')
-            .map(|s| -> Result<u64> { Ok(s.split('-').next().unwrap().parse::<u64>()?) })
-            .collect::<Result<Vec<u64>, _>>()?
-            .as_slice()
-        {
-            if latest_maj > this_maj || latest_min > this_min || latest_patch > this_patch {
-                eprintln!("{}", "A newer version of the CLI is available!".yellow());
-                eprintln!(
-                    "{}",
-                    "Get it at https://github.com/BismuthCloud/cli/releases".yellow()
-                );
-            }
+        [maj, min, patch] => Ok((*maj, *min, *patch)),
+        _ => Err(anyhow!("Invalid version {}", version)),
+    }
+}
+
+fn is_newer_version(latest: &str) -> Result<bool> {
+    Ok(parse_version(latest)? > parse_version(env!("CARGO_PKG_VERSION"))?)
+}
+
+/// How often `check_version` actually hits the network.
+const VERSION_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct VersionCheckCache {
+    latest: String,
+    /// Unix timestamp of the last fetch
+    checked_at: u64,
+}
+
+fn version_check_cache_file() -> PathBuf {
+    GLOBAL_OPTS
+        .get()
+        .unwrap()
+        .config_file
+        .with_file_name("bismuth-version-check.json")
+}
+
+async fn check_version() -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let cache_file = version_check_cache_file();
+    let cached = std::fs::read_to_string(&cache_file)
+        .ok()
+        .and_then(|s| serde_json::from_str::<VersionCheckCache>(&s).ok())
+        .filter(|c| now.saturating_sub(c.checked_at) < VERSION_CHECK_INTERVAL.as_secs());
+    let latest = match cached {
+        Some(cached) => cached.latest,
+        None => {
+            let latest = fetch_latest_version(Duration::from_secs(1)).await?;
+            let _ = std::fs::write(
+                &cache_file,
+                serde_json::to_string(&VersionCheckCache {
+                    latest: latest.clone(),
+                    checked_at: now,
+                })?,
+            );
+            latest
         }
+    };
+    if is_newer_version(&latest)? {
+        eprintln!("{}", "A newer version of the CLI is available!".yellow());
+        eprintln!("{}", "Run `bismuth update` to install it".yellow());
+    }
+    Ok(())
+}
+
+/// The target triple of the release asset for this platform.
+fn release_target() -> Result<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => Ok("x86_64-unknown-linux-gnu"),
+        ("linux", "aarch64") => Ok("aarch64-unknown-linux-gnu"),
+        ("macos", "x86_64") => Ok("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Ok("aarch64-apple-darwin"),
+        ("windows", "x86_64") => Ok("x86_64-pc-windows-gnu"),
+        (os, arch) => Err(anyhow!(
+            "No prebuilt release for {}-{}. Build from source instead",
+            os,
+            arch
+        )),
+    }
+}
+
+fn permission_hint(e: std::io::Error, path: &Path) -> anyhow::Error {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        anyhow!(
+            "Permission denied writing to {}. The CLI is probably installed in a system directory, try re-running with sudo.",
+            path.display()
+        )
+    } else {
+        anyhow!(e)
+    }
+}
+
+/// Download the latest release and replace the running binary with it.
+async fn self_update(check_only: bool) -> Result<()> {
+    let latest = fetch_latest_version(Duration::from_secs(10)).await?;
+    if !is_newer_version(&latest)? {
+        println!("Already up to date ({})", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if check_only {
+        println!(
+            "Update available: {} -> {}",
+            env!("CARGO_PKG_VERSION"),
+            latest
+        );
+        return Ok(());
+    }
+
+    let url = format!(
+        "https://github.com/BismuthCloud/cli/releases/download/v{}/bismuthcli.{}",
+        latest,
+        release_target()?
+    );
+    status!("Downloading {}", url);
+    let binary = http_client()?
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| anyhow!("Failed to download update: {}", e))?
+        .bytes()
+        .await?;
+
+    let exe = std::fs::canonicalize(std::env::current_exe()?)?;
+    let new_exe = exe.with_extension("update");
+    std::fs::write(&new_exe, &binary).map_err(|e| permission_hint(e, &new_exe))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        std::fs::set_permissions(&new_exe, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Make sure the download is actually a working CLI of the expected version before swapping it in
+    let verified = Command::new(&new_exe)
+        .arg("version")
+        .env("BISMUTH_NO_VERSION_CHECK", "1")
+        .output()
+        .map(|o| o.status.success() && String::from_utf8_lossy(&o.stdout).contains(&latest))
+        .unwrap_or(false);
+    if !verified {
+        let _ = std::fs::remove_file(&new_exe);
+        return Err(anyhow!(
+            "Downloaded binary failed verification, not updating"
+        ));
     }
+
+    if cfg!(windows) {
+        // Windows can't overwrite a running executable, but it can rename it
+        let old_exe = exe.with_extension("old");
+        let _ = std::fs::remove_file(&old_exe);
+        std::fs::rename(&exe, &old_exe).map_err(|e| permission_hint(e, &exe))?;
+    }
+    std::fs::rename(&new_exe, &exe).map_err(|e| permission_hint(e, &exe))?;
+
+    println!("{}", format!("Updated to {}", latest).green());
     Ok(())
 }
 
+/// Load the [backend] overrides from the bismuth.toml of the repository being operated on, if any.
+fn repo_backend_config(command: &cli::Command) -> Option<bismuth_toml::BackendConfig> {
+    let path = match command {
+        // Reports errors itself
+        cli::Command::Validate { .. } => return None,
+        cli::Command::Chat {
+            repo: Some(repo), ..
+        } => repo.clone(),
+        _ => std::env::current_dir().ok()?,
+    };
+    let repo = git2::Repository::discover(path).ok()?;
+    let root = repo.workdir()?;
+    match bismuth_toml::parse_config(root) {
+        Ok(mut config) => {
+            config.backend.ca_cert = config.backend.ca_cert.map(|path| root.join(path));
+            Some(config.backend)
+        }
+        Err(e) => {
+            eprintln!(
+                "{}",
+                format!("Ignoring invalid bismuth.toml: {}", e).yellow()
+            );
+            None
+        }
+    }
+}
+
 async fn _main() -> Result<()> {
-    let args = Cli::parse();
+    let matches = Cli::command().get_matches();
+    JSON_ERRORS.store(matches.get_flag("json_errors"), Ordering::Relaxed);
+    let mut args = Cli::from_arg_matches(&matches)?;
 
     if args.markdown_help {
         clap_markdown::print_help_markdown::<Cli>();
@@ -941,13 +2909,48 @@ async fn _main() -> Result<()> {
         return Ok(());
     }
 
+    let profile = active_profile(&args.global)?;
+    if matches.value_source("config_file") != Some(clap::parser::ValueSource::CommandLine) {
+        args.global.config_file = cli::profile_config_file(&profile);
+    }
+
+    let backend = repo_backend_config(&args.command);
+    // An explicit --api-url always wins over the repository's bismuth.toml,
+    // which wins over the API the profile logged in to
+    if matches.value_source("api_url") != Some(clap::parser::ValueSource::CommandLine) {
+        if let Some(api_url) = backend.as_ref().and_then(|b| b.api_url.clone()) {
+            args.global.api_url = api_url;
+        } else if std::env::var("BISMUTH_API").is_err() {
+            if let Some(api_url) = stored_api_url(&args.global.config_file) {
+                args.global.api_url = api_url;
+            }
+        }
+    }
+
+    if args.global.ca_cert.is_none() {
+        args.global.ca_cert = backend.and_then(|b| b.ca_cert);
+    }
+    if args.global.insecure {
+        eprintln!(
+            "{}",
+            "Warning: TLS certificate verification is disabled (--insecure)".yellow()
+        );
+    }
+
     GLOBAL_OPTS.set(args.global.clone()).unwrap();
 
-    env_logger::Builder::new()
-        .filter_level(args.global.verbose.log_level_filter())
-        .init();
+    let mut logger = env_logger::Builder::new();
+    logger.filter_level(args.global.verbose.log_level_filter());
+    if args.global.verbose.log_level() >= Some(log::Level::Info) {
+        logger.filter_module(HTTP_LOG_TARGET, log::LevelFilter::Trace);
+    }
+    logger.init();
+
+    if let cli::Command::Update { check_only } = args.command {
+        return self_update(check_only).await;
+    }
 
-    if std::env::var("BISMUTH_NO_VERSION_CHECK").is_err() {
+    if std::env::var("BISMUTH_NO_VERSION_CHECK").is_err() && !args.global.quiet() {
         let _ = check_version().await;
     }
 
@@ -960,6 +2963,52 @@ async fn _main() -> Result<()> {
         return Ok(());
     }
 
+    if let cli::Command::Validate { repo } = &args.command {
+        let repo = repo.clone().unwrap_or(std::env::current_dir()?);
+        let repo_root = git2::Repository::discover(&repo)
+            .ok()
+            .and_then(|r| r.workdir().map(Path::to_path_buf))
+            .unwrap_or(repo);
+        let warnings = bismuth_toml::lint_config(&repo_root)
+            .map_err(|e| anyhow!("Invalid bismuth.toml: {}", e))?;
+        for warning in &warnings {
+            println!("{}", format!("Warning: {}", warning).yellow());
+        }
+        println!("{}", "bismuth.toml is valid".green());
+        return Ok(());
+    }
+
+    if let cli::Command::Profile { command } = &args.command {
+        match command {
+            cli::ProfileCommand::List => {
+                let profiles = list_profiles();
+                if profiles.is_empty() {
+                    println!("No profiles. Run `bismuth login` to create one.");
+                }
+                for name in profiles {
+                    if name == profile {
+                        println!("{} {}", "*".green(), name.green());
+                    } else {
+                        println!("  {}", name);
+                    }
+                }
+            }
+            cli::ProfileCommand::Use { name } => {
+                validate_profile_name(name)?;
+                std::fs::write(cli::active_profile_file(), name)?;
+                if cli::profile_config_file(name).exists() {
+                    println!("Using profile {}", name);
+                } else {
+                    println!(
+                        "Using profile {}. Run `bismuth login` to log in to it.",
+                        name
+                    );
+                }
+            }
+        }
+        return Ok(());
+    }
+
     if let cli::Command::Login = args.command {
         debug!("Starting login flow");
 
@@ -977,22 +3026,37 @@ async fn _main() -> Result<()> {
 
         let organization = choice(&user.organizations, "organization").await?;
 
+        // Logging in again keeps the settings from `config set`
+        let previous: Option<Config> = std::fs::read_to_string(&args.global.config_file)
+            .ok()
+            .and_then(|config_str| serde_json::from_str(&config_str).ok());
         let config = Config {
             token: token.to_string(),
             organization_id: organization.id,
+            api_url: Some(args.global.api_url.to_string()),
+            low_credit_threshold: previous
+                .as_ref()
+                .map_or_else(default_low_credit_threshold, |c| c.low_credit_threshold),
+            theme: previous.and_then(|c| c.theme),
         };
-        let config_str = serde_json::to_string(&config)?;
-        let mut config_file = File::create(&args.global.config_file).await?;
-        config_file.write_all(config_str.as_bytes()).await?;
+        write_config(&args.global.config_file, &config).await?;
         return Ok(());
     }
 
     let mut config_file = File::open(&args.global.config_file).await.map_err(|_| {
-        anyhow!("Failed to open auth token. Maybe you need to `bismuth login` first?")
+        CliError::ConfigError(
+            "Failed to open auth token. Maybe you need to `bismuth login` first?".to_string(),
+        )
     })?;
     let mut config_str: String = String::new();
     config_file.read_to_string(&mut config_str).await?;
-    let config: Config = serde_json::from_str(&config_str)?;
+    let mut config: Config = serde_json::from_str(&config_str).map_err(|e| {
+        CliError::ConfigError(format!(
+            "Invalid config file {} ({})",
+            args.global.config_file.display(),
+            e
+        ))
+    })?;
 
     debug!("Organization ID: {}", config.organization_id);
 
@@ -1005,8 +3069,64 @@ async fn _main() -> Result<()> {
     )?;
 
     match &args.command {
+        cli::Command::Config { command } => match command {
+            cli::ConfigCommand::Show { show_token } => {
+                println!("Profile: {}", profile);
+                println!("Config file: {}", args.global.config_file.display());
+                println!("API URL: {}", args.global.api_url);
+                println!("Organization ID: {}", config.organization_id);
+                println!("Low credit threshold: {}", config.low_credit_threshold);
+                if let Some(theme) = config.theme {
+                    println!("Chat theme: {:?}", theme);
+                }
+                println!(
+                    "Token: {}",
+                    if *show_token {
+                        config.token.clone()
+                    } else {
+                        redact(&config.token)
+                    }
+                );
+                Ok(())
+            }
+            cli::ConfigCommand::Set { setting } => {
+                match setting {
+                    cli::ConfigSetting::LowCreditThreshold { credits } => {
+                        config.low_credit_threshold = *credits;
+                    }
+                    cli::ConfigSetting::Theme { theme } => {
+                        config.theme = Some(*theme);
+                    }
+                }
+                write_config(&args.global.config_file, &config).await
+            }
+        },
         cli::Command::Configure { command } => match command {
-            cli::ConfigureCommand::OpenRouter {} => {
+            cli::ConfigureCommand::OpenRouter { key } => {
+                let key = match key.as_deref() {
+                    Some("-") => {
+                        let mut key = String::new();
+                        std::io::stdin().read_to_string(&mut key)?;
+                        Some(key.trim().to_string())
+                    }
+                    Some(key) => Some(key.to_string()),
+                    None => std::env::var("BISMUTH_OPENROUTER_KEY").ok(),
+                };
+                if let Some(key) = key {
+                    if key.is_empty() {
+                        return Err(anyhow!("OpenRouter key is empty"));
+                    }
+                    client
+                        .post("/llm-configuration")
+                        .json(&api::LLMConfigurationRequest { key })
+                        .send()
+                        .await?
+                        .error_body_for_status()
+                        .await?;
+                    status!("{}", "OpenRouter key configured".green());
+                    return Ok(());
+                }
+
                 let server = tiny_http::Server::http("localhost:0").map_err(|e| anyhow!(e))?;
                 let port = server.server_addr().to_ip().unwrap().port();
                 let mut url = Url::parse("https://openrouter.ai/auth").unwrap();
@@ -1033,7 +3153,7 @@ async fn _main() -> Result<()> {
                     .unwrap()
                     .to_string();
                 debug!("Got code: {}", code);
-                let resp: serde_json::Value = reqwest::Client::new()
+                let resp: serde_json::Value = http_client()?
                     .post("https://openrouter.ai/api/v1/auth/keys")
                     .json(&json!({"code": code}))
                     .send()
@@ -1065,6 +3185,100 @@ async fn _main() -> Result<()> {
                 )?;
                 Ok(())
             }
+            cli::ConfigureCommand::Show => {
+                let config: api::LLMConfiguration = client
+                    .get("/llm-configuration")
+                    .send()
+                    .await?
+                    .error_body_for_status()
+                    .await?
+                    .json()
+                    .await?;
+                if config.has_key {
+                    println!(
+                        "Key: {} ({}****{})",
+                        "configured".green(),
+                        config.provider.as_deref().unwrap_or("unknown provider"),
+                        config.key_hint.as_deref().unwrap_or("")
+                    );
+                } else {
+                    println!("Key: {}", "not configured".yellow());
+                }
+                println!("Model: {}", config.model.as_deref().unwrap_or("(default)"));
+                Ok(())
+            }
+            cli::ConfigureCommand::Clear => {
+                if !confirm("Remove the configured LLM key?", false).await? {
+                    return Ok(());
+                }
+                client
+                    .delete("/llm-configuration")
+                    .send()
+                    .await?
+                    .error_body_for_status()
+                    .await?;
+                status!("{}", "LLM key removed".green());
+                Ok(())
+            }
+            cli::ConfigureCommand::Model { model } => {
+                let models: Vec<api::LLMModel> = client
+                    .get("/llm-configuration/models")
+                    .send()
+                    .await?
+                    .error_body_for_status()
+                    .await?
+                    .json()
+                    .await?;
+                match model {
+                    None => {
+                        let current: api::LLMConfiguration = client
+                            .get("/llm-configuration")
+                            .send()
+                            .await?
+                            .error_body_for_status()
+                            .await?
+                            .json()
+                            .await?;
+                        for model in &models {
+                            if current.model.as_ref() == Some(&model.id) {
+                                println!(
+                                    "{} ({}) {}",
+                                    model.id.bold(),
+                                    model.name,
+                                    "[current]".green()
+                                );
+                            } else {
+                                println!("{} ({})", model.id, model.name);
+                            }
+                        }
+                        Ok(())
+                    }
+                    Some(model) => {
+                        if !models.iter().any(|m| m.id == *model) {
+                            return Err(anyhow!(
+                                "Unknown model {}. Available models: {}",
+                                model,
+                                models
+                                    .iter()
+                                    .map(|m| m.id.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ));
+                        }
+                        client
+                            .post("/llm-configuration/model")
+                            .json(&api::SetModelRequest {
+                                model: model.clone(),
+                            })
+                            .send()
+                            .await?
+                            .error_body_for_status()
+                            .await?;
+                        status!("{}", format!("Chat will now use {}", model).green());
+                        Ok(())
+                    }
+                }
+            }
         },
         cli::Command::Project { command } => match command {
             cli::ProjectCommand::List => {
@@ -1092,7 +3306,7 @@ async fn _main() -> Result<()> {
                     .await?
                     .json()
                     .await?;
-                project_clone(&project, None)?;
+                project_clone(&project, None, None)?;
                 Ok(())
             }
             cli::ProjectCommand::Import(args) => project_import(args, &client).await,
@@ -1102,35 +3316,43 @@ async fn _main() -> Result<()> {
                 set_bismuth_remote(&repo, &project)?;
                 Ok(())
             }
-            cli::ProjectCommand::Upload { project, repo } => {
+            cli::ProjectCommand::Upload {
+                project,
+                repo,
+                branches,
+                force,
+            } => {
                 let project = resolve_project_id(&client, project).await?;
                 let repo = std::fs::canonicalize(repo.clone().unwrap_or(std::env::current_dir()?))?;
                 set_bismuth_remote(&repo, &project)?;
-                Command::new("git")
-                    .arg("-C")
-                    .arg(repo.as_path())
-                    .arg("push")
-                    .arg("--force")
-                    .arg("bismuth")
-                    .arg("--all")
+                if !confirm_push_contents(&repo).await? {
+                    return Err(anyhow!("Upload cancelled"));
+                }
+                let git_config = bismuth_toml::git_config(&repo)?;
+                push_to_bismuth(
+                    bismuth_git(&project, git_config.remote_auth)
+                        .arg("-C")
+                        .arg(repo.as_path())
+                        .arg("push")
+                        .args(force.then_some("--force"))
+                        .arg(&git_config.remote_name)
+                        .args(push_branch_args(branches, &repo)?),
                     //.arg("refs/remotes/origin/*")
                     //.arg("refs/heads/*")
-                    .stdout(std::process::Stdio::inherit())
-                    .stderr(std::process::Stdio::inherit())
-                    .output()
-                    .map_err(|e| anyhow!(e))
-                    .and_then(|o| {
-                        if o.status.success() {
-                            Ok(())
-                        } else {
-                            Err(anyhow!("Failed to push to Bismuth"))
-                        }
-                    })?;
+                )?;
                 Ok(())
             }
-            cli::ProjectCommand::Clone { project, outdir } => {
-                let project = resolve_project_id(&client, project).await?;
-                project_clone(&project, outdir.as_deref())?;
+            cli::ProjectCommand::Clone {
+                project,
+                outdir,
+                all,
+                depth,
+            } => {
+                if let Some(dir) = all {
+                    return project_clone_all(&client, dir, *depth).await;
+                }
+                let project = resolve_project_id(&client, project.as_ref().unwrap()).await?;
+                project_clone(&project, outdir.as_deref(), *depth)?;
                 Ok(())
             }
             cli::ProjectCommand::Link { project } => {
@@ -1147,7 +3369,7 @@ async fn _main() -> Result<()> {
                     println!("You'll need to install the GitHub app first.");
 
                     let url = github_app_url(&client.base_url);
-                    if can_launch_browser!() {
+                    if can_launch_browser() {
                         press_any_key("Press any key to open the installation page.").await?;
                         open::that_detached(url)?;
                     } else {
@@ -1157,12 +3379,16 @@ async fn _main() -> Result<()> {
                         );
                     }
 
-                    print!("Waiting for app install");
-                    std::io::stdout().flush()?;
+                    if !quiet() {
+                        print!("Waiting for app install");
+                        std::io::stdout().flush()?;
+                    }
                     loop {
                         tokio::time::sleep(Duration::from_secs(2)).await;
-                        print!(".");
-                        std::io::stdout().flush()?;
+                        if !quiet() {
+                            print!(".");
+                            std::io::stdout().flush()?;
+                        }
                         gh_orgs = client
                             .get("/projects/connect/github/organizations")
                             .send()
@@ -1186,7 +3412,7 @@ async fn _main() -> Result<()> {
                     .await?
                     .json()
                     .await?;
-                println!(
+                status!(
                     "{}",
                     format!(
                         "🎉 Successfully linked {} to https://github.com/{}",
@@ -1197,14 +3423,82 @@ async fn _main() -> Result<()> {
                 );
                 Ok(())
             }
+            cli::ProjectCommand::Unlink { project } => {
+                let project = resolve_project_id(&client, project).await?;
+                let github_repo = match &project.github_repo {
+                    Some(github_repo) => github_repo.clone(),
+                    None => {
+                        return Err(anyhow!(
+                            "Project {} is not linked to a GitHub repository",
+                            project.name
+                        ))
+                    }
+                };
+                if !confirm(
+                    format!(
+                        "Are you sure you want to unlink {} from https://github.com/{}?",
+                        project.name, github_repo
+                    ),
+                    false,
+                )
+                .await?
+                {
+                    return Ok(());
+                }
+                let updated_project: api::Project = client
+                    .delete(&format!("/projects/{}/connect/github", project.id))
+                    .send()
+                    .await?
+                    .error_body_for_status()
+                    .await?
+                    .json()
+                    .await?;
+                status!(
+                    "{}",
+                    format!(
+                        "Successfully unlinked {} from https://github.com/{}",
+                        updated_project.name, github_repo,
+                    )
+                    .green()
+                );
+                status!(
+                    "GitHub repository: {}",
+                    updated_project.github_repo.as_deref().unwrap_or("(none)")
+                );
+                status!("Future clones will use the Bismuth git remote instead of GitHub.");
+                Ok(())
+            }
             cli::ProjectCommand::Delete { project } => {
                 let project = resolve_project_id(&client, project).await?;
-                if confirm(
-                    format!("Are you sure you want to delete project {}?", project.name),
-                    false,
-                )
-                .await?
-                {
+                let mut running = 0;
+                for feature in &project.features {
+                    let resp = client
+                        .get(&format!(
+                            "/projects/{}/features/{}/deploy/status",
+                            project.id, feature.id
+                        ))
+                        .send()
+                        .await?;
+                    if resp.status().as_u16() == 404 {
+                        continue;
+                    }
+                    let status: api::DeployStatusResponse =
+                        resp.error_body_for_status().await?.json().await?;
+                    if status.status == api::ContainerState::Running {
+                        running += 1;
+                    }
+                }
+                let prompt = if running > 0 {
+                    format!(
+                        "Project {} has {} running deployment{}. Delete anyway?",
+                        project.name,
+                        running,
+                        if running == 1 { "" } else { "s" }
+                    )
+                } else {
+                    format!("Are you sure you want to delete project {}?", project.name)
+                };
+                if confirm(prompt, false).await? {
                     client
                         .delete(&format!("/projects/{}", project.id))
                         .send()
@@ -1216,36 +3510,31 @@ async fn _main() -> Result<()> {
             }
         },
         cli::Command::Feature { command } => match command {
-            cli::FeatureCommand::List { project } => {
-                let project = resolve_project_id(&client, project).await?;
-                for feature in &project.features {
-                    println!("{}", feature.name);
-                }
-                Ok(())
+            cli::FeatureCommand::List {
+                project,
+                all_projects,
+                status,
+                output,
+            } => {
+                let projects = match project {
+                    Some(project) => vec![resolve_project_id(&client, project).await?],
+                    None => list_projects_detailed(&client).await?,
+                };
+                feature_list(&client, &projects, *all_projects, *status, *output).await
             }
             cli::FeatureCommand::Config { feature, command } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
 
                 match command {
                     cli::FeatureConfigCommand::Get { key } => {
-                        let resp = client
-                            .get(&format!(
-                                "/projects/{}/features/{}/config",
-                                project.id, feature.id
-                            ))
-                            .send()
-                            .await?
-                            .error_body_for_status()
-                            .await?;
-                        let feature_config: Vec<api::FeatureConfig> = resp.json().await?;
+                        let feature_config =
+                            get_feature_config(&client, &project, &feature).await?;
                         match key {
                             Some(key) => {
                                 let config = feature_config
                                     .iter()
                                     .find(|c| c.key == *key)
-                                    .ok_or_else(|| anyhow!("No such key"))?;
+                                    .ok_or_else(|| CliError::NotFound("No such key".to_string()))?;
                                 println!("{}", config.value);
                             }
                             None => {
@@ -1257,43 +3546,109 @@ async fn _main() -> Result<()> {
                         Ok(())
                     }
                     cli::FeatureConfigCommand::Set { key, value } => {
-                        let resp = client
-                            .get(&format!(
-                                "/projects/{}/features/{}/config",
-                                project.id, feature.id
-                            ))
-                            .send()
-                            .await?
-                            .error_body_for_status()
-                            .await?;
-                        let mut feature_config: Vec<api::FeatureConfig> = resp.json().await?;
-                        feature_config.retain(|c| c.key != *key);
-                        feature_config.push(api::FeatureConfig {
-                            key: key.clone(),
-                            value: value.clone(),
-                        });
-                        client
-                            .post(&format!(
-                                "/projects/{}/features/{}/config",
-                                project.id, feature.id
-                            ))
-                            .json(&feature_config)
-                            .send()
-                            .await?
-                            .error_body_for_status()
-                            .await?;
+                        update_feature_config(&client, &project, &feature, |feature_config| {
+                            set_feature_config(feature_config, key, value);
+                            Ok(())
+                        })
+                        .await
+                    }
+                    cli::FeatureConfigCommand::Unset {
+                        key,
+                        ignore_missing,
+                    } => {
+                        update_feature_config(&client, &project, &feature, |feature_config| {
+                            if !*ignore_missing && !feature_config.iter().any(|c| c.key == *key) {
+                                return Err(
+                                    CliError::NotFound(format!("No such key: {}", key)).into()
+                                );
+                            }
+                            feature_config.retain(|c| c.key != *key);
+                            Ok(())
+                        })
+                        .await
+                    }
+                }
+            }
+            cli::FeatureCommand::Env { feature, command } => {
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
+
+                match command {
+                    cli::FeatureEnvCommand::Get { key, format } => {
+                        let feature_config =
+                            get_feature_config(&client, &project, &feature).await?;
+                        let value = |value: &str| match format {
+                            cli::EnvFormat::Plain => value.to_string(),
+                            cli::EnvFormat::Dotenv => dotenv_value(value),
+                        };
+                        match key {
+                            Some(key) => {
+                                let config = feature_config
+                                    .iter()
+                                    .find(|c| c.key == *key)
+                                    .ok_or_else(|| CliError::NotFound("No such key".to_string()))?;
+                                println!("{}", value(&config.value));
+                            }
+                            None => {
+                                for c in feature_config {
+                                    println!("{}={}", c.key, value(&c.value));
+                                }
+                            }
+                        }
+                        Ok(())
+                    }
+                    cli::FeatureEnvCommand::Set { vars } => {
+                        update_feature_config(&client, &project, &feature, |feature_config| {
+                            for var in vars {
+                                set_feature_config(feature_config, &var.key, &var.value);
+                            }
+                            Ok(())
+                        })
+                        .await
+                    }
+                    cli::FeatureEnvCommand::Unset { keys } => {
+                        update_feature_config(&client, &project, &feature, |feature_config| {
+                            if let Some(missing) = keys
+                                .iter()
+                                .find(|key| !feature_config.iter().any(|c| c.key == **key))
+                            {
+                                return Err(CliError::NotFound(format!(
+                                    "No such key: {}",
+                                    missing
+                                ))
+                                .into());
+                            }
+                            feature_config.retain(|c| !keys.contains(&c.key));
+                            Ok(())
+                        })
+                        .await
+                    }
+                    cli::FeatureEnvCommand::Import { file } => {
+                        let vars = parse_dotenv(&std::fs::read_to_string(file)?)
+                            .map_err(|e| anyhow!("{}: {}", file.display(), e))?;
+                        update_feature_config(&client, &project, &feature, |feature_config| {
+                            for var in &vars {
+                                set_feature_config(feature_config, &var.key, &var.value);
+                            }
+                            Ok(())
+                        })
+                        .await?;
+                        status!("Set {} variables", vars.len());
                         Ok(())
                     }
                 }
             }
             cli::FeatureCommand::Deploy {
                 feature,
+                wait: _,
                 no_wait,
                 timeout,
+                poll_interval,
+                force,
+                env,
             } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let env = deploy_env_vars(env)?;
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
+                warn_low_credits(&client, config.low_credit_threshold).await;
                 feature_deploy(
                     &project,
                     &feature,
@@ -1303,47 +3658,75 @@ async fn _main() -> Result<()> {
                     } else {
                         Some(Duration::from_secs(*timeout))
                     },
+                    Duration::from_secs(*poll_interval),
+                    *force,
+                    &env,
                 )
                 .await
             }
-            cli::FeatureCommand::DeployStatus { feature } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
-                feature_deploy_status(&project, &feature, &client).await
+            cli::FeatureCommand::DeployStatus {
+                feature,
+                watch,
+                poll_interval,
+                output,
+                fail_if_not_running,
+            } => {
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
+                feature_deploy_status(
+                    &project,
+                    &feature,
+                    &client,
+                    watch.then(|| Duration::from_secs(*poll_interval)),
+                    *output,
+                    *fail_if_not_running,
+                )
+                .await
             }
             cli::FeatureCommand::Teardown { feature } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
                 feature_teardown(&project, &feature, &client).await
             }
-            cli::FeatureCommand::GetURL { feature } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+            cli::FeatureCommand::GetURL {
+                feature,
+                open,
+                json,
+            } => {
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
                 let url = feature_get_url(&project, &feature, &client).await?;
-                println!("{}", url);
-                Ok(())
+                show_feature_url(url, *open, *json)
+            }
+            cli::FeatureCommand::Ping {
+                feature,
+                path,
+                expect_status,
+            } => {
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
+                feature_ping(&project, &feature, &client, path, *expect_status).await
             }
             cli::FeatureCommand::Logs { feature, follow } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
                 feature_logs(&project, &feature, *follow, &client).await
             }
         },
         cli::Command::KV { command } => match command {
-            cli::KVCommand::Get { feature, key } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+            cli::KVCommand::Get {
+                feature,
+                key,
+                metadata,
+            } => {
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
+                let path = format!(
+                    "/projects/{}/features/{}/svcprovider/kv/v1/{}",
+                    project.id, feature.id, key
+                );
+
+                if *metadata {
+                    print_object_metadata(&client, &path).await?;
+                    return Ok(());
+                }
 
                 let resp = client
-                    .get(&format!(
-                        "/projects/{}/features/{}/svcprovider/kv/v1/{}",
-                        project.id, feature.id, key
-                    ))
+                    .get(&path)
                     .send()
                     .await?
                     .error_body_for_status()
@@ -1363,9 +3746,7 @@ async fn _main() -> Result<()> {
                 key,
                 value,
             } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
 
                 client
                     .post(&format!(
@@ -1380,9 +3761,7 @@ async fn _main() -> Result<()> {
                 Ok(())
             }
             cli::KVCommand::Delete { feature, key } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
 
                 client
                     .delete(&format!(
@@ -1395,12 +3774,31 @@ async fn _main() -> Result<()> {
                     .await?;
                 Ok(())
             }
+            cli::KVCommand::Export { feature, output } => {
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
+                kv_export(&client, &project, &feature, output.as_deref()).await
+            }
+            cli::KVCommand::Import {
+                feature,
+                input,
+                overwrite,
+                skip_existing,
+            } => {
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
+                kv_import(
+                    &client,
+                    &project,
+                    &feature,
+                    input,
+                    *overwrite,
+                    *skip_existing,
+                )
+                .await
+            }
         },
         cli::Command::Blob { command } => match command {
             cli::BlobCommand::List { feature } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
 
                 let resp = client
                     .get(&format!(
@@ -1419,16 +3817,17 @@ async fn _main() -> Result<()> {
                 feature,
                 key,
                 value,
+                content_type,
             } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
 
+                let content_type = content_type.clone().unwrap_or_else(|| value.content_type());
                 client
                     .post(&format!(
                         "/projects/{}/features/{}/svcprovider/blob/v1/{}",
                         project.id, feature.id, key
                     ))
+                    .header(reqwest::header::CONTENT_TYPE, content_type.parse()?)
                     .body(if let Some(literal) = &value.literal {
                         reqwest::Body::from(literal.clone())
                     } else {
@@ -1444,48 +3843,66 @@ async fn _main() -> Result<()> {
                 feature,
                 key,
                 output,
+                range,
             } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
 
-                let resp = client
-                    .get(&format!(
-                        "/projects/{}/features/{}/svcprovider/blob/v1/{}",
-                        project.id, feature.id, key
-                    ))
-                    .send()
-                    .await?
-                    .error_body_for_status()
-                    .await?;
+                let mut req = client.get(&format!(
+                    "/projects/{}/features/{}/svcprovider/blob/v1/{}",
+                    project.id, feature.id, key
+                ));
+                if let Some(range) = range {
+                    req = req.header(reqwest::header::RANGE, format!("bytes={}", range).parse()?);
+                }
+                let resp = req.send().await?.error_body_for_status().await?;
                 let mut output: Pin<Box<dyn tokio::io::AsyncWrite>> = match output {
                     Some(output) => Box::pin(File::create(output).await?),
                     None => Box::pin(tokio::io::stdout()),
                 };
-                tokio::io::copy(
-                    &mut StreamReader::new(
-                        resp.bytes_stream()
-                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
-                    ),
-                    &mut output,
-                )
-                .await?;
+                let partial = resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+                let mut body = StreamReader::new(
+                    resp.bytes_stream()
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+                );
+                match range {
+                    Some(range) if !partial => {
+                        eprintln!(
+                            "{}",
+                            "Range requests aren't supported for this blob, \
+                             downloading it in full and keeping only the requested bytes"
+                                .yellow()
+                        );
+                        tokio::io::copy(&mut (&mut body).take(range.start), &mut tokio::io::sink())
+                            .await?;
+                        match range.end {
+                            Some(end) => {
+                                tokio::io::copy(&mut body.take(end - range.start + 1), &mut output)
+                                    .await?
+                            }
+                            None => tokio::io::copy(&mut body, &mut output).await?,
+                        };
+                    }
+                    _ => {
+                        tokio::io::copy(&mut body, &mut output).await?;
+                    }
+                }
                 Ok(())
             }
             cli::BlobCommand::Set {
                 feature,
                 key,
                 value,
+                content_type,
             } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
 
+                let content_type = content_type.clone().unwrap_or_else(|| value.content_type());
                 client
                     .put(&format!(
                         "/projects/{}/features/{}/svcprovider/blob/v1/{}",
                         project.id, feature.id, key
                     ))
+                    .header(reqwest::header::CONTENT_TYPE, content_type.parse()?)
                     .body(if let Some(literal) = &value.literal {
                         reqwest::Body::from(literal.clone())
                     } else {
@@ -1498,9 +3915,7 @@ async fn _main() -> Result<()> {
                 Ok(())
             }
             cli::BlobCommand::Delete { feature, key } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, &feature.into()).await?;
 
                 client
                     .delete(&format!(
@@ -1516,9 +3931,7 @@ async fn _main() -> Result<()> {
         },
         cli::Command::SQL { command } => match command {
             cli::SQLCommand::Query { feature, query } => {
-                let (project_name, feature_name) = feature.split();
-                let project = resolve_project_id(&client, &project_name).await?;
-                let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+                let (project, feature) = resolve_feature_ref(&client, feature).await?;
 
                 let resp = client
                     .post(&format!(
@@ -1575,8 +3988,8 @@ async fn _main() -> Result<()> {
                         .text()
                         .await?
                 };
-                if can_launch_browser!() {
-                    println!("Opening subscription management page");
+                if can_launch_browser() {
+                    status!("Opening subscription management page");
                     open::that_detached(url)?;
                 } else {
                     println!(
@@ -1601,6 +4014,53 @@ async fn _main() -> Result<()> {
                 );
                 Ok(())
             }
+            cli::BillingCommand::Usage {
+                since,
+                until,
+                output,
+            } => {
+                let until = until.unwrap_or_else(cli::Date::today);
+                let since = since.unwrap_or_else(|| cli::Date::today().start_of_month());
+                if since > until {
+                    return Err(anyhow!("--since {} is after --until {}", since, until));
+                }
+                let usage: Vec<api::CreditUsageEntry> = client
+                    .get("/billing/credits/history")
+                    .query(&[("since", since.to_string()), ("until", until.to_string())])
+                    .send()
+                    .await?
+                    .error_body_for_status()
+                    .await?
+                    .json()
+                    .await?;
+                if let cli::OutputFormat::Json = output {
+                    println!("{}", serde_json::to_string_pretty(&usage)?);
+                    return Ok(());
+                }
+
+                let mut by_day: std::collections::BTreeMap<&str, u64> = Default::default();
+                let mut by_feature: std::collections::BTreeMap<String, u64> = Default::default();
+                for entry in &usage {
+                    *by_day.entry(&entry.date).or_default() += entry.credits;
+                    if let (Some(project), Some(feature)) = (&entry.project, &entry.feature) {
+                        *by_feature
+                            .entry(format!("{}/{}", project, feature))
+                            .or_default() += entry.credits;
+                    }
+                }
+                println!("Credit usage {} to {}", since, until);
+                for (day, credits) in &by_day {
+                    println!("  {}  {}", day, credits);
+                }
+                if !by_feature.is_empty() {
+                    println!("By feature:");
+                    for (feature, credits) in &by_feature {
+                        println!("  {}  {}", feature, credits);
+                    }
+                }
+                println!("Total: {}", by_day.values().sum::<u64>());
+                Ok(())
+            }
             cli::BillingCommand::Refill => {
                 let url = client
                     .get("/billing/credits/buy")
@@ -1610,8 +4070,8 @@ async fn _main() -> Result<()> {
                     .await?
                     .text()
                     .await?;
-                if can_launch_browser!() {
-                    println!("Opening checkout page");
+                if can_launch_browser() {
+                    status!("Opening checkout page");
                     open::that_detached(url)?;
                 } else {
                     println!("Go to the following URL to checkout: {}", url.blue().bold());
@@ -1623,12 +4083,16 @@ async fn _main() -> Result<()> {
         cli::Command::Import(args) => project_import(args, &client).await,
         cli::Command::Deploy {
             feature,
+            wait: _,
             no_wait,
             timeout,
+            poll_interval,
+            force,
+            env,
         } => {
-            let (project_name, feature_name) = feature.split();
-            let project = resolve_project_id(&client, &project_name).await?;
-            let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+            let env = deploy_env_vars(env)?;
+            let (project, feature) = resolve_feature_ref(&client, feature).await?;
+            warn_low_credits(&client, config.low_credit_threshold).await;
             feature_deploy(
                 &project,
                 &feature,
@@ -1638,38 +4102,55 @@ async fn _main() -> Result<()> {
                 } else {
                     Some(Duration::from_secs(*timeout))
                 },
+                Duration::from_secs(*poll_interval),
+                *force,
+                &env,
             )
             .await
         }
-        cli::Command::DeployStatus { feature } => {
-            let (project_name, feature_name) = feature.split();
-            let project = resolve_project_id(&client, &project_name).await?;
-            let feature = resolve_feature_id(&client, &project, &feature_name).await?;
-            feature_deploy_status(&project, &feature, &client).await
+        cli::Command::DeployStatus {
+            feature,
+            watch,
+            poll_interval,
+            output,
+            fail_if_not_running,
+        } => {
+            let (project, feature) = resolve_feature_ref(&client, feature).await?;
+            feature_deploy_status(
+                &project,
+                &feature,
+                &client,
+                watch.then(|| Duration::from_secs(*poll_interval)),
+                *output,
+                *fail_if_not_running,
+            )
+            .await
         }
         cli::Command::Teardown { feature } => {
-            let (project_name, feature_name) = feature.split();
-            let project = resolve_project_id(&client, &project_name).await?;
-            let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+            let (project, feature) = resolve_feature_ref(&client, feature).await?;
             feature_teardown(&project, &feature, &client).await
         }
-        cli::Command::GetURL { feature } => {
-            let (project_name, feature_name) = feature.split();
-            let project = resolve_project_id(&client, &project_name).await?;
-            let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+        cli::Command::GetURL {
+            feature,
+            open,
+            json,
+        } => {
+            let (project, feature) = resolve_feature_ref(&client, feature).await?;
             let url = feature_get_url(&project, &feature, &client).await?;
-            println!("{}", url);
-            Ok(())
+            show_feature_url(url, *open, *json)
         }
         cli::Command::Logs { feature, follow } => {
-            let (project_name, feature_name) = feature.split();
-            let project = resolve_project_id(&client, &project_name).await?;
-            let feature = resolve_feature_id(&client, &project, &feature_name).await?;
+            let (project, feature) = resolve_feature_ref(&client, feature).await?;
             feature_logs(&project, &feature, *follow, &client).await
         }
         cli::Command::Chat {
             repo,
             session_name,
+            dry_run,
+            message,
+            auto_apply,
+            no_aci,
+            theme,
             command,
         } => {
             let current_user: api::User = client
@@ -1700,28 +4181,29 @@ async fn _main() -> Result<()> {
                             if repo.exists() {
                                 repo.to_path_buf()
                             } else {
-                                project_clone(&project, Some(repo))?
+                                project_clone(&project, Some(repo), None)?
                             }
                         }
                         None => {
                             // Check if CWD is a git repo which has the correct remote
                             let repo = git2::Repository::open_from_env()?;
                             let remote_url = repo
-                                .find_remote("bismuth")
+                                .find_remote(&bismuth_toml::remote_name(repo.path())?)
                                 .map(|r| r.url().unwrap().to_string())
                                 .unwrap_or("".to_string());
-                            if remote_url.contains(&project.clone_token) {
+                            if remote_matches_project(&remote_url, &project) {
                                 repo.workdir().unwrap().to_path_buf()
                             } else {
-                                project_clone(&project, None)?
+                                project_clone(&project, None, None)?
                             }
                         }
                     };
-                    Command::new("git")
+                    let git_config = bismuth_toml::git_config(&repo_path)?;
+                    bismuth_git(&project, git_config.remote_auth)
                         .arg("-C")
                         .arg(&repo_path)
                         .arg("fetch")
-                        .arg("bismuth")
+                        .arg(&git_config.remote_name)
                         .output()
                         .map_err(|e| anyhow!(e))
                         .and_then(|o| {
@@ -1731,6 +4213,7 @@ async fn _main() -> Result<()> {
                                 Err(anyhow!("Failed to `git fetch` ({})", o.status))
                             }
                         })?;
+                    deepen_if_needed(&project, &repo_path)?;
 
                     let sessions: Vec<api::ChatSession> = client
                         .get(&format!(
@@ -1755,6 +4238,14 @@ async fn _main() -> Result<()> {
                     let session = match existing_session {
                         Some(session) => session,
                         None => {
+                            let session_name = match (session_name, message) {
+                                (Some(name), _) => Some(name.clone()),
+                                // Don't hold up one-shot messages
+                                (None, Some(_)) => None,
+                                (None, None) => {
+                                    prompt_text("Name for the new chat session (optional):").await?
+                                }
+                            };
                             client
                                 .post(&format!(
                                     "/projects/{}/features/{}/chat/sessions",
@@ -1774,6 +4265,8 @@ async fn _main() -> Result<()> {
                         return Err(anyhow!("Invalid bismuth.toml: {}", e));
                     }
 
+                    warn_low_credits(&client, config.low_credit_threshold).await;
+
                     start_chat(
                         &current_user,
                         &project,
@@ -1782,6 +4275,13 @@ async fn _main() -> Result<()> {
                         &session,
                         &repo_path,
                         &client,
+                        &chat::ChatOptions {
+                            dry_run: *dry_run,
+                            message: message.clone(),
+                            auto_apply: *auto_apply,
+                            no_aci: *no_aci,
+                            theme: theme.or(config.theme).unwrap_or_default(),
+                        },
                     )
                     .await
                 }
@@ -1818,8 +4318,33 @@ async fn _main() -> Result<()> {
 
                     Ok(())
                 }
-                Some(cli::ChatSubcommand::DeleteSession { name }) => {
+                Some(cli::ChatSubcommand::DeleteSession {
+                    name: None,
+                    older_than,
+                    force,
+                    ..
+                }) => {
+                    prune_chat_sessions(
+                        &client,
+                        &project,
+                        &feature,
+                        older_than.map(Into::into),
+                        *force,
+                    )
+                    .await
+                }
+                Some(cli::ChatSubcommand::DeleteSession {
+                    name: Some(name),
+                    force,
+                    ..
+                }) => {
                     let session = resolve_chat_session(&client, &project, &feature, name).await?;
+                    if !force && chat::session_is_open(session.id) {
+                        return Err(anyhow!(
+                            "Session {} is open in a running chat. Use --force to delete it anyway",
+                            name
+                        ));
+                    }
                     client
                         .delete(&format!(
                             "/projects/{}/features/{}/chat/sessions/{}",
@@ -1829,13 +4354,17 @@ async fn _main() -> Result<()> {
                         .await?
                         .error_body_for_status()
                         .await?;
+                    chat::remove_draft(session.id);
 
                     Ok(())
                 }
             }
         }
         cli::Command::Version => unreachable!(),
+        cli::Command::Update { .. } => unreachable!(),
+        cli::Command::Validate { .. } => unreachable!(),
         cli::Command::Login => unreachable!(),
+        cli::Command::Profile { .. } => unreachable!(),
     }
 }
 
@@ -1844,11 +4373,449 @@ async fn main() -> Result<()> {
     match _main().await {
         Ok(_) => Ok(()),
         Err(e) => {
-            eprintln!("{}", e.to_string().red());
+            let message = redact_urls(&e.to_string());
+            if JSON_ERRORS.load(Ordering::Relaxed) {
+                eprintln!("{}", json!({"error": message, "kind": CliError::kind(&e)}));
+            } else {
+                eprintln!("{}", message.red());
+            }
             if std::env::var("RUST_BACKTRACE").is_ok() {
                 return Err(e);
             }
-            std::process::exit(1);
+            std::process::exit(CliError::exit_code(&e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_feature_ref_split() {
+        let feature: cli::FeatureRef = "2024/main".parse().unwrap();
+        assert!(matches!(
+            feature.split().unwrap(),
+            (IdOrName::Name(project), IdOrName::Name(feature)) if project == "2024" && feature == "main"
+        ));
+        let feature: cli::FeatureRef = "2024/".parse().unwrap();
+        assert!(feature.split().is_err());
+    }
+
+    #[test]
+    fn test_dotenv_round_trip() {
+        let values = [
+            "plain",
+            "",
+            "with space",
+            "quote \" inside",
+            "back\\slash",
+            "multi\nline\r\n",
+            "hash # not a comment",
+            "'single'",
+            "trailing\\",
+        ];
+        let contents: String = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("KEY{}={}\n", i, dotenv_value(value)))
+            .collect();
+        let vars = parse_dotenv(&contents).unwrap();
+        assert_eq!(vars.len(), values.len());
+        for (i, (var, value)) in vars.iter().zip(values).enumerate() {
+            assert_eq!(var.key, format!("KEY{}", i));
+            assert_eq!(var.value, value);
+        }
+    }
+
+    #[test]
+    fn test_parse_dotenv() {
+        let vars = parse_dotenv(
+            r#"
+# a comment
+export A=1
+B = two # comment
+C="a" # say "hi"
+D='no\escape' # comment
+E="esc\"aped\n"
+F=
+G=""
+H=a#b
+"#,
+        )
+        .unwrap();
+        let vars: Vec<(&str, &str)> = vars
+            .iter()
+            .map(|v| (v.key.as_str(), v.value.as_str()))
+            .collect();
+        assert_eq!(
+            vars,
+            vec![
+                ("A", "1"),
+                ("B", "two"),
+                ("C", "a"),
+                ("D", "no\\escape"),
+                ("E", "esc\"aped\n"),
+                ("F", ""),
+                ("G", ""),
+                ("H", "a#b"),
+            ]
+        );
+
+        for (contents, error) in [
+            ("NOEQUALS", "Line 1: expected KEY=VALUE"),
+            ("=value", "Line 1: missing key"),
+            ("\nA=\"open", "Line 2: unterminated quote"),
+            ("A='open", "Line 1: unterminated quote"),
+            ("A=\"a\" b", "Line 1: unexpected text after closing quote"),
+        ] {
+            assert_eq!(parse_dotenv(contents).unwrap_err().to_string(), error);
         }
     }
+
+    #[test]
+    fn test_kv_export_value() {
+        let text = KVExportValue::new(b"hello".to_vec());
+        assert_eq!(serde_json::to_string(&text).unwrap(), "\"hello\"");
+
+        let binary = KVExportValue::new(vec![0xff, 0x00]);
+        let json = serde_json::to_string(&binary).unwrap();
+        assert_eq!(json, r#"{"base64":"/wA="}"#);
+        let parsed: KVExportValue = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.into_bytes().unwrap(), vec![0xff, 0x00]);
+    }
+
+    #[test]
+    fn test_redact_urls() {
+        let url = Url::parse("https://:secrettoken@api.bismuth.cloud/projects/list").unwrap();
+        assert_eq!(
+            redact_url(&url),
+            "https://:****@api.bismuth.cloud/projects/list"
+        );
+        assert_eq!(
+            redact_urls(&format!("error sending request for url ({})", url)),
+            "error sending request for url (https://:****@api.bismuth.cloud/projects/list)"
+        );
+        assert_eq!(
+            redact_urls("see https://api.bismuth.cloud/docs"),
+            "see https://api.bismuth.cloud/docs"
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let e = anyhow!(CliError::NotFound("No such project".to_string())).context("Deploying");
+        assert_eq!(CliError::exit_code(&e), CliError::EXIT_NOT_FOUND);
+        let e = anyhow!(git2::Error::from_str("bad ref"));
+        assert_eq!(CliError::exit_code(&e), CliError::EXIT_GIT);
+        assert_eq!(CliError::kind(&e), "git");
+        assert_eq!(CliError::exit_code(&anyhow!("something else")), 1);
+        assert_eq!(CliError::kind(&anyhow!("something else")), "other");
+    }
+
+    #[test]
+    fn test_parse_api_timestamp() {
+        let epoch_day = std::time::UNIX_EPOCH + Duration::from_secs(86400);
+        assert_eq!(parse_api_timestamp("1970-01-02T00:00:00Z"), Some(epoch_day));
+        assert_eq!(
+            parse_api_timestamp("1970-01-02T00:00:00+00:00"),
+            Some(epoch_day)
+        );
+        assert_eq!(
+            parse_api_timestamp("1970-01-02T00:00:00.5Z"),
+            Some(epoch_day + Duration::from_millis(500))
+        );
+        assert_eq!(parse_api_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_clone_dir_names() {
+        let projects: Vec<api::Project> = [(1, "a"), (2, "b"), (3, "a")]
+            .into_iter()
+            .map(|(id, name)| {
+                serde_json::from_value(serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "hash": "h",
+                    "features": [],
+                    "cloneToken": "",
+                    "hasPushed": true,
+                }))
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(clone_dir_names(&projects), vec!["a-1", "b", "a-3"]);
+    }
+
+    #[test]
+    fn test_deepen_if_needed() {
+        let git = |dir: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        let project: api::Project = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "p",
+            "hash": "abc123",
+            "features": [],
+            "cloneToken": "",
+            "hasPushed": true,
+        }))
+        .unwrap();
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let upstream = tmpdir.path().join("upstream");
+        let clone = tmpdir.path().join("clone");
+        std::fs::create_dir(&upstream).unwrap();
+        git(&upstream, &["init", "-b", "main"]);
+        git(&upstream, &["commit", "--allow-empty", "-m", "one"]);
+        git(&upstream, &["commit", "--allow-empty", "-m", "two"]);
+        git(&upstream, &["branch", "feature"]);
+
+        git(
+            tmpdir.path(),
+            &[
+                "clone",
+                "--depth=1",
+                "--origin=bismuth",
+                &format!("file://{}", upstream.display()),
+                clone.to_str().unwrap(),
+            ],
+        );
+        std::fs::write(
+            clone.join("bismuth.toml"),
+            "[git]\nremote_auth = \"credential-helper\"\n",
+        )
+        .unwrap();
+        git(&clone, &["checkout", "-b", "local-only"]);
+        deepen_if_needed(&project, &clone).unwrap();
+        assert!(git2::Repository::open(&clone).unwrap().is_shallow());
+
+        git(&clone, &["checkout", "-b", "feature"]);
+        let repo = git2::Repository::open(&clone).unwrap();
+        assert!(repo.is_shallow());
+        assert!(repo.find_reference("refs/remotes/bismuth/feature").is_err());
+
+        deepen_if_needed(&project, &clone).unwrap();
+        let repo = git2::Repository::open(&clone).unwrap();
+        assert!(!repo.is_shallow());
+        let upstream_commit = repo
+            .find_reference("refs/remotes/bismuth/feature")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(upstream_commit.parent_count(), 1);
+    }
+
+    #[test]
+    fn test_check_not_pushed_github_origin() {
+        let project: api::Project = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "p",
+            "hash": "abc123",
+            "features": [],
+            "cloneToken": "",
+            "githubRepo": "org/p",
+            "githubAppInstall": {"installationId": 1},
+            "hasPushed": true,
+        }))
+        .unwrap();
+        let feature = api::Feature {
+            id: 1,
+            name: "main".to_string(),
+        };
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init_opts(
+            tmpdir.path(),
+            git2::RepositoryInitOptions::new().initial_head("main"),
+        )
+        .unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let first = repo
+            .commit(Some("HEAD"), &sig, &sig, "one", &tree, &[])
+            .unwrap();
+        let second = repo
+            .commit(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                "two",
+                &tree,
+                &[&repo.find_commit(first).unwrap()],
+            )
+            .unwrap();
+        repo.remote("origin", "git@github.com:org/p.git").unwrap();
+        repo.remote("bismuth", "https://api.bismuth.cloud/git/abc123")
+            .unwrap();
+        repo.reference("refs/remotes/origin/main", second, false, "")
+            .unwrap();
+        repo.reference("refs/remotes/bismuth/main", first, false, "")
+            .unwrap();
+
+        assert!(check_not_pushed(tmpdir.path(), &project, &feature).unwrap());
+        repo.reference("refs/remotes/bismuth/main", second, true, "")
+            .unwrap();
+        assert!(!check_not_pushed(tmpdir.path(), &project, &feature).unwrap());
+    }
+
+    #[test]
+    fn test_project_clone_existing_dir() {
+        let project: api::Project = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "p",
+            "hash": "abc123",
+            "features": [],
+            "cloneToken": "",
+            "hasPushed": true,
+        }))
+        .unwrap();
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        std::fs::write(tmpdir.path().join("README"), "").unwrap();
+        assert!(!is_clone_of(tmpdir.path(), &project));
+        let err = project_clone(&project, Some(tmpdir.path()), None).unwrap_err();
+        assert!(err.to_string().contains("is not a clone of p"));
+        assert_eq!(CliError::exit_code(&err), CliError::EXIT_GIT);
+
+        let repo = git2::Repository::init(tmpdir.path()).unwrap();
+        repo.remote("origin", "https://api.bismuth.cloud/git/other")
+            .unwrap();
+        assert!(!is_clone_of(tmpdir.path(), &project));
+        repo.remote("bismuth", "https://git@api.bismuth.cloud/git/abc123")
+            .unwrap();
+        assert!(is_clone_of(tmpdir.path(), &project));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_concurrently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const ITEMS: usize = 10;
+
+        let fetch = |concurrency| async move {
+            let running = AtomicUsize::new(0);
+            let peak = AtomicUsize::new(0);
+            let results = fetch_concurrently(concurrency, (0..ITEMS).rev(), |i| {
+                let (running, peak) = (&running, &peak);
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    // Finish in a different order than started
+                    for _ in 0..i {
+                        tokio::task::yield_now().await;
+                    }
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(i * 2)
+                }
+            })
+            .await
+            .unwrap();
+            (results, peak.into_inner())
+        };
+
+        let expected: Vec<usize> = (0..ITEMS).rev().map(|i| i * 2).collect();
+        assert_eq!(fetch(1).await, (expected.clone(), 1));
+        assert_eq!(fetch(3).await, (expected.clone(), 3));
+        assert_eq!(fetch(ITEMS).await, (expected, ITEMS));
+
+        let err = fetch_concurrently(4, 0..ITEMS, |i| async move {
+            if i == 5 {
+                Err(anyhow!("item {} failed", i))
+            } else {
+                Ok(i)
+            }
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "item 5 failed");
+    }
+
+    #[tokio::test]
+    async fn test_update_feature_config_retries_stale_write() {
+        let server = tiny_http::Server::http("localhost:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let server_thread = std::thread::spawn(move || {
+            // Another writer adds `b` between our first read and write
+            let responses = [
+                ("1", r#"[{"key":"a","value":"1"}]"#),
+                ("2", r#"[{"key":"a","value":"1"},{"key":"b","value":"2"}]"#),
+            ];
+            let mut written = None;
+            for (etag, body) in responses {
+                let req = server.recv().unwrap();
+                assert_eq!(req.method(), &tiny_http::Method::Get);
+                let etag_header = format!("ETag: {}", etag)
+                    .parse::<tiny_http::Header>()
+                    .unwrap();
+                req.respond(tiny_http::Response::from_string(body).with_header(etag_header))
+                    .unwrap();
+
+                let mut req = server.recv().unwrap();
+                assert_eq!(req.method(), &tiny_http::Method::Post);
+                let if_match = req
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("If-Match"))
+                    .map(|h| h.value.to_string());
+                assert_eq!(if_match.as_deref(), Some(etag));
+                if etag == "1" {
+                    req.respond(tiny_http::Response::empty(412)).unwrap();
+                } else {
+                    let mut body = String::new();
+                    req.as_reader().read_to_string(&mut body).unwrap();
+                    written = Some(body);
+                    req.respond(tiny_http::Response::empty(200)).unwrap();
+                }
+            }
+            written.unwrap()
+        });
+
+        let client = APIClient::new(
+            &Url::parse(&format!("http://localhost:{}/", port)).unwrap(),
+            "token",
+        )
+        .unwrap();
+        let project: api::Project = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "p",
+            "hash": "h",
+            "features": [],
+            "cloneToken": "",
+            "hasPushed": true,
+        }))
+        .unwrap();
+        let feature: api::Feature =
+            serde_json::from_value(serde_json::json!({"id": 2, "name": "f"})).unwrap();
+
+        let mut attempts = 0;
+        update_feature_config(&client, &project, &feature, |feature_config| {
+            attempts += 1;
+            set_feature_config(feature_config, "c", "3");
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(attempts, 2);
+
+        let written: Vec<api::FeatureConfig> =
+            serde_json::from_str(&server_thread.join().unwrap()).unwrap();
+        let keys: Vec<&str> = written.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+    }
 }